@@ -0,0 +1,533 @@
+use crate::{
+    create_blank_map, run, Config, PassabilityCheck, Region, Symmetry, Terrain, TerrainRepair,
+    TileVariants, Transform, VariantWeighting, TRANSPARENT_COLOR,
+};
+use rayon::prelude::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+// Blank map a job starts from, the fields match the `new` command options.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NewMap {
+    base: String,
+    #[serde(default = "default_land_terrain")]
+    terrain: Terrain,
+    #[serde(default = "default_underground_terrain")]
+    underground_terrain: Terrain,
+}
+
+fn default_land_terrain() -> Terrain {
+    Terrain::Water
+}
+
+fn default_underground_terrain() -> Terrain {
+    Terrain::Rock
+}
+
+// Rectangle of the cells to convert, the fields match the `--region` option.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RegionRectangle {
+    column: usize,
+    row: usize,
+    width: usize,
+    height: usize,
+}
+
+// One conversion of the manifest, the fields match the CLI options.
+// The job converts either an input map or a new map written to the output.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Job {
+    map: Option<String>,
+    new_map: Option<NewMap>,
+    output: Option<String>, // the input map is updated in place by default
+    preview: Option<String>,
+    repair_overlay: Option<String>,
+    land_image: Option<String>,
+    underground_image: Option<String>,
+    cave_mask: Option<String>,
+    land_keep_clear_mask: Option<String>,
+    underground_keep_clear_mask: Option<String>,
+    region: Option<RegionRectangle>,
+    region_mask: Option<String>,
+    #[serde(default)]
+    one_tile_water: bool,
+    #[serde(default)]
+    obstacles: bool,
+    #[serde(default)]
+    integration_mode: bool,
+    #[serde(default)]
+    transparent: bool,
+    problem_rules: Option<String>,
+    #[serde(default)]
+    terrain_repair: TerrainRepair,
+    #[serde(default)]
+    smooth_coastline: bool,
+    transform: Option<Transform>,
+    symmetry: Option<Symmetry>,
+    passability: Option<PassabilityCheck>,
+    #[serde(default)]
+    strict_obstacles: bool,
+    #[serde(default)]
+    tile_variants: HashMap<Terrain, VariantWeighting>,
+    variant_repeat_distance: Option<usize>,
+    seed: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    jobs: Vec<Job>,
+}
+
+struct BatchJob {
+    new_map: Option<NewMap>,
+    config: Config,
+}
+
+impl Job {
+    fn validate(&self) -> Result<(), String> {
+        match (&self.map, &self.new_map, &self.output) {
+            (Some(_), Some(_), _) => Err("map and new_map can't be set together".to_string()),
+            (None, None, _) => Err("map or new_map must be set".to_string()),
+            (None, Some(_), None) => Err("new_map needs an output".to_string()),
+            _ => Ok(()),
+        }?;
+        if self.region.is_some() && self.region_mask.is_some() {
+            return Err("region and region_mask can't be set together".to_string());
+        }
+        if self
+            .region
+            .as_ref()
+            .is_some_and(|region| region.width == 0 || region.height == 0)
+        {
+            return Err("the region size must be non-empty".to_string());
+        }
+        if self.symmetry.is_some() && (self.region.is_some() || self.region_mask.is_some()) {
+            return Err("symmetry can't be set with a region".to_string());
+        }
+        Ok(())
+    }
+
+    // The map and the images the job writes.
+    fn written_paths(&self) -> impl Iterator<Item = &String> {
+        self.output
+            .as_ref()
+            .or(self.map.as_ref())
+            .into_iter()
+            .chain(&self.preview)
+            .chain(&self.repair_overlay)
+    }
+
+    // Relative paths are relative to the manifest directory.
+    fn into_batch_job(self, base_dir: &Path) -> BatchJob {
+        let resolve = |path: String| base_dir.join(path).to_string_lossy().into_owned();
+        let mut tile_variants = TileVariants::default();
+        for (terrain, weighting) in self.tile_variants {
+            tile_variants.set_weighting(terrain, weighting);
+        }
+        if let Some(repeat_distance) = self.variant_repeat_distance {
+            tile_variants.set_repeat_distance(repeat_distance);
+        }
+
+        let new_map = self.new_map.map(|new_map| NewMap {
+            base: resolve(new_map.base),
+            ..new_map
+        });
+        let output_map_path = self.output.map(resolve);
+        // the new map is created at the output and converted there
+        let map_path = match self.map {
+            Some(map) => resolve(map),
+            None => output_map_path.clone().unwrap(),
+        };
+
+        let config = Config {
+            land_image_path: self.land_image.map(resolve),
+            underground_image_path: self.underground_image.map(resolve),
+            cave_mask_path: self.cave_mask.map(resolve),
+            land_keep_clear_mask_path: self.land_keep_clear_mask.map(resolve),
+            underground_keep_clear_mask_path: self.underground_keep_clear_mask.map(resolve),
+            map_path,
+            output_map_path,
+            preview_path: self.preview.map(resolve),
+            repair_overlay_path: self.repair_overlay.map(resolve),
+            obstacles: self.obstacles,
+            one_tile_water: self.one_tile_water,
+            integration_mode: self.integration_mode,
+            transparent_color: if self.transparent {
+                Some(TRANSPARENT_COLOR)
+            } else {
+                None
+            },
+            problem_rules_path: self.problem_rules.map(resolve),
+            terrain_repair: self.terrain_repair,
+            smooth_coastline: self.smooth_coastline,
+            transform: self.transform,
+            symmetry: self.symmetry,
+            region: match (self.region, self.region_mask) {
+                (Some(region), _) => Some(Region::Rectangle {
+                    column: region.column,
+                    row: region.row,
+                    width: region.width,
+                    height: region.height,
+                }),
+                (_, Some(region_mask)) => Some(Region::Mask(resolve(region_mask))),
+                _ => None,
+            },
+            passability_check: self.passability,
+            strict_obstacles: self.strict_obstacles,
+            tile_variants,
+            seed: self.seed,
+            history: false,
+        };
+        BatchJob { new_map, config }
+    }
+}
+
+fn parse_manifest(manifest: &str, is_json: bool) -> Result<Manifest, Box<dyn Error>> {
+    let manifest: Manifest = if is_json {
+        serde_json::from_str(manifest)?
+    } else {
+        toml::from_str(manifest)?
+    };
+    for (index, job) in manifest.jobs.iter().enumerate() {
+        job.validate()
+            .map_err(|e| format!("Job {} of the manifest: {}.", index + 1, e))?;
+    }
+    // parallel jobs writing the same file would race
+    let mut writing_jobs: HashMap<&Path, usize> = HashMap::new();
+    for (index, job) in manifest.jobs.iter().enumerate() {
+        for path in job.written_paths() {
+            match writing_jobs.insert(Path::new(path), index) {
+                Some(writing_job) if writing_job == index => {
+                    return Err(format!(
+                        "Job {} of the manifest writes {} twice.",
+                        index + 1,
+                        path
+                    )
+                    .into());
+                }
+                Some(writing_job) => {
+                    return Err(format!(
+                        "Jobs {} and {} of the manifest both write {}.",
+                        writing_job + 1,
+                        index + 1,
+                        path
+                    )
+                    .into());
+                }
+                None => {}
+            }
+        }
+    }
+    Ok(manifest)
+}
+
+fn run_job(BatchJob { new_map, config }: BatchJob) -> Result<(), String> {
+    if config.land_image_path.is_none()
+        && config.underground_image_path.is_none()
+        && config.cave_mask_path.is_none()
+    {
+        return Err("no land image, underground image or cave mask is set".to_string());
+    }
+    // the caves replace the whole underground, as the CLI options conflict
+    if config.underground_image_path.is_some() && config.cave_mask_path.is_some() {
+        return Err("the underground image and the cave mask can't be set together".to_string());
+    }
+    if let Some(new_map) = new_map {
+        create_blank_map(
+            &new_map.base,
+            &config.map_path,
+            new_map.terrain,
+            new_map.underground_terrain,
+            config.seed,
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    run(config).map_err(|e| e.to_string())
+}
+
+pub struct JobResult {
+    output_map_path: String,
+    result: Result<(), String>,
+}
+
+/// Results of the batch jobs in the manifest order.
+pub struct BatchSummary {
+    job_results: Vec<JobResult>,
+}
+
+impl BatchSummary {
+    pub fn failed_jobs_count(&self) -> usize {
+        self.job_results
+            .iter()
+            .filter(|job_result| job_result.result.is_err())
+            .count()
+    }
+}
+
+impl fmt::Display for BatchSummary {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for job_result in &self.job_results {
+            if let Err(e) = &job_result.result {
+                writeln!(fmt, "Failed {}: {}", job_result.output_map_path, e)?;
+            }
+        }
+        let failed_jobs_count = self.failed_jobs_count();
+        write!(
+            fmt,
+            "Batch done: {} succeeded, {} failed.",
+            self.job_results.len() - failed_jobs_count,
+            failed_jobs_count
+        )
+    }
+}
+
+/// Runs the jobs of a TOML or, with the `.json` extension, JSON manifest.
+/// A job converts its `map` or a `new_map` which is first created at its output
+/// as the `new` command does. Failed jobs don't stop the batch, they are listed
+/// in the summary.
+pub fn run_batch(manifest_path: &str, parallel: bool) -> Result<BatchSummary, Box<dyn Error>> {
+    let manifest_path = Path::new(manifest_path);
+    let is_json = manifest_path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+    let manifest = parse_manifest(&fs::read_to_string(manifest_path)?, is_json)?;
+    let base_dir = manifest_path.parent().unwrap_or_else(|| Path::new(""));
+
+    let batch_jobs: Vec<BatchJob> = manifest
+        .jobs
+        .into_iter()
+        .map(|job| job.into_batch_job(base_dir))
+        .collect();
+
+    let run_job_with_result = |batch_job: BatchJob| JobResult {
+        output_map_path: batch_job
+            .config
+            .output_map_path
+            .clone()
+            .unwrap_or_else(|| batch_job.config.map_path.clone()),
+        result: run_job(batch_job),
+    };
+
+    let job_results = if parallel {
+        batch_jobs
+            .into_par_iter()
+            .map(run_job_with_result)
+            .collect()
+    } else {
+        batch_jobs.into_iter().map(run_job_with_result).collect()
+    };
+
+    Ok(BatchSummary { job_results })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::h3m::TestMap;
+    use crate::H3m;
+
+    #[test]
+    fn parse_manifest_test() {
+        let toml_manifest = r#"
+            [[jobs]]
+            map = "blank.h3m"
+            output = "out/forest.h3m"
+            land_image = "forest.png"
+            obstacles = true
+            passability = "fix"
+            strict_obstacles = true
+            seed = 7
+
+            [[jobs]]
+            map = "/maps/islands.h3m"
+            underground_image = "caves.png"
+            transparent = true
+            region = { column = 2, row = 3, width = 20, height = 12 }
+        "#;
+        let json_manifest = r#"{"jobs": [
+            {"map": "blank.h3m", "output": "out/forest.h3m", "land_image": "forest.png",
+             "obstacles": true, "passability": "fix", "strict_obstacles": true, "seed": 7},
+            {"map": "/maps/islands.h3m", "underground_image": "caves.png", "transparent": true,
+             "region": {"column": 2, "row": 3, "width": 20, "height": 12}}
+        ]}"#;
+
+        for (manifest, is_json) in [(toml_manifest, false), (json_manifest, true)] {
+            let configs: Vec<Config> = parse_manifest(manifest, is_json)
+                .unwrap()
+                .jobs
+                .into_iter()
+                .map(|job| job.into_batch_job(Path::new("/batch")).config)
+                .collect();
+
+            assert_eq!(configs.len(), 2);
+
+            assert_eq!(configs[0].map_path, "/batch/blank.h3m");
+            assert_eq!(
+                configs[0].output_map_path.as_deref(),
+                Some("/batch/out/forest.h3m")
+            );
+            assert_eq!(
+                configs[0].land_image_path.as_deref(),
+                Some("/batch/forest.png")
+            );
+            assert!(configs[0].obstacles);
+            assert_eq!(configs[0].passability_check, Some(PassabilityCheck::Fix));
+            assert!(configs[0].strict_obstacles);
+            assert!(!configs[1].strict_obstacles);
+            assert_eq!(configs[0].seed, Some(7));
+
+            assert_eq!(configs[1].map_path, "/maps/islands.h3m");
+            assert_eq!(configs[1].output_map_path, None);
+            assert_eq!(configs[1].transparent_color, Some(TRANSPARENT_COLOR));
+            assert!(!configs[1].obstacles);
+            assert!(configs[0].region.is_none());
+            assert!(matches!(
+                configs[1].region,
+                Some(Region::Rectangle {
+                    column: 2,
+                    row: 3,
+                    width: 20,
+                    height: 12
+                })
+            ));
+        }
+    }
+
+    #[test]
+    fn invalid_manifest_test() {
+        for (jobs, error) in [
+            (
+                "[[jobs]]\nmap = \"a.h3m\"\n[[jobs]]\nmap = \"b.h3m\"\noutput = \"a.h3m\"",
+                "Jobs 1 and 2 of the manifest both write a.h3m.",
+            ),
+            (
+                "[[jobs]]\nmap = \"a.h3m\"\npreview = \"a.png\"\n\
+                 [[jobs]]\nmap = \"b.h3m\"\nrepair_overlay = \"a.png\"",
+                "Jobs 1 and 2 of the manifest both write a.png.",
+            ),
+            (
+                "[[jobs]]\nmap = \"a.h3m\"\npreview = \"a.h3m\"",
+                "Job 1 of the manifest writes a.h3m twice.",
+            ),
+            (
+                "[[jobs]]\nmap = \"a.h3m\"\nregion_mask = \"region.png\"\n\
+                 region = { column = 0, row = 0, width = 4, height = 4 }",
+                "Job 1 of the manifest: region and region_mask can't be set together.",
+            ),
+            (
+                "[[jobs]]\nmap = \"a.h3m\"\nregion = { column = 0, row = 0, width = 0, height = 4 }",
+                "Job 1 of the manifest: the region size must be non-empty.",
+            ),
+        ] {
+            let manifest = format!("{}\nland_image = \"land.png\"\n", jobs);
+            match parse_manifest(&manifest, false) {
+                Ok(_) => panic!("{}", jobs),
+                Err(e) => assert_eq!(e.to_string(), error),
+            }
+        }
+
+        let manifest = "[[jobs]]\nmap = \"a.h3m\"\noutput = \"a2.h3m\"\n\
+                        [[jobs]]\nmap = \"a.h3m\"\noutput = \"a3.h3m\"\n";
+        assert!(parse_manifest(manifest, false).is_ok());
+    }
+
+    #[test]
+    fn underground_image_with_cave_mask_test() {
+        let manifest = r#"
+            [[jobs]]
+            map = "blank.h3m"
+            underground_image = "caves.png"
+            cave_mask = "tunnels.png"
+        "#;
+        let job = parse_manifest(manifest, false).unwrap().jobs.remove(0);
+        let batch_job = job.into_batch_job(Path::new("/batch"));
+        assert_eq!(
+            batch_job.config.cave_mask_path.as_deref(),
+            Some("/batch/tunnels.png")
+        );
+
+        let error = run_job(batch_job).unwrap_err();
+        assert!(error.contains("cave mask"));
+    }
+
+    #[test]
+    fn new_map_manifest_test() {
+        let manifest = r#"
+            [[jobs]]
+            output = "out/new.h3m"
+            land_image = "land.png"
+            new_map = { base = "blank.h3m", terrain = "grass" }
+        "#;
+        let job = parse_manifest(manifest, false).unwrap().jobs.remove(0);
+        let batch_job = job.into_batch_job(Path::new("/batch"));
+        let new_map = batch_job.new_map.unwrap();
+        assert_eq!(new_map.base, "/batch/blank.h3m");
+        assert_eq!(new_map.terrain, Terrain::Grass);
+        assert_eq!(new_map.underground_terrain, Terrain::Rock);
+        assert_eq!(batch_job.config.map_path, "/batch/out/new.h3m");
+
+        let new_map = "new_map = { base = \"blank.h3m\" }";
+        for (job, is_valid) in [
+            (
+                format!("map = \"a.h3m\"\noutput = \"b.h3m\"\n{}", new_map),
+                false,
+            ),
+            (format!("output = \"b.h3m\"\n{}", new_map), true),
+            (new_map.to_string(), false),
+            ("output = \"b.h3m\"".to_string(), false),
+        ] {
+            let manifest = format!("[[jobs]]\nland_image = \"land.png\"\n{}\n", job);
+            assert_eq!(
+                parse_manifest(&manifest, false).is_ok(),
+                is_valid,
+                "{}",
+                job
+            );
+        }
+    }
+
+    #[test]
+    fn new_map_job_test() {
+        let dir = std::env::temp_dir().join("img2h3m_new_map_job_test");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("blank.h3m"),
+            TestMap::new(4, Terrain::Grass).compressed(),
+        )
+        .unwrap();
+        let sand_color = image::Rgb([0xDE, 0xCE, 0x8C]);
+        image::RgbImage::from_pixel(4, 4, sand_color)
+            .save(dir.join("land.png"))
+            .unwrap();
+        let manifest_path = dir.join("jobs.toml");
+        fs::write(
+            &manifest_path,
+            "[[jobs]]\noutput = \"new.h3m\"\nland_image = \"land.png\"\n\
+             new_map = { base = \"blank.h3m\" }\nseed = 1\n",
+        )
+        .unwrap();
+
+        let batch_summary = run_batch(manifest_path.to_str().unwrap(), false).unwrap();
+        assert_eq!(batch_summary.failed_jobs_count(), 0, "{}", batch_summary);
+        let h3m = H3m::load(fs::File::open(dir.join("new.h3m")).unwrap()).unwrap();
+        assert!(h3m
+            .surfaces(false)
+            .unwrap()
+            .iter()
+            .all(|surface| surface.terrain == Terrain::Sand));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unknown_manifest_field_test() {
+        assert!(parse_manifest("[[jobs]]\nmap = \"a.h3m\"\nobstacle = true\n", false).is_err());
+    }
+}
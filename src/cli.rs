@@ -1,75 +1,786 @@
-use clap::{App, Arg};
-use img2h3m::Config;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use img2h3m::{
+    Config, PassabilityCheck, Region, Symmetry, Terrain, TerrainRepair, TileVariants, Transform,
+    VariantWeighting, KEEP_CLEAR_COLOR, REGION_COLOR, STDIO_PATH, TRANSPARENT_COLOR, TUNNEL_COLOR,
+};
+use std::collections::HashMap;
+use std::env;
 use std::process;
+use strum::IntoEnumIterator;
 
-pub fn get_config() -> Config {
-    let transparent_color = [0, 0xFF, 0xFF];
+pub enum Command {
+    Convert(Config),
+    Watch(Config),
+    New {
+        base_map_path: String,
+        output_map_path: String,
+        land_terrain: Terrain,
+        underground_terrain: Terrain,
+        seed: Option<u64>,
+    },
+    Replace {
+        map_path: String,
+        output_map_path: Option<String>,
+        replacements: HashMap<Terrain, Terrain>,
+        one_tile_water: bool,
+        seed: Option<u64>,
+        history: bool,
+    },
+    Undo {
+        map_path: String,
+    },
+    Redo {
+        map_path: String,
+    },
+    Export {
+        map_path: String,
+        land_image_path: Option<String>,
+        underground_image_path: Option<String>,
+    },
+    Info {
+        map_path: String,
+        json: bool,
+    },
+    Palette {
+        obstacles: bool,
+        image_path: Option<String>,
+        transparent: bool,
+    },
+    Batch {
+        manifest_path: String,
+        parallel: bool,
+    },
+}
 
-    let matches = App::new(env!("CARGO_PKG_NAME"))
-        .version(env!("CARGO_PKG_VERSION"))
-        .about(env!("CARGO_PKG_DESCRIPTION"))
+fn color_hex(color: [u8; 3]) -> String {
+    format!("0x{:02X}{:02X}{:02X}", color[0], color[1], color[2])
+}
+
+const MAX_VARIANT_REPEAT_DISTANCE: usize = 8;
+
+const TERRAIN_NAMES: [&str; 12] = [
+    "dirt",
+    "sand",
+    "grass",
+    "snow",
+    "swamp",
+    "rough",
+    "subterranean",
+    "lava",
+    "highlands",
+    "wasteland",
+    "water",
+    "rock",
+];
+
+fn terrain_name(terrain: Terrain) -> String {
+    format!("{:?}", terrain).to_lowercase()
+}
+
+fn parse_terrain(name: &str) -> Terrain {
+    Terrain::iter()
+        .find(|&terrain| terrain_name(terrain) == name)
+        .unwrap()
+}
+
+fn parse_replacement(replacement: &str) -> Result<(Terrain, Terrain), String> {
+    let error = || {
+        format!(
+            "the replacement must look like 'snow=grass', terrains: {}",
+            TERRAIN_NAMES.join(", ")
+        )
+    };
+    let (from, to) = replacement.split_once('=').ok_or_else(error)?;
+    if !TERRAIN_NAMES.contains(&from) || !TERRAIN_NAMES.contains(&to) {
+        return Err(error());
+    }
+    Ok((parse_terrain(from), parse_terrain(to)))
+}
+
+fn parse_tile_variants(tile_variants: &str) -> Result<(Terrain, VariantWeighting), String> {
+    let error = || {
+        format!(
+            "the tile variants must look like 'grass=plain', weightings: plain, balanced, decorated, terrains: {}",
+            TERRAIN_NAMES.join(", ")
+        )
+    };
+    let (terrain, weighting) = tile_variants.split_once('=').ok_or_else(error)?;
+    if !TERRAIN_NAMES.contains(&terrain) {
+        return Err(error());
+    }
+    let weighting = match weighting {
+        "plain" => VariantWeighting::Plain,
+        "balanced" => VariantWeighting::Balanced,
+        "decorated" => VariantWeighting::Decorated,
+        _ => return Err(error()),
+    };
+    Ok((parse_terrain(terrain), weighting))
+}
+
+// clap can't read flags from the environment, so their variables are checked here:
+// any value but an empty one, '0' or 'false' sets the flag.
+fn is_flag_set(matches: &ArgMatches, name: &str, env_name: &str) -> bool {
+    matches.is_present(name)
+        || env::var(env_name)
+            .map(|value| !matches!(value.to_lowercase().as_str(), "" | "0" | "false"))
+            .unwrap_or(false)
+}
+
+fn parse_region(region: &str) -> Result<Region, String> {
+    let values: Vec<usize> = region
+        .split(',')
+        .map(|value| value.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| "the region must look like 'COLUMN,ROW,WIDTH,HEIGHT'".to_string())?;
+    match values[..] {
+        [column, row, width, height] if width > 0 && height > 0 => Ok(Region::Rectangle {
+            column,
+            row,
+            width,
+            height,
+        }),
+        _ => Err(
+            "the region must look like 'COLUMN,ROW,WIDTH,HEIGHT' with a non-empty size".to_string(),
+        ),
+    }
+}
+
+fn map_arg<'a>() -> Arg<'a, 'a> {
+    Arg::with_name("map")
+        .short("m")
+        .long("map")
+        .env("IMG2H3M_MAP")
+        .takes_value(true)
+        .required(true)
+}
+
+fn seed_arg<'a>() -> Arg<'a, 'a> {
+    Arg::with_name("seed")
+        .short("s")
+        .long("seed")
+        .env("IMG2H3M_SEED")
+        .help("Seed of the random generator: the same seed gives the same map")
+        .takes_value(true)
+        .validator(|seed| {
+            seed.parse::<u64>()
+                .map(|_| ())
+                .map_err(|_| "the seed must be an unsigned integer".to_string())
+        })
+}
+
+fn history_arg<'a>() -> Arg<'a, 'a> {
+    Arg::with_name("history").long("history").help(
+        "Record the run in the history file next to the map to undo it later, \
+               the map must be updated in place",
+    )
+}
+
+fn terrain_arg<'a>(name: &'a str, env: &'a str, default: &'a str, help: &'a str) -> Arg<'a, 'a> {
+    Arg::with_name(name)
+        .long(name)
+        .env(env)
+        .help(help)
+        .takes_value(true)
+        .possible_values(&TERRAIN_NAMES)
+        .default_value(default)
+}
+
+fn convert_subcommand<'a>(
+    keep_clear_help: &'a [String; 2],
+    transparent_help: &'a str,
+    region_mask_help: &'a str,
+    cave_mask_help: &'a str,
+) -> App<'a, 'a> {
+    SubCommand::with_name("convert")
+        .about("Update the terrain and the obstacles of a map from images")
         .arg(
             Arg::with_name("land image")
                 .short("l")
-                .help("Path to the input land image file")
+                .long("land-image")
+                .env("IMG2H3M_LAND_IMAGE")
+                .help("Path to the input land image file, '-' to read it from stdin")
                 .takes_value(true),
         )
         .arg(
             Arg::with_name("underground image")
                 .short("u")
-                .help("Path to the input underground image file")
+                .long("underground-image")
+                .env("IMG2H3M_UNDERGROUND_IMAGE")
+                .help("Path to the input underground image file, '-' to read it from stdin")
                 .takes_value(true),
         )
         .arg(
-            Arg::with_name("map")
-                .short("m")
-                .help("Path to the existing h3m file for update it's map")
+            Arg::with_name("cave mask")
+                .long("cave-mask")
+                .env("IMG2H3M_CAVE_MASK")
+                .help(cave_mask_help)
                 .takes_value(true)
-                .required(true),
+                .conflicts_with("underground image"),
+        )
+        .arg(
+            Arg::with_name("land keep clear mask")
+                .short("k")
+                .long("land-keep-clear")
+                .env("IMG2H3M_LAND_KEEP_CLEAR")
+                .help(&keep_clear_help[0])
+                .takes_value(true)
+                .requires("land image"),
+        )
+        .arg(
+            Arg::with_name("underground keep clear mask")
+                .short("K")
+                .long("underground-keep-clear")
+                .env("IMG2H3M_UNDERGROUND_KEEP_CLEAR")
+                .help(&keep_clear_help[1])
+                .takes_value(true)
+                .requires("underground image"),
+        )
+        .arg(map_arg().help(
+            "Path to the existing h3m file for update it's map, \
+             '-' to read the map from stdin and write the result to stdout",
+        ))
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .env("IMG2H3M_OUTPUT")
+                .help("Path to the output h3m file, the input map is updated by default")
+                .takes_value(true),
         )
         .arg(
             Arg::with_name("onetile water")
                 .short("w")
-                .help("Use one-tile water"),
+                .long("one-tile-water")
+                .help("Use one-tile water [env: IMG2H3M_ONE_TILE_WATER]"),
         )
         .arg(
             Arg::with_name("obstacles")
                 .short("o")
+                .long("obstacles")
                 .help("Create obstacles on the map \
-                       (attention, this option is only available for blank maps without any objects)"),
+                       (attention, this option is only available for blank maps without any objects, \
+                       or with a region for maps which objects are all obstacles) [env: IMG2H3M_OBSTACLES]"),
         )
         .arg(
             Arg::with_name("integration mode")
                 .short("i")
-                .help("Connect new tiles with existing ones on the map"),
+                .long("integration")
+                .help("Connect new tiles with existing ones on the map [env: IMG2H3M_INTEGRATION]"),
         )
         .arg(
             Arg::with_name("transparent")
                 .short("t")
-                .help(
-                    &format!("Transparent mode: pixels with color 0x{:02X}{:02X}{:02X} are not processed",
-                    transparent_color[0], transparent_color[1], transparent_color[2])),
+                .long("transparent")
+                .help(transparent_help),
         )
-        .get_matches();
+        .arg(
+            Arg::with_name("problem rules")
+                .long("problem-rules")
+                .env("IMG2H3M_PROBLEM_RULES")
+                .help("Path to the TOML or JSON file of the terrain patterns which no tiles can draw, \
+                       by default water and rock can't form 1-wide strips, checkerboards and corners")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("terrain repair")
+                .long("terrain-repair")
+                .env("IMG2H3M_TERRAIN_REPAIR")
+                .help("Repair of the cells which no tiles can draw, like 1-wide water strips: \
+                       'nearest' (default) takes the ground terrain of the nearest color, \
+                       'majority' the ground terrain of most neighbours, \
+                       'widen' widens the features to 2 cells, \
+                       'remove' replaces the features with the ground around them")
+                .takes_value(true)
+                .possible_values(&["nearest", "majority", "widen", "remove"]),
+        )
+        .arg(
+            Arg::with_name("smooth coastline")
+                .long("smooth-coastline")
+                .help("Smooth the coastlines of the image: bumps, notches and the corners of steps \
+                       move across them by one cell at most, keeping the water width rules \
+                       [env: IMG2H3M_SMOOTH_COASTLINE]"),
+        )
+        .arg(
+            Arg::with_name("transform")
+                .long("transform")
+                .env("IMG2H3M_TRANSFORM")
+                .help("Mirror or rotate the images before the conversion: \
+                       'mirror-horizontal' swaps the left and the right, 'mirror-vertical' the top \
+                       and the bottom, 'mirror-diagonal' the rows and the columns, \
+                       'rotate-90' rotates clockwise, 'rotate-180' upside down, \
+                       'rotate-270' counterclockwise")
+                .takes_value(true)
+                .possible_values(&[
+                    "mirror-horizontal",
+                    "mirror-vertical",
+                    "mirror-diagonal",
+                    "rotate-90",
+                    "rotate-180",
+                    "rotate-270",
+                ]),
+        )
+        .arg(
+            Arg::with_name("symmetry")
+                .long("symmetry")
+                .env("IMG2H3M_SYMMETRY")
+                .help("Make the map symmetric: the left half ('mirror-horizontal'), the top half \
+                       ('mirror-vertical', 'rotate-180'), the half above the diagonal \
+                       ('mirror-diagonal'), the top left quarter ('mirror-both') or the top quarter \
+                       between the diagonals ('rotate-90') of the images is copied to the rest \
+                       of the map, the obstacles are reproduced too")
+                .takes_value(true)
+                .possible_values(&[
+                    "mirror-horizontal",
+                    "mirror-vertical",
+                    "mirror-diagonal",
+                    "rotate-180",
+                    "mirror-both",
+                    "rotate-90",
+                ])
+                .conflicts_with_all(&["region", "region mask"]),
+        )
+        .arg(
+            Arg::with_name("region")
+                .long("region")
+                .env("IMG2H3M_REGION")
+                .value_name("COLUMN,ROW,WIDTH,HEIGHT")
+                .help("Convert only the cells of the rectangle: the terrain around it is kept \
+                       and obstacles are generated only inside it")
+                .takes_value(true)
+                .validator(|region| parse_region(&region).map(|_| ())),
+        )
+        .arg(
+            Arg::with_name("region mask")
+                .long("region-mask")
+                .env("IMG2H3M_REGION_MASK")
+                .help(region_mask_help)
+                .takes_value(true)
+                .conflicts_with("region"),
+        )
+        .arg(
+            Arg::with_name("passability")
+                .short("p")
+                .long("passability")
+                .env("IMG2H3M_PASSABILITY")
+                .help("Check that obstacles don't cut off passable areas of the image: \
+                       'warn' reports cut off areas, 'fix' also removes obstacles on optional obstacle cells to reconnect them")
+                .takes_value(true)
+                .possible_values(&["warn", "fix"]),
+        )
+        .arg(
+            Arg::with_name("strict obstacles")
+                .long("strict-obstacles")
+                .help("Fail if an obstacle cell which is not optional can't be covered, \
+                       by default such cells are reported and left free [env: IMG2H3M_STRICT_OBSTACLES]"),
+        )
+        .arg(
+            Arg::with_name("tile variants")
+                .long("tile-variants")
+                .help("Weighting of the tile variants inside a terrain like 'grass=plain': \
+                       'plain' uses plain tiles only, 'balanced' (default) mostly plain tiles, \
+                       'decorated' mostly decorated tiles; may be repeated")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .validator(|tile_variants| parse_tile_variants(&tile_variants).map(|_| ())),
+        )
+        .arg(
+            Arg::with_name("variant repeat distance")
+                .long("variant-repeat-distance")
+                .env("IMG2H3M_VARIANT_REPEAT_DISTANCE")
+                .help("Distance in cells within which the same tile variant isn't repeated \
+                       on the same terrain, 1 (default) means the neighbour cells")
+                .takes_value(true)
+                .validator(|distance| match distance.parse::<usize>() {
+                    Ok(1..=MAX_VARIANT_REPEAT_DISTANCE) => Ok(()),
+                    _ => Err(format!(
+                        "the distance must be from 1 to {}",
+                        MAX_VARIANT_REPEAT_DISTANCE
+                    )),
+                }),
+        )
+        .arg(seed_arg())
+        .arg(
+            Arg::with_name("preview")
+                .long("preview")
+                .env("IMG2H3M_PREVIEW")
+                .help("Path to the preview image drawn with the image colors after the conversion, \
+                       '-' to write a PNG to stdout")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("repair overlay")
+                .long("repair-overlay")
+                .env("IMG2H3M_REPAIR_OVERLAY")
+                .help("Path to the image of the pixels changed by the terrain repair, \
+                       the levels side by side as in the preview: red for the nearest color, \
+                       yellow for the majority, green for widened and magenta for removed features, \
+                       '-' to write a PNG to stdout")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("watch")
+                .long("watch")
+                .help("Convert the map again each time the images or masks change, \
+                       every conversion starts from the original map [env: IMG2H3M_WATCH]"),
+        )
+        .arg(history_arg())
+}
+
+fn convert_command(matches: &ArgMatches) -> Command {
+    let mut tile_variants = TileVariants::default();
+    for (terrain, weighting) in matches
+        .values_of("tile variants")
+        .into_iter()
+        .flatten()
+        .map(|tile_variants| parse_tile_variants(tile_variants).unwrap())
+    {
+        tile_variants.set_weighting(terrain, weighting);
+    }
+    if let Some(distance) = matches.value_of("variant repeat distance") {
+        tile_variants.set_repeat_distance(distance.parse().unwrap());
+    }
 
-    if !matches.is_present("land image") && !matches.is_present("underground image") {
+    if !matches.is_present("land image")
+        && !matches.is_present("underground image")
+        && !matches.is_present("cave mask")
+    {
         eprintln!(
-            "You must set the path to at least one surface image (land image or underground image)"
+            "You must set the path to at least one surface image \
+             (land image, underground image or cave mask)"
         );
         process::exit(1);
     }
 
-    Config {
+    let stdin_inputs_count = [
+        "map",
+        "land image",
+        "underground image",
+        "cave mask",
+        "land keep clear mask",
+        "underground keep clear mask",
+        "region mask",
+    ]
+    .iter()
+    .filter(|name| matches.value_of(name) == Some(STDIO_PATH))
+    .count();
+    if stdin_inputs_count > 1 {
+        eprintln!("Only one input can be read from stdin");
+        process::exit(1);
+    }
+
+    let obstacles = is_flag_set(matches, "obstacles", "IMG2H3M_OBSTACLES");
+    let strict_obstacles = is_flag_set(matches, "strict obstacles", "IMG2H3M_STRICT_OBSTACLES");
+    if (strict_obstacles || matches.is_present("passability")) && !obstacles {
+        eprintln!("The strict obstacles and passability options need the obstacles option");
+        process::exit(1);
+    }
+
+    let watch = is_flag_set(matches, "watch", "IMG2H3M_WATCH");
+    if watch && matches.is_present("history") {
+        eprintln!("Watch mode can't record the history");
+        process::exit(1);
+    }
+
+    if watch && stdin_inputs_count > 0 {
+        eprintln!("Watch mode can't read inputs from stdin");
+        process::exit(1);
+    }
+
+    let config = Config {
         land_image_path: matches.value_of("land image").map(|i| i.to_string()),
         underground_image_path: matches.value_of("underground image").map(|i| i.to_string()),
+        cave_mask_path: matches.value_of("cave mask").map(|i| i.to_string()),
+        land_keep_clear_mask_path: matches
+            .value_of("land keep clear mask")
+            .map(|i| i.to_string()),
+        underground_keep_clear_mask_path: matches
+            .value_of("underground keep clear mask")
+            .map(|i| i.to_string()),
         map_path: matches.value_of("map").unwrap().to_string(),
-        obstacles: matches.is_present("obstacles"),
-        one_tile_water: matches.is_present("onetile water"),
-        integration_mode: matches.is_present("integration mode"),
-        transparent_color: if matches.is_present("transparent") {
-            Some(transparent_color)
+        output_map_path: matches.value_of("output").map(|i| i.to_string()),
+        preview_path: matches.value_of("preview").map(|i| i.to_string()),
+        repair_overlay_path: matches.value_of("repair overlay").map(|i| i.to_string()),
+        obstacles,
+        one_tile_water: is_flag_set(matches, "onetile water", "IMG2H3M_ONE_TILE_WATER"),
+        integration_mode: is_flag_set(matches, "integration mode", "IMG2H3M_INTEGRATION"),
+        transparent_color: if is_flag_set(matches, "transparent", "IMG2H3M_TRANSPARENT") {
+            Some(TRANSPARENT_COLOR)
         } else {
             None
         },
+        problem_rules_path: matches.value_of("problem rules").map(|i| i.to_string()),
+        smooth_coastline: is_flag_set(matches, "smooth coastline", "IMG2H3M_SMOOTH_COASTLINE"),
+        terrain_repair: match matches.value_of("terrain repair") {
+            Some("majority") => TerrainRepair::Majority,
+            Some("widen") => TerrainRepair::Widen,
+            Some("remove") => TerrainRepair::Remove,
+            _ => TerrainRepair::Nearest,
+        },
+        transform: match matches.value_of("transform") {
+            Some("mirror-horizontal") => Some(Transform::MirrorHorizontal),
+            Some("mirror-vertical") => Some(Transform::MirrorVertical),
+            Some("mirror-diagonal") => Some(Transform::MirrorDiagonal),
+            Some("rotate-90") => Some(Transform::Rotate90),
+            Some("rotate-180") => Some(Transform::Rotate180),
+            Some("rotate-270") => Some(Transform::Rotate270),
+            _ => None,
+        },
+        symmetry: match matches.value_of("symmetry") {
+            Some("mirror-horizontal") => Some(Symmetry::MirrorHorizontal),
+            Some("mirror-vertical") => Some(Symmetry::MirrorVertical),
+            Some("mirror-diagonal") => Some(Symmetry::MirrorDiagonal),
+            Some("rotate-180") => Some(Symmetry::Rotate180),
+            Some("mirror-both") => Some(Symmetry::MirrorBoth),
+            Some("rotate-90") => Some(Symmetry::Rotate90),
+            _ => None,
+        },
+        region: match (matches.value_of("region"), matches.value_of("region mask")) {
+            (Some(region), _) => Some(parse_region(region).unwrap()),
+            (_, Some(mask_path)) => Some(Region::Mask(mask_path.to_string())),
+            _ => None,
+        },
+        strict_obstacles,
+        passability_check: match matches.value_of("passability") {
+            Some("warn") => Some(PassabilityCheck::Warn),
+            Some("fix") => Some(PassabilityCheck::Fix),
+            _ => None,
+        },
+        tile_variants,
+        seed: matches.value_of("seed").map(|seed| seed.parse().unwrap()),
+        history: matches.is_present("history"),
+    };
+
+    if watch {
+        Command::Watch(config)
+    } else {
+        Command::Convert(config)
+    }
+}
+
+pub fn get_command() -> Command {
+    let keep_clear_help = ["land", "underground"].map(|level| {
+        format!(
+            "Path to the {} keep clear mask image: no obstacles are placed on cells with color {}",
+            level,
+            color_hex(KEEP_CLEAR_COLOR)
+        )
+    });
+    let transparent_help = format!(
+        "Transparent mode: pixels with color {} are not processed",
+        color_hex(TRANSPARENT_COLOR)
+    );
+    let convert_transparent_help = format!("{} [env: IMG2H3M_TRANSPARENT]", transparent_help);
+    let region_mask_help = format!(
+        "Path to the region mask image: only cells with color {} are converted, \
+         the terrain around them is kept and obstacles are generated only inside them",
+        color_hex(REGION_COLOR)
+    );
+    let cave_mask_help = format!(
+        "Path to the cave mask image: the underground becomes rock with caves \
+         around the tunnels of cells with color {}, widened and smoothed randomly",
+        color_hex(TUNNEL_COLOR)
+    );
+
+    let matches = App::new(env!("CARGO_PKG_NAME"))
+        .version(env!("CARGO_PKG_VERSION"))
+        .about(env!("CARGO_PKG_DESCRIPTION"))
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .setting(AppSettings::VersionlessSubcommands)
+        .subcommand(convert_subcommand(
+            &keep_clear_help,
+            &convert_transparent_help,
+            &region_mask_help,
+            &cave_mask_help,
+        ))
+        .subcommand(
+            SubCommand::with_name("new")
+                .about("Create a blank map: a copy of a base map filled with one terrain")
+                .arg(
+                    Arg::with_name("base")
+                        .long("base")
+                        .env("IMG2H3M_BASE_MAP")
+                        .help("Path to the base h3m file setting the map size and levels")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .env("IMG2H3M_OUTPUT")
+                        .help("Path to the output h3m file, '-' to write it to stdout")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(terrain_arg(
+                    "terrain",
+                    "IMG2H3M_TERRAIN",
+                    "water",
+                    "Terrain of the land",
+                ))
+                .arg(terrain_arg(
+                    "underground-terrain",
+                    "IMG2H3M_UNDERGROUND_TERRAIN",
+                    "rock",
+                    "Terrain of the underground",
+                ))
+                .arg(seed_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("replace")
+                .about("Replace terrains on every level of a map, fixing up the transitions")
+                .arg(map_arg().help("Path to the h3m file, '-' to read the map from stdin and write the result to stdout"))
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .env("IMG2H3M_OUTPUT")
+                        .help("Path to the output h3m file, the input map is updated by default")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("replacement")
+                        .short("r")
+                        .long("replace")
+                        .help("Terrain replacement like 'snow=grass', may be repeated")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .required(true)
+                        .validator(|replacement| parse_replacement(&replacement).map(|_| ())),
+                )
+                .arg(
+                    Arg::with_name("onetile water")
+                        .short("w")
+                        .long("one-tile-water")
+                        .help("Use one-tile water [env: IMG2H3M_ONE_TILE_WATER]"),
+                )
+                .arg(seed_arg())
+                .arg(history_arg()),
+        )
+        .subcommand(
+            SubCommand::with_name("undo")
+                .about("Revert the last run recorded in the history of a map")
+                .arg(map_arg().help("Path to the h3m file")),
+        )
+        .subcommand(
+            SubCommand::with_name("redo")
+                .about("Apply the last undone run of a map again")
+                .arg(map_arg().help("Path to the h3m file")),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Draw the map levels to images which convert reads back")
+                .arg(map_arg().help("Path to the h3m file, '-' to read it from stdin"))
+                .arg(
+                    Arg::with_name("land image")
+                        .long("land-image")
+                        .env("IMG2H3M_LAND_IMAGE")
+                        .help("Path to the output land image file, '-' to write a PNG to stdout")
+                        .takes_value(true)
+                        .required_unless("underground image"),
+                )
+                .arg(
+                    Arg::with_name("underground image")
+                        .long("underground-image")
+                        .env("IMG2H3M_UNDERGROUND_IMAGE")
+                        .help("Path to the output underground image file, '-' to write a PNG to stdout")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("info")
+                .about("Print the version, size, object counts, section offsets and terrain of a map")
+                .arg(map_arg().help("Path to the h3m file, '-' to read it from stdin"))
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print the information as JSON"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("palette")
+                .about("Print the palette colors or check that an image only uses them")
+                .arg(
+                    Arg::with_name("obstacles")
+                        .long("obstacles")
+                        .help("Include the obstacle colors"),
+                )
+                .arg(
+                    Arg::with_name("validate")
+                        .long("validate")
+                        .env("IMG2H3M_VALIDATE_IMAGE")
+                        .help("Path to the image to check, '-' to read it from stdin")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("transparent")
+                        .long("transparent")
+                        .help(&transparent_help)
+                        .requires("validate"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("batch")
+                .about("Run the conversion jobs listed in a TOML or JSON manifest")
+                .arg(
+                    Arg::with_name("manifest")
+                        .help("Path to the manifest file")
+                        .env("IMG2H3M_MANIFEST")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("parallel")
+                        .short("P")
+                        .long("parallel")
+                        .help("Run the jobs in parallel"),
+                ),
+        )
+        .get_matches();
+
+    match matches.subcommand() {
+        ("convert", Some(matches)) => convert_command(matches),
+        ("new", Some(matches)) => Command::New {
+            base_map_path: matches.value_of("base").unwrap().to_string(),
+            output_map_path: matches.value_of("output").unwrap().to_string(),
+            land_terrain: parse_terrain(matches.value_of("terrain").unwrap()),
+            underground_terrain: parse_terrain(matches.value_of("underground-terrain").unwrap()),
+            seed: matches.value_of("seed").map(|seed| seed.parse().unwrap()),
+        },
+        ("replace", Some(matches)) => Command::Replace {
+            map_path: matches.value_of("map").unwrap().to_string(),
+            output_map_path: matches.value_of("output").map(|i| i.to_string()),
+            replacements: matches
+                .values_of("replacement")
+                .unwrap()
+                .map(|replacement| parse_replacement(replacement).unwrap())
+                .collect(),
+            one_tile_water: is_flag_set(matches, "onetile water", "IMG2H3M_ONE_TILE_WATER"),
+            seed: matches.value_of("seed").map(|seed| seed.parse().unwrap()),
+            history: matches.is_present("history"),
+        },
+        ("undo", Some(matches)) => Command::Undo {
+            map_path: matches.value_of("map").unwrap().to_string(),
+        },
+        ("redo", Some(matches)) => Command::Redo {
+            map_path: matches.value_of("map").unwrap().to_string(),
+        },
+        ("export", Some(matches)) => Command::Export {
+            map_path: matches.value_of("map").unwrap().to_string(),
+            land_image_path: matches.value_of("land image").map(|i| i.to_string()),
+            underground_image_path: matches.value_of("underground image").map(|i| i.to_string()),
+        },
+        ("info", Some(matches)) => Command::Info {
+            map_path: matches.value_of("map").unwrap().to_string(),
+            json: matches.is_present("json"),
+        },
+        ("palette", Some(matches)) => Command::Palette {
+            obstacles: matches.is_present("obstacles"),
+            image_path: matches.value_of("validate").map(|i| i.to_string()),
+            transparent: matches.is_present("transparent"),
+        },
+        ("batch", Some(matches)) => Command::Batch {
+            manifest_path: matches.value_of("manifest").unwrap().to_string(),
+            parallel: matches.is_present("parallel"),
+        },
+        _ => unreachable!(),
     }
 }
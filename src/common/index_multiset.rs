@@ -1,17 +1,19 @@
+use super::rng::SeededRng;
 use hashbag::HashBag;
 use num::Unsigned;
-use rand::{rngs::ThreadRng, Rng};
-use std::hash::Hash;
+use rand::Rng;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{BuildHasherDefault, Hash};
 
 #[derive(Clone)]
 pub struct IndexMultiset<T: Clone + Copy + Unsigned + Eq + Hash> {
-    inner: HashBag<T>,
+    inner: HashBag<T, BuildHasherDefault<DefaultHasher>>, // fixed hasher keeps iteration order reproducible
 }
 
 impl<T: Clone + Copy + Unsigned + Eq + Hash> IndexMultiset<T> {
     pub fn new() -> Self {
         IndexMultiset {
-            inner: HashBag::new(),
+            inner: HashBag::with_hasher(BuildHasherDefault::default()),
         }
     }
 
@@ -19,13 +21,17 @@ impl<T: Clone + Copy + Unsigned + Eq + Hash> IndexMultiset<T> {
         self.inner.is_empty()
     }
 
+    pub fn contains(&self, index: T) -> bool {
+        self.inner.contains(&index) > 0
+    }
+
     pub fn add_index(&mut self, index: T, frequency: usize) {
         if frequency > 0 {
             self.inner.insert_many(index, frequency);
         }
     }
 
-    pub fn random_index(&self, rng: &mut ThreadRng) -> Option<T> {
+    pub fn random_index(&self, rng: &mut SeededRng) -> Option<T> {
         if self.inner.is_empty() {
             return None;
         }
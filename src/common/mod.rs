@@ -1,4 +1,5 @@
 pub mod index_multiset;
 pub mod position;
+pub mod rng;
 
 pub type RgbColor = [u8; 3];
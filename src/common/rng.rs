@@ -0,0 +1,12 @@
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+pub type SeededRng = ChaCha8Rng;
+
+// Independent random stream of the seed, so that parts of the map
+// may be generated in any order with the same result.
+pub fn stream_rng(seed: u64, stream: u64) -> SeededRng {
+    let mut rng = SeededRng::seed_from_u64(seed);
+    rng.set_stream(stream);
+    rng
+}
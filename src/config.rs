@@ -1,11 +1,40 @@
 use crate::common::RgbColor;
+use crate::{PassabilityCheck, Symmetry, TerrainRepair, TileVariants, Transform};
+
+/// Cells of a level to convert, the rest of the map is left as it is.
+pub enum Region {
+    Rectangle {
+        column: usize,
+        row: usize,
+        width: usize,
+        height: usize,
+    },
+    Mask(String), // path to an image, the region cells are of `REGION_COLOR`
+}
 
 pub struct Config {
     pub land_image_path: Option<String>,
     pub underground_image_path: Option<String>,
+    pub cave_mask_path: Option<String>, // tunnels of the underground caves, see `cave_image`
+    pub land_keep_clear_mask_path: Option<String>,
+    pub underground_keep_clear_mask_path: Option<String>,
     pub map_path: String,
+    pub output_map_path: Option<String>,
+    pub preview_path: Option<String>,
+    pub repair_overlay_path: Option<String>, // image of the pixels changed by the terrain repair
     pub obstacles: bool,
     pub one_tile_water: bool,
     pub integration_mode: bool,
     pub transparent_color: Option<RgbColor>,
+    pub problem_rules_path: Option<String>, // the default problem rules without it
+    pub terrain_repair: TerrainRepair,
+    pub smooth_coastline: bool,
+    pub transform: Option<Transform>, // of the image surfaces
+    pub symmetry: Option<Symmetry>,
+    pub region: Option<Region>,
+    pub passability_check: Option<PassabilityCheck>,
+    pub strict_obstacles: bool, // fail on the mandatory obstacle cells which can't be covered
+    pub tile_variants: TileVariants,
+    pub seed: Option<u64>,
+    pub history: bool, // record the run in the history file of the map
 }
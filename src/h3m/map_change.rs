@@ -0,0 +1,185 @@
+use crate::h3m::result::*;
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+use libflate::deflate::{Decoder, Encoder};
+use std::io::{self, Read, Write};
+
+// Bytes of the raw map replaced at the offset. The last patch of a change
+// may reach the end of the map, then its before and after sizes may differ.
+struct Patch {
+    offset: usize,
+    to_end: bool,
+    before: Vec<u8>,
+    after: Vec<u8>,
+}
+
+fn write_bytes<W: Write>(bytes: &[u8], output: &mut W) -> H3mResult<()> {
+    output.write_u32::<LE>(u32::try_from(bytes.len())?)?;
+    output.write_all(bytes)?;
+    Ok(())
+}
+
+fn read_bytes<R: Read>(input: &mut R) -> H3mResult<Vec<u8>> {
+    let size = input.read_u32::<LE>()?;
+    // the size isn't trusted for the allocation, the data may be shorter
+    let mut bytes = Vec::new();
+    input.take(u64::from(size)).read_to_end(&mut bytes)?;
+    if bytes.len() != usize::try_from(size)? {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok(bytes)
+}
+
+impl Patch {
+    // Bytes expected in the map and their replacement.
+    fn sides(&self, undo: bool) -> (&[u8], &[u8]) {
+        if undo {
+            (&self.after, &self.before)
+        } else {
+            (&self.before, &self.after)
+        }
+    }
+
+    fn range_end(&self, raw_map_len: usize, bytes: &[u8]) -> usize {
+        if self.to_end {
+            raw_map_len
+        } else {
+            self.offset + bytes.len()
+        }
+    }
+}
+
+/// State of a map to compare a later state with.
+pub struct MapSnapshot {
+    pub(super) raw_map: Vec<u8>,
+}
+
+/// Difference of two states of a map: the changed cells of each level
+/// and the objects section, enough to go from one state to the other.
+pub struct MapChange {
+    patches: Vec<Patch>,
+}
+
+impl MapChange {
+    // Ranges are given as (offset, size) in both maps, `tail_offset` starts
+    // the part of the maps compared up to their ends.
+    pub(super) fn new(
+        before: &[u8],
+        after: &[u8],
+        ranges: &[(usize, usize)],
+        tail_offset: usize,
+    ) -> MapChange {
+        let mut patches = Vec::new();
+        for &(offset, size) in ranges {
+            let range = offset..offset + size;
+            let (before_bytes, after_bytes) = (&before[range.clone()], &after[range]);
+            let first = before_bytes
+                .iter()
+                .zip(after_bytes)
+                .position(|(b, a)| b != a);
+            let last = before_bytes
+                .iter()
+                .zip(after_bytes)
+                .rposition(|(b, a)| b != a);
+            if let (Some(first), Some(last)) = (first, last) {
+                patches.push(Patch {
+                    offset: offset + first,
+                    to_end: false,
+                    before: before_bytes[first..=last].to_vec(),
+                    after: after_bytes[first..=last].to_vec(),
+                });
+            }
+        }
+
+        if before[tail_offset..] != after[tail_offset..] {
+            patches.push(Patch {
+                offset: tail_offset,
+                to_end: true,
+                before: before[tail_offset..].to_vec(),
+                after: after[tail_offset..].to_vec(),
+            });
+        }
+
+        MapChange { patches }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patches.is_empty()
+    }
+
+    // Goes back to the state before the change with `undo`, forward otherwise.
+    // Fails if the map isn't in the state the change starts from.
+    pub(super) fn apply(&self, raw_map: &mut Vec<u8>, undo: bool) -> H3mResult<()> {
+        for patch in &self.patches {
+            let (expected, _) = patch.sides(undo);
+            let end = patch.range_end(raw_map.len(), expected);
+            if raw_map.get(patch.offset..end) != Some(expected) {
+                return Err(H3mError::Parameter(ParameterError::new(
+                    "The map was changed since the recorded run.",
+                )));
+            }
+        }
+
+        for patch in &self.patches {
+            let (expected, replacement) = patch.sides(undo);
+            let end = patch.range_end(raw_map.len(), expected);
+            raw_map.splice(patch.offset..end, replacement.iter().copied());
+        }
+        Ok(())
+    }
+
+    /// Writes the change deflate compressed.
+    pub fn write<W: Write>(&self, output: W) -> H3mResult<()> {
+        let mut encoder = Encoder::new(output);
+        encoder.write_u32::<LE>(u32::try_from(self.patches.len())?)?;
+        for patch in &self.patches {
+            encoder.write_u32::<LE>(u32::try_from(patch.offset)?)?;
+            encoder.write_u8(patch.to_end as u8)?;
+            write_bytes(&patch.before, &mut encoder)?;
+            write_bytes(&patch.after, &mut encoder)?;
+        }
+        encoder.finish().into_result()?;
+        Ok(())
+    }
+
+    /// Reads a change written by [`MapChange::write`].
+    pub fn read<R: Read>(input: R) -> H3mResult<MapChange> {
+        let mut decoder = Decoder::new(input);
+        let patches_count = decoder.read_u32::<LE>()?;
+        let mut patches = Vec::new();
+        for _ in 0..patches_count {
+            patches.push(Patch {
+                offset: usize::try_from(decoder.read_u32::<LE>()?)?,
+                to_end: decoder.read_u8()? != 0,
+                before: read_bytes(&mut decoder)?,
+                after: read_bytes(&mut decoder)?,
+            });
+        }
+        Ok(MapChange { patches })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_change_test() {
+        let before = [1, 2, 3, 4, 5, 6, 7, 8];
+        let after = [1, 9, 3, 9, 5, 6, 7, 8, 0, 0];
+        let change = MapChange::new(&before, &after[..8], &[(0, 4)], 6);
+        assert_eq!(change.patches.len(), 1);
+        assert_eq!(change.patches[0].before, [2, 3, 4]);
+
+        let change = MapChange::new(&before, &after, &[(0, 4)], 6);
+        let mut written = Vec::new();
+        change.write(&mut written).unwrap();
+        let change = MapChange::read(&written[..]).unwrap();
+
+        let mut raw_map = before.to_vec();
+        change.apply(&mut raw_map, false).unwrap();
+        assert_eq!(raw_map, after);
+        assert!(change.apply(&mut raw_map, false).is_err());
+        change.apply(&mut raw_map, true).unwrap();
+        assert_eq!(raw_map, before);
+    }
+}
@@ -0,0 +1,212 @@
+use super::parser::{self, H3mHeaderInfo, OffsetsSource, Version, MAP_CELL_SIZE};
+use super::result::*;
+use super::Terrain;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::{self, Cursor};
+use strum::IntoEnumIterator;
+
+/// Offsets of the map sections in the decompressed map data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct MapOffsets {
+    pub land: usize,
+    pub underground: Option<usize>,
+    pub object_templates: usize,
+    pub source: OffsetsSource,
+}
+
+/// Number of the level cells with a terrain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct TerrainCells {
+    pub terrain: Terrain,
+    pub cells_count: usize,
+}
+
+/// Terrain distribution of a level, terrains without cells are skipped.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LevelInfo {
+    pub underground: bool,
+    pub terrain_cells: Vec<TerrainCells>,
+}
+
+/// Summary of a map file, also for maps `H3m::load` rejects.
+///
+/// The fields are filled as far as the map could be parsed: on a parsing
+/// failure the rest stays `None` or empty and `error` tells what went wrong.
+#[derive(Debug, Default, Serialize)]
+pub struct MapInfo {
+    pub version: Option<String>,
+    pub hota_subversion: Option<u32>,
+    pub map_size: Option<usize>,
+    pub has_underground: Option<bool>,
+    pub offsets: Option<MapOffsets>,
+    pub structure_error: Option<String>, // why the offsets were searched by signature
+    pub object_templates_count: Option<usize>,
+    pub objects_count: Option<usize>,
+    pub levels: Vec<LevelInfo>,
+    pub error: Option<String>,
+}
+
+fn level_info(
+    raw_map: &[u8],
+    header_info: &H3mHeaderInfo,
+    offset: usize,
+    underground: bool,
+) -> H3mResult<LevelInfo> {
+    let map_length = header_info.map_size * header_info.map_size;
+    let mut counts = HashMap::new();
+    for cell in raw_map[offset..]
+        .chunks_exact(MAP_CELL_SIZE)
+        .take(map_length)
+    {
+        *counts.entry(Terrain::from_code(cell[0])?).or_insert(0) += 1;
+    }
+
+    Ok(LevelInfo {
+        underground,
+        terrain_cells: Terrain::iter()
+            .filter_map(|terrain| {
+                Some(TerrainCells {
+                    terrain,
+                    cells_count: *counts.get(&terrain)?,
+                })
+            })
+            .collect(),
+    })
+}
+
+impl MapInfo {
+    /// Reads the summary of a gzip compressed map.
+    ///
+    /// Only fails if the data can't be decompressed, parsing errors go to `error`.
+    pub fn read<R: io::Read>(input: R) -> H3mResult<MapInfo> {
+        let raw_map = super::decompress(input)?;
+        let mut map_info = MapInfo::default();
+        if let Err(error) = map_info.fill(&raw_map) {
+            map_info.error = Some(error.to_string());
+        }
+        Ok(map_info)
+    }
+
+    fn fill(&mut self, raw_map: &[u8]) -> H3mResult<()> {
+        let mut input = Cursor::new(raw_map);
+        let version = parser::read_version(&mut input)?;
+        self.version = Some(format!("{:?}", version));
+        if version != Version::HotA {
+            return Err(H3mError::Parameter(ParameterError::new(
+                "Only HotA maps are supported.",
+            )));
+        }
+
+        input.set_position(0);
+        let header_info = parser::read_header(&mut input)?;
+        self.hota_subversion = Some(header_info.hota_subversion);
+        self.map_size = Some(header_info.map_size);
+        self.has_underground = Some(header_info.has_underground);
+
+        let sections = parser::locate_sections(raw_map, &header_info)?;
+        self.offsets = Some(MapOffsets {
+            land: sections.land_offset,
+            underground: sections.underground_offset,
+            object_templates: sections.objects_templates_offset,
+            source: sections.source,
+        });
+        self.structure_error = sections.structure_error;
+
+        let counts = parser::read_counts(raw_map, sections.objects_templates_offset)?;
+        self.object_templates_count = Some(counts.templates_count);
+        self.objects_count = Some(counts.objects_count);
+
+        self.levels.push(level_info(
+            raw_map,
+            &header_info,
+            sections.land_offset,
+            false,
+        )?);
+        if let Some(underground_offset) = sections.underground_offset {
+            self.levels
+                .push(level_info(raw_map, &header_info, underground_offset, true)?);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::h3m::parser::test_map::TestMap;
+    use libflate::gzip::Encoder;
+    use std::io::Write;
+
+    #[test]
+    fn read_map_info_test() {
+        let mut test_map = TestMap::new(2, Terrain::Grass);
+        test_map.land[1] = Terrain::Water;
+        test_map.underground = Some(vec![Terrain::Rock; 4]);
+        let raw_map = test_map.raw_map();
+        let land_offset = raw_map.len() - 124 - 4 - (4 + 2 * 58) - 2 * 4 * MAP_CELL_SIZE;
+
+        let map_info = MapInfo::read(&test_map.compressed()[..]).unwrap();
+
+        assert_eq!(map_info.error, None);
+        assert_eq!(map_info.structure_error, None);
+        assert_eq!(map_info.version.as_deref(), Some("HotA"));
+        assert_eq!(map_info.hota_subversion, Some(3));
+        assert_eq!(map_info.map_size, Some(2));
+        assert_eq!(map_info.has_underground, Some(true));
+        assert_eq!(map_info.object_templates_count, Some(2));
+        assert_eq!(map_info.objects_count, Some(0));
+        assert_eq!(
+            map_info.offsets,
+            Some(MapOffsets {
+                land: land_offset,
+                underground: Some(land_offset + 4 * MAP_CELL_SIZE),
+                object_templates: land_offset + 8 * MAP_CELL_SIZE,
+                source: OffsetsSource::Structure,
+            })
+        );
+        assert_eq!(
+            map_info.levels,
+            [
+                LevelInfo {
+                    underground: false,
+                    terrain_cells: vec![
+                        TerrainCells {
+                            terrain: Terrain::Grass,
+                            cells_count: 3,
+                        },
+                        TerrainCells {
+                            terrain: Terrain::Water,
+                            cells_count: 1,
+                        },
+                    ],
+                },
+                LevelInfo {
+                    underground: true,
+                    terrain_cells: vec![TerrainCells {
+                        terrain: Terrain::Rock,
+                        cells_count: 4,
+                    }],
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn map_info_of_broken_map_test() {
+        let mut raw_map = TestMap::new(2, Terrain::Grass).raw_map();
+        raw_map.truncate(raw_map.len() - 200); // cut off the object templates
+        let mut encoder = Encoder::new(Vec::new()).unwrap();
+        encoder.write_all(&raw_map).unwrap();
+        let map = encoder.finish().into_result().unwrap();
+
+        let map_info = MapInfo::read(&map[..]).unwrap();
+        assert_eq!(map_info.map_size, Some(2));
+        assert_eq!(map_info.offsets, None);
+        assert!(map_info
+            .error
+            .unwrap()
+            .contains("Templates signature not found"));
+    }
+}
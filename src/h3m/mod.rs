@@ -1,25 +1,56 @@
+use crate::common::position::Position;
 use libflate::gzip::{Decoder, Encoder};
+pub use map_change::{MapChange, MapSnapshot};
+pub use map_info::{LevelInfo, MapInfo, MapOffsets, TerrainCells};
+#[cfg(test)]
+pub(crate) use obstacle_generator::has_obstacle_templates;
 use obstacle_generator::ObstacleGenerator;
+pub use obstacle_generator::{CutOffArea, ObstacleCoverage, ObstacleReport, PassabilityCheck};
+#[cfg(test)]
+pub(crate) use parser::test_map::TestMap;
+pub use parser::OffsetsSource;
 use parser::{H3mInfo, MAP_CELL_SIZE};
 use result::*;
+use std::collections::HashMap;
 use std::io::{self, Read, Write};
-pub use surface::{Surface, Terrain};
-use terrain_map::{MapCell, TerrainMap};
+pub use surface::{Obstacle, ObstacleCategory, Surface, Terrain};
+pub use symmetry::{transform_cells, Symmetry, Transform};
+use terrain_map::{ExistingTile, MapCell, TerrainMap};
+pub use terrain_map::{TileVariants, VariantWeighting};
 
+mod map_change;
+mod map_info;
 mod obstacle_generator;
 mod parser;
 pub mod result;
 mod surface;
+mod symmetry;
 mod terrain_map;
 
+/// Side of the biggest (giant) map.
 pub const MAX_MAP_SIZE: usize = 252;
 
+/// HotA map loaded into memory.
+///
+/// The map keeps everything but the terrain and the obstacles untouched:
+/// the terrain cells are rewritten in place and the generated obstacles
+/// replace the object templates and objects sections on save.
 pub struct H3m {
     info: H3mInfo,
     raw_map: Vec<u8>,
     obstacle_generator: Option<ObstacleGenerator>,
+    passability_check: Option<PassabilityCheck>,
+    strict_obstacles: bool, // uncovered mandatory obstacle cells fail the generation
+    symmetry: Option<Symmetry>,
+    seed: u64,
+    tile_variants: TileVariants,
+    unfitting_tile_cells: [Vec<(usize, usize)>; 2], // of the land and the underground
+    objects_end_offset: Option<usize>,              // set when the map objects are kept
 }
 
+const HORIZONTAL_MIRRORING_BIT: u8 = 0b0000_0001;
+const VERTICAL_MIRRORING_BIT: u8 = 0b0000_0010;
+
 fn set_map_cell(map_cell: &MapCell, data: &mut [u8]) {
     data[0] = map_cell.surface().terrain.code();
     data[1] = map_cell.tile().code();
@@ -32,9 +63,6 @@ fn set_map_cell(map_cell: &MapCell, data: &mut [u8]) {
         }
     };
 
-    const HORIZONTAL_MIRRORING_BIT: u8 = 0b0000_0001;
-    const VERTICAL_MIRRORING_BIT: u8 = 0b0000_0010;
-
     set_mirroring(
         HORIZONTAL_MIRRORING_BIT,
         map_cell.tile().horizontal_mirroring(),
@@ -42,94 +70,563 @@ fn set_map_cell(map_cell: &MapCell, data: &mut [u8]) {
     set_mirroring(VERTICAL_MIRRORING_BIT, map_cell.tile().vertical_mirroring());
 }
 
+// Marks the cells within `radius` cells (diagonals included) of the marked ones.
+fn expand_cells(size: usize, cells: &[bool], radius: usize) -> Vec<bool> {
+    let mut expanded_cells = vec![false; cells.len()];
+    for index in (0..cells.len()).filter(|&index| cells[index]) {
+        let position = Position::from_index(size, index);
+        let rows = position.row().saturating_sub(radius)..(position.row() + radius + 1).min(size);
+        for row in rows {
+            let columns = position.column().saturating_sub(radius)
+                ..(position.column() + radius + 1).min(size);
+            for column in columns {
+                expanded_cells[Position::new(row, column).index(size)] = true;
+            }
+        }
+    }
+    expanded_cells
+}
+
+fn decompress<R: io::Read>(input: R) -> H3mResult<Vec<u8>> {
+    let mut decoder = Decoder::new(input)?;
+    let mut raw_map = Vec::new();
+    decoder.read_to_end(&mut raw_map)?;
+    Ok(raw_map)
+}
+
+/// Options of [`H3m::set_surfaces`] and [`H3m::set_surfaces_in_region`], all off by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SurfaceOptions {
+    /// Draw the water with the HotA one-tile water tiles, which also fit
+    /// single water cells and strips of one cell.
+    pub one_tile_water: bool,
+    /// Connect the new tiles with the current terrain of the `None` cells
+    /// and keep the tiles of the cells whose neighbourhood terrain is unchanged.
+    /// Always on in [`H3m::set_surfaces_in_region`].
+    pub integration_mode: bool,
+    /// Fill the cells of the surfaces with `Some` obstacle with obstacles.
+    pub obstacles: bool,
+    /// Set the underground level instead of the land.
+    pub underground: bool,
+}
+
+/// Result of [`H3m::replace_terrain`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TerrainReplacement {
+    pub replaced_cells_count: usize,
+    pub removed_obstacles_count: usize, // of the cells replaced with water or rock
+}
+
 impl H3m {
+    /// Loads a gzip compressed HotA map, e.g. an opened h3m file.
+    ///
+    /// Fails with `H3mError::Parsing` for other map versions or a broken map.
     pub fn load<R: io::Read>(input: R) -> H3mResult<H3m> {
-        let mut decoder = Decoder::new(input)?;
-        let mut raw_map = Vec::new();
-        decoder.read_to_end(&mut raw_map)?;
+        let raw_map = decompress(input)?;
 
         Ok(H3m {
             info: parser::parse(&raw_map)?,
             raw_map,
             obstacle_generator: None,
+            passability_check: None,
+            strict_obstacles: false,
+            symmetry: None,
+            seed: rand::random(),
+            tile_variants: TileVariants::default(),
+            unfitting_tile_cells: Default::default(),
+            objects_end_offset: None,
         })
     }
 
+    /// Writes the map gzip compressed, as the map editor does.
     pub fn save<W: io::Write>(&self, output: W) -> H3mResult<()> {
         let mut encoder = Encoder::new(output)?;
+        encoder.write_all(&self.raw_map[..self.info.objects_templates_offset])?;
+        encoder.write_all(&self.objects_section()?)?;
+        encoder.finish().into_result()?;
+        Ok(())
+    }
 
-        if let Some(obstacle_generator) = &self.obstacle_generator {
-            encoder.write_all(&self.raw_map[..self.info.objects_templates_offset])?;
-            parser::write_object_templates(obstacle_generator.object_templates(), &mut encoder)?;
-            parser::write_objects(obstacle_generator.objects(), &mut encoder)?;
-            encoder.write_all(&[0u8; 124])?;
-        } else {
-            encoder.write_all(&self.raw_map)?;
+    // The object templates, the objects and the rest of the map as saved.
+    fn objects_section(&self) -> H3mResult<Vec<u8>> {
+        let objects_templates_offset = self.info.objects_templates_offset;
+        let Some(obstacle_generator) = &self.obstacle_generator else {
+            return Ok(self.raw_map[objects_templates_offset..].to_vec());
+        };
+
+        let mut objects_section = Vec::new();
+        parser::write_object_templates(
+            obstacle_generator.object_templates(),
+            &mut objects_section,
+        )?;
+        parser::write_objects(obstacle_generator.objects(), &mut objects_section)?;
+        match self.objects_end_offset {
+            Some(objects_end_offset) => {
+                objects_section.extend_from_slice(&self.raw_map[objects_end_offset..])
+            }
+            None => objects_section.extend_from_slice(&[0u8; 124]),
         }
+        Ok(objects_section)
+    }
 
-        encoder.finish().into_result()?;
+    /// Current state of the map as it would be saved.
+    pub fn snapshot(&self) -> H3mResult<MapSnapshot> {
+        let objects_templates_offset = self.info.objects_templates_offset;
+        let mut raw_map = self.raw_map[..objects_templates_offset].to_vec();
+        raw_map.extend_from_slice(&self.objects_section()?);
+        Ok(MapSnapshot { raw_map })
+    }
+
+    /// Changes of the map since the snapshot: the range of changed cells
+    /// of each level and the objects section if it changed.
+    pub fn change_since(&self, snapshot: &MapSnapshot) -> H3mResult<MapChange> {
+        let level_size = self.map_size() * self.map_size() * MAP_CELL_SIZE;
+        let ranges: Vec<(usize, usize)> =
+            [Some(self.info.land_offset), self.info.underground_offset]
+                .into_iter()
+                .flatten()
+                .map(|offset| (offset, level_size))
+                .collect();
+
+        Ok(MapChange::new(
+            &snapshot.raw_map,
+            &self.snapshot()?.raw_map,
+            &ranges,
+            self.info.objects_templates_offset,
+        ))
+    }
+
+    /// Reverts the change with `undo` or applies it again otherwise.
+    /// The map must be loaded as saved, without changes in this session.
+    ///
+    /// Fails with `H3mError::Parameter` if the map isn't in the state the change starts from.
+    pub fn apply_change(&mut self, change: &MapChange, undo: bool) -> H3mResult<()> {
+        if self.obstacle_generator.is_some() {
+            return Err(H3mError::Parameter(ParameterError::new(
+                "Can't apply a change to a map with obstacles generated in this session.",
+            )));
+        }
+
+        change.apply(&mut self.raw_map, undo)?;
+        self.info = parser::parse(&self.raw_map)?;
         Ok(())
     }
 
+    /// Side of the map in cells, both levels are square.
     pub fn map_size(&self) -> usize {
         self.info.map_size
     }
 
+    /// Whether the map has the underground level besides the land.
+    pub fn has_underground(&self) -> bool {
+        self.info.underground_offset.is_some()
+    }
+
+    /// Whether the map has objects, i.e. object templates besides the default ones.
+    pub fn has_objects(&self) -> bool {
+        self.info.has_non_default_templates
+    }
+
+    /// Passability check applied after the obstacle generation, none by default.
+    pub fn set_passability_check(&mut self, passability_check: Option<PassabilityCheck>) {
+        self.passability_check = passability_check;
+    }
+
+    /// Whether the mandatory obstacle cells no obstacle fits into fail
+    /// [`H3m::set_surfaces`] instead of being reported, off by default.
+    pub fn set_strict_obstacles(&mut self, strict_obstacles: bool) {
+        self.strict_obstacles = strict_obstacles;
+    }
+
+    /// Symmetry of the levels set by [`H3m::set_surfaces`], none by default.
+    ///
+    /// The surfaces of the symmetry source region are copied to the rest of the level,
+    /// so only the source region has to be painted. The obstacles are generated
+    /// in the source region and reproduced in the rest by the same templates or,
+    /// where they don't fit, by other templates of the same footprint. There are
+    /// no obstacles on the cells which are their own images.
+    pub fn set_symmetry(&mut self, symmetry: Option<Symmetry>) {
+        self.symmetry = symmetry;
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Seed of the tile and obstacle generation, random by default.
+    /// The same map, surfaces and seed give the same result.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+    }
+
+    /// Variants of the tiles inside one terrain areas, balanced by default.
+    pub fn set_tile_variants(&mut self, tile_variants: TileVariants) {
+        self.tile_variants = tile_variants;
+    }
+
+    /// Cells of a level, as (row, column), no tile fits in the last generation
+    /// of its tiles. Their neighbourhood can't be drawn exactly, they got
+    /// the closest tile.
+    pub fn unfitting_tile_cells(&self, underground: bool) -> &[(usize, usize)] {
+        &self.unfitting_tile_cells[underground as usize]
+    }
+
+    // Keeps the unfitting cells of the generated terrain among the written ones.
+    fn set_unfitting_tile_cells(
+        &mut self,
+        terrain_map: &TerrainMap,
+        updated_cells: Option<&[bool]>,
+    ) {
+        let map_size = self.map_size();
+        self.unfitting_tile_cells[terrain_map.underground() as usize] = terrain_map
+            .unfitting_cells()
+            .iter()
+            .filter(|position| {
+                updated_cells.map_or(true, |updated_cells| {
+                    updated_cells[position.index(map_size)]
+                })
+            })
+            .map(|position| (position.row(), position.column()))
+            .collect();
+    }
+
+    /// Sets the surfaces of the land or, with `options.underground`, the underground
+    /// level and generates the terrain tiles for them, see [`SurfaceOptions`].
+    ///
+    /// `surfaces` lists the cells row by row and must have `map_size()²` items.
+    /// `None` cells keep their current terrain; in the integration mode the new tiles
+    /// are also connected with the current terrain of these cells, and the cells
+    /// whose neighbourhood terrain is unchanged keep their current tiles.
+    ///
+    /// With `options.obstacles` the cells of surfaces with `Some` obstacle are filled
+    /// with obstacles. This is only possible for maps without objects, and obstacles
+    /// should be generated at most once per level. Returns the obstacle report if any
+    /// obstacles were requested, the mandatory obstacle cells no obstacle fits into
+    /// are listed in it.
+    ///
+    /// Fails with `H3mError::Parameter` for a wrong surfaces length, a level the map
+    /// doesn't have, obstacle surfaces without `options.obstacles`, obstacles on a map
+    /// with objects or, with [`H3m::set_strict_obstacles`], uncovered mandatory obstacle
+    /// cells. With a symmetry set the surfaces are completed by it, see [`H3m::set_symmetry`].
     pub fn set_surfaces(
         &mut self,
-        one_tile_water: bool,
-        integration_mode: bool,
-        obstacles: bool,
-        underground: bool,
+        options: SurfaceOptions,
         surfaces: &[Option<Surface>],
-    ) -> H3mResult<()> {
-        if obstacles && self.info.has_non_default_templates {
+    ) -> H3mResult<Option<ObstacleReport>> {
+        self.set_level_surfaces(options, surfaces, None)
+    }
+
+    /// Sets the surfaces of the region cells of a level in the integration mode,
+    /// `region` lists the cells row by row as `surfaces` does.
+    ///
+    /// Only the region cells and a border of one cell around them get new tiles,
+    /// the surfaces outside the region are ignored. With `options.obstacles`
+    /// the obstacles lying entirely in the region are generated again, the objects
+    /// elsewhere are left as they are. This needs the map objects to be obstacles only.
+    ///
+    /// Fails with `H3mError::Parameter` for a wrong region length, for the reasons
+    /// of [`H3m::set_surfaces`], for obstacles on a map with other objects
+    /// and with a symmetry set.
+    pub fn set_surfaces_in_region(
+        &mut self,
+        options: SurfaceOptions,
+        surfaces: &[Option<Surface>],
+        region: &[bool],
+    ) -> H3mResult<Option<ObstacleReport>> {
+        if region.len() != surfaces.len() {
+            return Err(H3mError::Parameter(ParameterError::new(format!(
+                "Region length ({}) not equal surfaces length ({}).",
+                region.len(),
+                surfaces.len()
+            ))));
+        }
+
+        let options = SurfaceOptions {
+            integration_mode: true,
+            ..options
+        };
+        self.set_level_surfaces(options, surfaces, Some(region))
+    }
+
+    fn set_level_surfaces(
+        &mut self,
+        options: SurfaceOptions,
+        surfaces: &[Option<Surface>],
+        region: Option<&[bool]>,
+    ) -> H3mResult<Option<ObstacleReport>> {
+        let SurfaceOptions {
+            one_tile_water,
+            integration_mode,
+            obstacles,
+            underground,
+        } = options;
+        if underground && !self.has_underground() {
+            return Err(H3mError::Parameter(ParameterError::new(
+                "Can't set underground map, input map has not underground.",
+            )));
+        }
+
+        if !obstacles && surfaces.iter().flatten().any(|s| s.obstacle.is_some()) {
+            return Err(H3mError::Parameter(ParameterError::new(
+                "Surfaces have obstacles, but obstacles creation is off.",
+            )));
+        }
+
+        if obstacles && region.is_none() && self.info.has_non_default_templates {
             return Err(H3mError::Parameter(ParameterError::new(
                 "Unable to add obstacles: there are several objects on the input map.",
             )));
         }
 
+        let symmetric_surfaces;
+        let surfaces = if let Some(symmetry) = self.symmetry {
+            if region.is_some() {
+                return Err(H3mError::Parameter(ParameterError::new(
+                    "Can't set surfaces in a region of a symmetric map.",
+                )));
+            }
+            let map_len = self.map_size() * self.map_size();
+            if surfaces.len() != map_len {
+                return Err(H3mError::Parameter(ParameterError::new(format!(
+                    "Surfaces length ({}) not equal squared map size ({}).",
+                    surfaces.len(),
+                    map_len
+                ))));
+            }
+            symmetric_surfaces = symmetry.complete(self.map_size(), surfaces);
+            symmetric_surfaces.as_slice()
+        } else {
+            surfaces
+        };
+
+        let mut region_surfaces;
+        let surfaces = if let Some(region) = region {
+            region_surfaces = surfaces
+                .iter()
+                .zip(region)
+                .map(|(surface, &in_region)| surface.filter(|_| in_region))
+                .collect::<Vec<_>>();
+
+            if obstacles {
+                let map_size = self.map_size();
+                let obstacle_generator =
+                    self.obstacle_generator_with_map_objects("add obstacles")?;
+                obstacle_generator.remove_objects_inside(map_size, underground, region)?;
+
+                // no new obstacles on the cells of the objects partly outside the region
+                let covered_cells = obstacle_generator.covered_cells(map_size, underground);
+                for (surface, is_covered) in region_surfaces.iter_mut().zip(covered_cells) {
+                    if let (Some(surface), true) = (surface, is_covered) {
+                        surface.obstacle = None;
+                    }
+                }
+            }
+            region_surfaces.as_slice()
+        } else {
+            surfaces
+        };
+
         let mut integrated_surfaces;
+        let mut existing_tiles = vec![None; surfaces.len()];
         let surfaces = if integration_mode {
-            integrated_surfaces = Vec::with_capacity(self.map_size());
+            integrated_surfaces = Vec::with_capacity(surfaces.len());
+            let mut changed_cells = Vec::with_capacity(surfaces.len());
             for (index, surface) in surfaces.iter().enumerate() {
+                let terrain = self.get_terrain_by_index(index, underground)?;
+                changed_cells.push(surface.is_some_and(|surface| surface.terrain != terrain));
                 if surface.is_some() {
                     integrated_surfaces.push(*surface);
                 } else {
                     let current_map_surface = Surface {
-                        terrain: self.get_terrain_by_index(index, underground)?,
-                        obstacle: false,
+                        terrain,
+                        obstacle: None,
                     };
                     integrated_surfaces.push(Some(current_map_surface));
                 }
             }
+
+            // The tiles depend on the neighbours' terrain, so only the cells
+            // without changed neighbours may keep their tiles.
+            let regenerated_cells =
+                expand_cells(self.map_size(), region.unwrap_or(&changed_cells), 1);
+            for (index, existing_tile) in existing_tiles.iter_mut().enumerate() {
+                if !regenerated_cells[index] {
+                    *existing_tile = Some(self.get_existing_tile_by_index(index, underground)?);
+                }
+            }
             integrated_surfaces.as_slice()
         } else {
             surfaces
         };
 
-        let terrain_map =
-            TerrainMap::generate(self.map_size(), one_tile_water, underground, surfaces)?;
+        let terrain_map = TerrainMap::generate_keeping_tiles(
+            self.map_size(),
+            one_tile_water,
+            &self.tile_variants,
+            underground,
+            self.seed,
+            surfaces,
+            &existing_tiles,
+        )?;
 
+        let updated_cells = region.map(|region| expand_cells(self.map_size(), region, 1));
+        self.set_unfitting_tile_cells(&terrain_map, updated_cells.as_deref());
         for (index, map_cell) in terrain_map.cells().iter().enumerate() {
-            if let Some(map_cell) = map_cell {
+            let is_updated = updated_cells
+                .as_ref()
+                .map_or(true, |updated_cells| updated_cells[index]);
+            if let (Some(map_cell), true) = (map_cell, is_updated) {
                 self.set_map_cell_by_index(index, underground, map_cell)?;
             }
         }
 
         if obstacles {
-            let obstacle_generator = self
-                .obstacle_generator
-                .get_or_insert_with(|| ObstacleGenerator::new(&self.info.default_object_templates));
+            let obstacle_generator = self.obstacle_generator.get_or_insert_with(|| {
+                ObstacleGenerator::new(&self.info.default_object_templates, self.seed)
+            });
 
             if terrain_map.has_obstacles() {
-                obstacle_generator.generate(&terrain_map)?;
+                return Ok(Some(obstacle_generator.generate(
+                    &terrain_map,
+                    self.passability_check,
+                    self.symmetry,
+                    self.strict_obstacles,
+                )?));
             }
         } else {
             assert!(!terrain_map.has_obstacles());
         }
 
-        Ok(())
+        Ok(None)
+    }
+
+    // Obstacle generator continuing the objects of the map, which must all be obstacles.
+    // The action names what fails in the error, e.g. "add obstacles".
+    fn obstacle_generator_with_map_objects(
+        &mut self,
+        action: &str,
+    ) -> H3mResult<&mut ObstacleGenerator> {
+        if self.obstacle_generator.is_none() {
+            let mut obstacle_generator =
+                ObstacleGenerator::new(&self.info.default_object_templates, self.seed);
+            let objects_section = parser::read_objects_section(
+                &self.raw_map,
+                self.info.objects_templates_offset,
+                |template| obstacle_generator.is_obstacle_template(template),
+            )?
+            .ok_or_else(|| {
+                H3mError::Parameter(ParameterError::new(format!(
+                    "Unable to {}: there are objects other than obstacles on the input map.",
+                    action
+                )))
+            })?;
+            obstacle_generator.set_objects(objects_section.templates, objects_section.objects)?;
+            self.objects_end_offset = Some(objects_section.end_offset);
+            self.obstacle_generator = Some(obstacle_generator);
+        }
+        Ok(self.obstacle_generator.as_mut().unwrap())
+    }
+
+    /// Replaces terrains of a level by the replacements map, e.g. snow with grass.
+    ///
+    /// Only the tiles of the replaced cells and their neighbours are generated again,
+    /// the other cells keep their tiles. Objects are left as they are, except
+    /// the obstacles covering the cells replaced with water or rock, which are removed.
+    ///
+    /// Fails with `H3mError::Parameter` for a replacement with water or rock
+    /// of the cells of a map with objects other than obstacles, as the cells
+    /// of those objects can't be read.
+    pub fn replace_terrain(
+        &mut self,
+        one_tile_water: bool,
+        underground: bool,
+        replacements: &HashMap<Terrain, Terrain>,
+    ) -> H3mResult<TerrainReplacement> {
+        let map_size = self.map_size();
+        let mut terrains = Vec::with_capacity(map_size * map_size);
+        for index in 0..map_size * map_size {
+            terrains.push(self.get_terrain_by_index(index, underground)?);
+        }
+
+        let replaced_cells: Vec<bool> = terrains
+            .iter()
+            .map(|terrain| replacements.get(terrain).is_some_and(|new| new != terrain))
+            .collect();
+        let replaced_cells_count = replaced_cells.iter().filter(|&&replaced| replaced).count();
+        if replaced_cells_count == 0 {
+            return Ok(TerrainReplacement::default());
+        }
+
+        // no obstacle may stay on the cells it can't be placed on
+        let non_ground_cells: Vec<bool> = terrains
+            .iter()
+            .zip(&replaced_cells)
+            .map(|(terrain, &is_replaced)| is_replaced && !replacements[terrain].is_ground())
+            .collect();
+        let removed_obstacles_count = if non_ground_cells.contains(&true) {
+            self.obstacle_generator_with_map_objects("replace terrain with water or rock")?
+                .remove_objects_covering(map_size, underground, &non_ground_cells)?
+        } else {
+            0
+        };
+
+        // The tiles of the neighbours depend on their own neighbours' terrain,
+        // so one more ring of cells is given to the generator but not written.
+        let updated_cells = expand_cells(map_size, &replaced_cells, 1);
+        let context_cells = expand_cells(map_size, &replaced_cells, 2);
+        let surfaces: Vec<Option<Surface>> = terrains
+            .iter()
+            .zip(&context_cells)
+            .map(|(terrain, &is_context)| {
+                is_context.then(|| Surface::new(*replacements.get(terrain).unwrap_or(terrain)))
+            })
+            .collect();
+
+        let terrain_map = TerrainMap::generate(
+            map_size,
+            one_tile_water,
+            &self.tile_variants,
+            underground,
+            self.seed,
+            &surfaces,
+        )?;
+        self.set_unfitting_tile_cells(&terrain_map, Some(&updated_cells));
+        for (index, map_cell) in terrain_map.cells().iter().enumerate() {
+            if let (Some(map_cell), true) = (map_cell, updated_cells[index]) {
+                self.set_map_cell_by_index(index, underground, map_cell)?;
+            }
+        }
+
+        Ok(TerrainReplacement {
+            replaced_cells_count,
+            removed_obstacles_count,
+        })
+    }
+
+    /// Current surfaces of a level row by row: the terrain of the cells and
+    /// an obstacle for the cells covered by the obstacles generated in this session.
+    pub fn surfaces(&self, underground: bool) -> H3mResult<Vec<Surface>> {
+        let map_size = self.map_size();
+        let covered_cells = self
+            .obstacle_generator
+            .as_ref()
+            .map(|obstacle_generator| obstacle_generator.covered_cells(map_size, underground));
+
+        let mut surfaces = Vec::with_capacity(map_size * map_size);
+        for index in 0..map_size * map_size {
+            let is_covered = covered_cells
+                .as_ref()
+                .is_some_and(|covered_cells| covered_cells[index]);
+            surfaces.push(Surface {
+                terrain: self.get_terrain_by_index(index, underground)?,
+                obstacle: if is_covered {
+                    Some(Obstacle::new(ObstacleCategory::Any))
+                } else {
+                    None
+                },
+            });
+        }
+        Ok(surfaces)
     }
 
     fn set_map_cell_by_index(
@@ -163,6 +660,24 @@ impl H3m {
     }
 
     fn get_terrain_by_index(&self, index: usize, underground: bool) -> H3mResult<Terrain> {
+        let data = self.map_cell_data_by_index(index, underground)?;
+        Terrain::from_code(data[0])
+    }
+
+    fn get_existing_tile_by_index(
+        &self,
+        index: usize,
+        underground: bool,
+    ) -> H3mResult<ExistingTile> {
+        let data = self.map_cell_data_by_index(index, underground)?;
+        Ok(ExistingTile {
+            code: data[1],
+            vertical_mirroring: data[6] & VERTICAL_MIRRORING_BIT != 0,
+            horizontal_mirroring: data[6] & HORIZONTAL_MIRRORING_BIT != 0,
+        })
+    }
+
+    fn map_cell_data_by_index(&self, index: usize, underground: bool) -> H3mResult<&[u8]> {
         let map_length = self.map_size() * self.map_size();
         if index >= map_length {
             return Err(H3mError::Parameter(ParameterError::new(format!(
@@ -181,7 +696,290 @@ impl H3m {
             self.info.land_offset
         } + index * MAP_CELL_SIZE;
 
-        let data = &self.raw_map[offset..offset + MAP_CELL_SIZE];
-        Terrain::from_code(data[0])
+        Ok(&self.raw_map[offset..offset + MAP_CELL_SIZE])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::test_map::TestMap;
+
+    fn map_cell_data(h3m: &H3m, index: usize) -> &[u8] {
+        let offset = h3m.info.land_offset + index * MAP_CELL_SIZE;
+        &h3m.raw_map[offset..offset + MAP_CELL_SIZE]
+    }
+
+    #[test]
+    fn expand_cells_test() {
+        #[rustfmt::skip]
+        let cells = [
+            false, false, false, false,
+            false, false, false, false,
+            false, false, false, true,
+            false, false, false, false,
+        ];
+
+        #[rustfmt::skip]
+        assert_eq!(expand_cells(4, &cells, 1), [
+            false, false, false, false,
+            false, false, true,  true,
+            false, false, true,  true,
+            false, false, true,  true,
+        ]);
+    }
+
+    #[test]
+    fn replace_terrain_test() {
+        let map_size = 8;
+        let mut test_map = TestMap::new(map_size, Terrain::Grass);
+        for index in [0, 1, map_size, map_size + 1] {
+            test_map.land[index] = Terrain::Snow;
+        }
+        let mut h3m = H3m::load(&test_map.compressed()[..]).unwrap();
+        let original_h3m = H3m::load(&test_map.compressed()[..]).unwrap();
+
+        let replacements = HashMap::from([(Terrain::Snow, Terrain::Sand)]);
+        let replacement = h3m.replace_terrain(false, false, &replacements).unwrap();
+        assert_eq!(replacement.replaced_cells_count, 4);
+        assert_eq!(replacement.removed_obstacles_count, 0);
+
+        for index in 0..map_size * map_size {
+            let position = Position::from_index(map_size, index);
+            let expected_terrain = if position.row() < 2 && position.column() < 2 {
+                Terrain::Sand
+            } else {
+                Terrain::Grass
+            };
+            assert_eq!(
+                h3m.get_terrain_by_index(index, false).unwrap(),
+                expected_terrain
+            );
+
+            if position.row() > 2 || position.column() > 2 {
+                assert_eq!(
+                    map_cell_data(&h3m, index),
+                    map_cell_data(&original_h3m, index)
+                );
+            }
+        }
+        assert_ne!(map_cell_data(&h3m, 0), map_cell_data(&original_h3m, 0));
+    }
+
+    #[test]
+    fn replace_terrain_with_water_removes_obstacles_test() {
+        let map_size = 16;
+        let mut test_map = TestMap::new(map_size, Terrain::Grass);
+        for index in 0..map_size * map_size {
+            if index / map_size < 6 && index % map_size < 6 {
+                test_map.land[index] = Terrain::Snow;
+            }
+        }
+        let mut h3m = H3m::load(&test_map.compressed()[..]).unwrap();
+        h3m.set_seed(0);
+        let surfaces: Vec<Option<Surface>> = test_map
+            .land
+            .iter()
+            .map(|&terrain| {
+                Some(Surface::with_obstacle(
+                    terrain,
+                    Obstacle::new(ObstacleCategory::Any),
+                ))
+            })
+            .collect();
+        h3m.set_surfaces(
+            SurfaceOptions {
+                obstacles: true,
+                ..SurfaceOptions::default()
+            },
+            &surfaces,
+        )
+        .unwrap();
+        let mut map = Vec::new();
+        h3m.save(&mut map).unwrap();
+
+        let mut h3m = H3m::load(&map[..]).unwrap();
+        let replacements = HashMap::from([(Terrain::Snow, Terrain::Water)]);
+        let replacement = h3m.replace_terrain(false, false, &replacements).unwrap();
+        assert_eq!(replacement.replaced_cells_count, 36);
+        assert!(replacement.removed_obstacles_count > 0);
+
+        let mut saved_map = Vec::new();
+        h3m.save(&mut saved_map).unwrap();
+        let mut h3m = H3m::load(&saved_map[..]).unwrap();
+        let covered_cells = h3m
+            .obstacle_generator_with_map_objects("add obstacles")
+            .unwrap()
+            .covered_cells(map_size, false);
+        for (index, &is_covered) in covered_cells.iter().enumerate() {
+            if is_covered {
+                assert_ne!(
+                    h3m.get_terrain_by_index(index, false).unwrap(),
+                    Terrain::Water
+                );
+            }
+        }
+        assert!(covered_cells.contains(&true));
+    }
+
+    #[test]
+    fn integration_mode_keeps_tiles_test() {
+        let map_size = 12;
+        let mut test_map = TestMap::new(map_size, Terrain::Grass);
+        for index in [5 * map_size + 5, 5 * map_size + 6, 6 * map_size + 5] {
+            test_map.land[index] = Terrain::Dirt;
+        }
+        let mut h3m = H3m::load(&test_map.compressed()[..]).unwrap();
+        let surfaces: Vec<Option<Surface>> = test_map
+            .land
+            .iter()
+            .map(|&terrain| Some(Surface::new(terrain)))
+            .collect();
+        h3m.set_surfaces(SurfaceOptions::default(), &surfaces)
+            .unwrap();
+        let generated_map = h3m.raw_map.clone();
+
+        h3m.set_seed(h3m.seed.wrapping_add(1));
+        h3m.set_surfaces(
+            SurfaceOptions {
+                integration_mode: true,
+                ..SurfaceOptions::default()
+            },
+            &surfaces,
+        )
+        .unwrap();
+        assert!(h3m.raw_map == generated_map);
+
+        let mut surfaces = vec![None; map_size * map_size];
+        surfaces[0] = Some(Surface::new(Terrain::Sand));
+        h3m.set_surfaces(
+            SurfaceOptions {
+                integration_mode: true,
+                ..SurfaceOptions::default()
+            },
+            &surfaces,
+        )
+        .unwrap();
+        assert_eq!(h3m.get_terrain_by_index(0, false).unwrap(), Terrain::Sand);
+
+        let offset = h3m.info.land_offset;
+        for index in 0..map_size * map_size {
+            let position = Position::from_index(map_size, index);
+            if position.row() > 2 || position.column() > 2 {
+                let cell_offset = offset + index * MAP_CELL_SIZE;
+                assert_eq!(
+                    map_cell_data(&h3m, index),
+                    &generated_map[cell_offset..cell_offset + MAP_CELL_SIZE]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn set_surfaces_in_region_test() {
+        let map_size = 16;
+        let test_map = TestMap::new(map_size, Terrain::Grass);
+        let mut h3m = H3m::load(&test_map.compressed()[..]).unwrap();
+        h3m.set_seed(0);
+        let forest = Surface::with_obstacle(Terrain::Grass, Obstacle::new(ObstacleCategory::Any));
+        h3m.set_surfaces(
+            SurfaceOptions {
+                obstacles: true,
+                ..SurfaceOptions::default()
+            },
+            &vec![Some(forest); map_size * map_size],
+        )
+        .unwrap();
+        let mut map = Vec::new();
+        h3m.save(&mut map).unwrap();
+
+        let original_h3m = H3m::load(&map[..]).unwrap();
+        let mut h3m = H3m::load(&map[..]).unwrap();
+        let region: Vec<bool> = (0..map_size * map_size)
+            .map(|index| index / map_size < 6 && index % map_size < 6)
+            .collect();
+        let sand = Surface::with_obstacle(Terrain::Sand, Obstacle::new(ObstacleCategory::Any));
+        let report = h3m
+            .set_surfaces_in_region(
+                SurfaceOptions {
+                    obstacles: true,
+                    ..SurfaceOptions::default()
+                },
+                &vec![Some(sand); map_size * map_size],
+                &region,
+            )
+            .unwrap();
+        assert!(report.is_some());
+
+        let updated_cells = expand_cells(map_size, &region, 1);
+        for index in 0..map_size * map_size {
+            let expected_terrain = if region[index] {
+                Terrain::Sand
+            } else {
+                Terrain::Grass
+            };
+            assert_eq!(
+                h3m.get_terrain_by_index(index, false).unwrap(),
+                expected_terrain
+            );
+            if !updated_cells[index] {
+                assert_eq!(
+                    map_cell_data(&h3m, index),
+                    map_cell_data(&original_h3m, index)
+                );
+            }
+        }
+
+        // the obstacles outside the region are kept, the new ones are inside it
+        let covered_cells = |map: &[u8]| {
+            let mut h3m = H3m::load(map).unwrap();
+            let obstacle_generator = h3m
+                .obstacle_generator_with_map_objects("add obstacles")
+                .unwrap();
+            obstacle_generator.covered_cells(map_size, false)
+        };
+        let mut saved_map = Vec::new();
+        h3m.save(&mut saved_map).unwrap();
+        let original_covered_cells = covered_cells(&map);
+        let covered_cells = covered_cells(&saved_map);
+        for index in 0..map_size * map_size {
+            if !region[index] {
+                assert_eq!(covered_cells[index], original_covered_cells[index]);
+            }
+        }
+        assert!((0..map_size * map_size).any(|index| region[index] && covered_cells[index]));
+    }
+
+    #[test]
+    fn apply_change_test() {
+        let map_size = 16;
+        let test_map = TestMap::new(map_size, Terrain::Grass);
+        let mut h3m = H3m::load(&test_map.compressed()[..]).unwrap();
+        let snapshot = h3m.snapshot().unwrap();
+        let forest = Surface::with_obstacle(Terrain::Dirt, Obstacle::new(ObstacleCategory::Any));
+        let mut surfaces = vec![None; map_size * map_size];
+        surfaces[..3 * map_size].fill(Some(forest));
+        h3m.set_surfaces(
+            SurfaceOptions {
+                integration_mode: true,
+                obstacles: true,
+                ..SurfaceOptions::default()
+            },
+            &surfaces,
+        )
+        .unwrap();
+        let change = h3m.change_since(&snapshot).unwrap();
+        let mut changed_map = Vec::new();
+        h3m.save(&mut changed_map).unwrap();
+
+        assert!(h3m.apply_change(&change, true).is_err());
+        let mut h3m = H3m::load(&changed_map[..]).unwrap();
+        h3m.apply_change(&change, true).unwrap();
+        assert!(h3m.raw_map == test_map.raw_map());
+        assert!(!h3m.info.has_non_default_templates);
+
+        assert!(h3m.apply_change(&change, true).is_err());
+        h3m.apply_change(&change, false).unwrap();
+        assert!(h3m.raw_map == H3m::load(&changed_map[..]).unwrap().raw_map);
     }
 }
@@ -1,7 +1,7 @@
 use crate::h3m::Terrain;
 
 impl Terrain {
-    pub fn group(self) -> u16 {
+    pub(crate) fn group(self) -> u16 {
         1 << (self.code() as u16)
     }
 }
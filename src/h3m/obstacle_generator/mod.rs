@@ -1,19 +1,74 @@
-use crate::h3m::parser::{DefaultObjectTemplates, H3mObject, H3mObjectTemplate};
+use crate::common::position::Position;
+use crate::common::rng::{self, SeededRng};
+use crate::h3m::parser::{
+    DefaultObjectTemplates, H3mObject, H3mObjectTemplate, DEFAULT_OBJECT_TEMPLATES_COUNT,
+};
 use crate::h3m::result::*;
+use crate::h3m::symmetry::Symmetry;
 use crate::h3m::terrain_map::TerrainMap;
+#[cfg(test)]
+use crate::h3m::{ObstacleCategory, Terrain};
 use filename_to_template_index_map::FilenameToTemplateIndexMap;
+pub use obstacle_coverage::ObstacleCoverage;
+pub use obstacle_map::CutOffArea;
 use obstacle_map::{ObstacleMap, ObstacleMapArea};
+pub use obstacle_report::ObstacleReport;
 use obstacle_template_list::ObstacleTemplateList;
-use rand::rngs::ThreadRng;
+use rayon::prelude::*;
+use serde::Deserialize;
+use symmetric_templates::SymmetricTemplates;
 use template_index_set::TemplateIndexSet;
 
 mod common;
 mod filename_to_template_index_map;
+mod obstacle_coverage;
 mod obstacle_map;
+mod obstacle_report;
 mod obstacle_template;
 mod obstacle_template_list;
+mod symmetric_templates;
 mod template_index_set;
 
+// Whether some obstacle of the category may be placed on the terrain.
+#[cfg(test)]
+pub fn has_obstacle_templates(terrain: Terrain, category: ObstacleCategory) -> bool {
+    ObstacleTemplateList::new().has_category_templates(terrain, category)
+}
+
+// Areas of one group are processed in parallel, so the side has to exceed
+// the obstacle shape size (8) plus the biggest sparsity distance (25):
+// then obstacles of the different areas of the group never affect each other.
+const AREA_SIDE: usize = 36;
+
+const SPARSITY_PENALTIES: [usize; 7] = [0, 1, 2, 4, 8, 16, 32];
+
+// Random stream of the area, independent of the order the areas are processed in.
+fn area_stream(underground: bool, pass_index: usize, area_index: usize) -> u64 {
+    (1 << 32) | ((underground as u64) << 31) | ((pass_index as u64) << 16) | area_index as u64
+}
+
+// Indexes of the map cells covered by the object.
+fn object_cells(
+    object: &H3mObject,
+    template: &H3mObjectTemplate,
+    map_size: usize,
+) -> impl Iterator<Item = usize> {
+    let position = Position::new(object.row() as usize, object.column() as usize);
+    obstacle_template::make_shape(&template.shape_mask)
+        .into_iter()
+        .filter_map(move |delta| position.checked_sub_delta(&delta))
+        .filter(move |position| position.row() < map_size && position.column() < map_size)
+        .map(move |position| position.index(map_size))
+}
+
+/// Check that obstacles don't cut off passable areas of the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PassabilityCheck {
+    Warn, // report passable areas cut off by obstacles
+    Fix,  // remove obstacles on optional cells to reconnect cut off areas
+}
+
 struct ObjectsData {
     templates: Vec<H3mObjectTemplate>,
     objects: Vec<H3mObject>,
@@ -31,105 +86,353 @@ impl ObjectsData {
     }
 }
 
+struct Placement {
+    template_index: usize,
+    position_index: usize,
+}
+
 pub struct ObstacleGenerator {
     obstacle_template_list: ObstacleTemplateList,
     objects_data: ObjectsData,
-    rng: ThreadRng,
+    seed: u64,
+    #[cfg(test)]
+    full_scan: bool,
 }
 
 impl ObstacleGenerator {
-    pub fn new(default_object_templates: &DefaultObjectTemplates) -> ObstacleGenerator {
+    pub fn new(default_object_templates: &DefaultObjectTemplates, seed: u64) -> ObstacleGenerator {
         ObstacleGenerator {
             obstacle_template_list: ObstacleTemplateList::new(),
             objects_data: ObjectsData::new(default_object_templates),
-            rng: rand::thread_rng(),
+            seed,
+            #[cfg(test)]
+            full_scan: false,
+        }
+    }
+
+    // Templates of the generated obstacles, the objects of them have no properties.
+    pub fn is_obstacle_template(&self, template: &H3mObjectTemplate) -> bool {
+        self.obstacle_template_list
+            .iter()
+            .any(|obstacle| obstacle.filename() == template.filename)
+    }
+
+    // Continues the objects of the map, the new obstacles reuse its templates.
+    pub fn set_objects(
+        &mut self,
+        templates: Vec<H3mObjectTemplate>,
+        objects: Vec<H3mObject>,
+    ) -> H3mResult<()> {
+        for (h3m_template_index, template) in templates.iter().enumerate() {
+            let template_index = self
+                .obstacle_template_list
+                .iter()
+                .position(|obstacle| obstacle.filename() == template.filename);
+            if let Some(template_index) = template_index {
+                self.obstacle_template_list
+                    .template_mut(template_index)
+                    .set_h3m_template_index(h3m_template_index)?;
+            }
         }
+
+        self.objects_data = ObjectsData { templates, objects };
+        Ok(())
+    }
+
+    // Removes the objects of the level which cells are all in the region,
+    // returns the number of removed objects.
+    pub fn remove_objects_inside(
+        &mut self,
+        map_size: usize,
+        underground: bool,
+        region: &[bool],
+    ) -> H3mResult<usize> {
+        let objects_count = self.objects_data.objects.len();
+        let templates = &self.objects_data.templates;
+        self.objects_data.objects.retain(|object| {
+            object.underground() != underground
+                || !object_cells(object, &templates[object.template_idx() as usize], map_size)
+                    .all(|index| region[index])
+        });
+        self.remove_unused_templates()?;
+        Ok(objects_count - self.objects_data.objects.len())
+    }
+
+    // Removes the objects of the level which cover any of the cells,
+    // returns the number of removed objects.
+    pub fn remove_objects_covering(
+        &mut self,
+        map_size: usize,
+        underground: bool,
+        cells: &[bool],
+    ) -> H3mResult<usize> {
+        let objects_count = self.objects_data.objects.len();
+        let templates = &self.objects_data.templates;
+        self.objects_data.objects.retain(|object| {
+            object.underground() != underground
+                || !object_cells(object, &templates[object.template_idx() as usize], map_size)
+                    .any(|index| cells[index])
+        });
+        self.remove_unused_templates()?;
+        Ok(objects_count - self.objects_data.objects.len())
     }
 
-    pub fn generate(&mut self, terrain_map: &TerrainMap) -> H3mResult<()> {
-        let mut obstacle_map = ObstacleMap::new(terrain_map)?;
+    // Removes the templates no object uses any more, but the default ones,
+    // and renumbers the templates of the objects and the obstacle templates.
+    fn remove_unused_templates(&mut self) -> H3mResult<()> {
+        let templates_count = self.objects_data.templates.len();
+        let mut is_used = vec![false; templates_count];
+        is_used[..DEFAULT_OBJECT_TEMPLATES_COUNT].fill(true);
+        for object in &self.objects_data.objects {
+            is_used[object.template_idx() as usize] = true;
+        }
+        if is_used.iter().all(|&is_used| is_used) {
+            return Ok(());
+        }
+
+        let mut new_indexes = vec![None; templates_count];
+        let mut used_templates_count = 0;
+        for (new_index, &is_used) in new_indexes.iter_mut().zip(&is_used) {
+            if is_used {
+                *new_index = Some(used_templates_count);
+                used_templates_count += 1;
+            }
+        }
+
+        let mut template_index = 0;
+        self.objects_data.templates.retain(|_| {
+            template_index += 1;
+            is_used[template_index - 1]
+        });
+        for object in &mut self.objects_data.objects {
+            let new_index = new_indexes[object.template_idx() as usize].unwrap();
+            object.set_template_idx(new_index.try_into()?);
+        }
+        // 0 marks the obstacle templates not added to the map
+        for obstacle in self.obstacle_template_list.iter_mut() {
+            let h3m_template_index = obstacle.h3m_template_index() as usize;
+            if h3m_template_index != 0 {
+                obstacle.set_h3m_template_index(new_indexes[h3m_template_index].unwrap_or(0))?;
+            }
+        }
+        Ok(())
+    }
+
+    // With the symmetry the obstacles are generated in its source region and reproduced
+    // in the image regions by the same template or, if it doesn't fit there, another one
+    // of the same footprint. The passability fix may break the symmetry.
+    // The mandatory cells no obstacle covers are reported, in the strict mode they fail.
+    pub fn generate(
+        &mut self,
+        terrain_map: &TerrainMap,
+        passability_check: Option<PassabilityCheck>,
+        symmetry: Option<Symmetry>,
+        strict: bool,
+    ) -> H3mResult<ObstacleReport> {
+        let mut obstacle_map = ObstacleMap::new(terrain_map, symmetry)?;
+        let fallback_categories =
+            obstacle_map.fall_back_to_any_category(&self.obstacle_template_list);
+        let symmetric_templates = symmetry
+            .map(|symmetry| SymmetricTemplates::new(symmetry, &self.obstacle_template_list));
 
         let filename_to_template_index_map =
             FilenameToTemplateIndexMap::new(&self.obstacle_template_list);
 
         let map_size = terrain_map.size();
-        let areas = obstacle_map::make_areas(map_size, 36, 36);
+        let mut areas = obstacle_map::make_areas(map_size, AREA_SIDE, AREA_SIDE);
+        obstacle_map.set_areas_terrain_group(&mut areas);
+        #[cfg(test)]
+        for area in &mut areas {
+            area.set_full_scan(self.full_scan);
+        }
+        let area_groups = obstacle_map::make_area_groups(map_size, AREA_SIDE, AREA_SIDE);
 
-        for sparsity_penalty in [0, 1, 2, 4, 8, 16, 32] {
+        let mut covered_cells_count = obstacle_map.coverage().covered_cells_count();
+        for (pass_index, sparsity_penalty) in SPARSITY_PENALTIES.into_iter().enumerate() {
             obstacle_map.set_sparsity_penalty(sparsity_penalty);
 
             let template_index_set = TemplateIndexSet::new(
                 obstacle_map.generalized_terrain_group(),
                 &self.obstacle_template_list,
+                symmetric_templates.as_ref(),
+                obstacle_map.may_place_surface_only_obstacles(),
             );
 
-            for area in areas.iter().rev() {
-                self.generate_in_area(
+            for area_group in area_groups.iter().rev() {
+                let placements = self.generate_in_area_group(
                     terrain_map.underground(),
+                    pass_index,
                     &filename_to_template_index_map,
-                    template_index_set.clone(),
-                    area,
-                    &mut obstacle_map,
-                )?;
+                    &template_index_set,
+                    symmetric_templates.as_ref(),
+                    &mut areas,
+                    area_group,
+                    &obstacle_map,
+                );
+
+                for placement in placements {
+                    self.add_obstacle(
+                        placement.template_index,
+                        placement.position_index,
+                        terrain_map.underground(),
+                        &mut obstacle_map,
+                    )?;
+                }
             }
 
-            if obstacle_map.first_position_to_place_obstacle().is_none() {
+            // the optional cells may still gain obstacles with a higher sparsity penalty
+            let previous_covered_cells_count = covered_cells_count;
+            covered_cells_count = obstacle_map.coverage().covered_cells_count();
+            if obstacle_map.first_position_to_place_obstacle().is_none()
+                && covered_cells_count == previous_covered_cells_count
+            {
                 break;
             }
         }
 
-        if let Some(position) = obstacle_map.first_position_to_place_obstacle() {
-            return Err(H3mError::Internal(InternalError::new(format!(
-                "failed to place obstacle in position (row: {}, column: {}).",
+        if let (Some(position), true) = (obstacle_map.first_position_to_place_obstacle(), strict) {
+            return Err(H3mError::Parameter(ParameterError::new(format!(
+                "Failed to place obstacle in position (row: {}, column: {}).",
                 position.row(),
                 position.column()
             ))));
         }
+        let uncovered_cells = obstacle_map.uncovered_mandatory_cells();
 
-        Ok(())
+        let removed_obstacles_count = if passability_check == Some(PassabilityCheck::Fix) {
+            self.remove_cut_off_obstacles(&mut obstacle_map)?
+        } else {
+            0
+        };
+
+        let cut_off_areas = if passability_check.is_some() {
+            obstacle_map.cut_off_areas(&self.obstacle_template_list)
+        } else {
+            Vec::new()
+        };
+
+        Ok(ObstacleReport::new(
+            obstacle_map.coverage(),
+            uncovered_cells,
+            fallback_categories,
+            cut_off_areas,
+            removed_obstacles_count,
+        ))
     }
 
-    fn generate_in_area(
-        &mut self,
+    fn remove_cut_off_obstacles(&mut self, obstacle_map: &mut ObstacleMap) -> H3mResult<usize> {
+        let mut removed_object_indexes: Vec<usize> = obstacle_map
+            .fix_cut_off_areas(&self.obstacle_template_list)
+            .iter()
+            .map(|placed_obstacle| placed_obstacle.object_index())
+            .collect();
+
+        removed_object_indexes.sort_unstable();
+        for &object_index in removed_object_indexes.iter().rev() {
+            self.objects_data.objects.remove(object_index);
+        }
+        self.remove_unused_templates()?;
+        Ok(removed_object_indexes.len())
+    }
+
+    // Generates obstacles in the areas of the group on copies of the obstacle map,
+    // returns the placements in the area order.
+    #[allow(clippy::too_many_arguments)]
+    fn generate_in_area_group(
+        &self,
         underground: bool,
+        pass_index: usize,
+        filename_to_template_index_map: &FilenameToTemplateIndexMap,
+        template_index_set: &TemplateIndexSet,
+        symmetric_templates: Option<&SymmetricTemplates>,
+        areas: &mut [ObstacleMapArea],
+        area_group: &[usize],
+        obstacle_map: &ObstacleMap,
+    ) -> Vec<Placement> {
+        let mut area_placements: Vec<Vec<Placement>> = areas
+            .par_iter_mut()
+            .enumerate()
+            .filter(|(area_index, _)| area_group.contains(area_index))
+            .map_init(
+                || obstacle_map.clone(),
+                |thread_obstacle_map, (area_index, area)| {
+                    // The images of the obstacles of an area may lie next to another area
+                    // of the group, so with the symmetry each area gets a clean copy.
+                    let mut area_obstacle_map;
+                    let obstacle_map = if symmetric_templates.is_some() {
+                        area_obstacle_map = obstacle_map.clone();
+                        &mut area_obstacle_map
+                    } else {
+                        thread_obstacle_map
+                    };
+                    let mut rng = rng::stream_rng(
+                        self.seed,
+                        area_stream(underground, pass_index, area_index),
+                    );
+                    self.generate_in_area(
+                        filename_to_template_index_map,
+                        template_index_set.clone(),
+                        symmetric_templates,
+                        area,
+                        obstacle_map,
+                        &mut rng,
+                    )
+                },
+            )
+            .collect();
+
+        area_placements.reverse();
+        area_placements.into_iter().flatten().collect()
+    }
+
+    fn generate_in_area(
+        &self,
         filename_to_template_index_map: &FilenameToTemplateIndexMap,
         mut template_index_set: TemplateIndexSet,
-        area: &ObstacleMapArea,
+        symmetric_templates: Option<&SymmetricTemplates>,
+        area: &mut ObstacleMapArea,
         obstacle_map: &mut ObstacleMap,
-    ) -> H3mResult<()> {
+        rng: &mut SeededRng,
+    ) -> Vec<Placement> {
+        let mut placements = Vec::new();
         while !template_index_set.is_empty() {
-            let template_index = template_index_set.random_index(&mut self.rng);
-            let position_index = self.try_position_obstacle(
+            let template_index = template_index_set.random_index(rng);
+            let position_index = obstacle_map.try_position_obstacle(
+                area,
                 template_index,
                 filename_to_template_index_map,
-                area,
-                obstacle_map,
+                &self.obstacle_template_list,
+                symmetric_templates,
+                rng,
             );
             match position_index {
                 Some(position_index) => {
-                    self.add_obstacle(template_index, position_index, underground, obstacle_map)?
+                    let image_placements = match symmetric_templates {
+                        Some(symmetric_templates) => obstacle_map
+                            .image_placements(
+                                position_index,
+                                template_index,
+                                &self.obstacle_template_list,
+                                symmetric_templates,
+                            )
+                            .unwrap(),
+                        None => Vec::new(),
+                    };
+                    for (template_index, position_index) in
+                        std::iter::once((template_index, position_index)).chain(image_placements)
+                    {
+                        let obstacle = self.obstacle_template_list.template(template_index);
+                        obstacle_map.place_obstacle(position_index, template_index, obstacle);
+                        placements.push(Placement {
+                            template_index,
+                            position_index,
+                        });
+                    }
                 }
                 None => template_index_set.remove_index(template_index),
             }
         }
-        Ok(())
-    }
-
-    fn try_position_obstacle(
-        &mut self,
-        template_index: usize,
-        filename_to_template_index_map: &FilenameToTemplateIndexMap,
-        area: &ObstacleMapArea,
-        obstacle_map: &ObstacleMap,
-    ) -> Option<usize> {
-        let obstacle = self.obstacle_template_list.template(template_index);
-        obstacle_map.try_position_obstacle(
-            area,
-            template_index,
-            filename_to_template_index_map,
-            obstacle,
-            &mut self.rng,
-        )
+        placements
     }
 
     fn add_obstacle(
@@ -149,6 +452,7 @@ impl ObstacleGenerator {
         }
 
         let position = obstacle_map.position(position_index);
+        let object_index = self.objects_data.objects.len();
         self.objects_data
             .objects
             .push(H3mObject::without_properties(
@@ -158,7 +462,7 @@ impl ObstacleGenerator {
                 obstacle.h3m_template_index(),
             ));
 
-        obstacle_map.add_obstacle(position_index, template_index, obstacle);
+        obstacle_map.add_obstacle(position_index, template_index, object_index, obstacle);
 
         Ok(())
     }
@@ -170,4 +474,477 @@ impl ObstacleGenerator {
     pub fn objects(&self) -> &[H3mObject] {
         &self.objects_data.objects
     }
+
+    pub fn covered_cells(&self, map_size: usize, underground: bool) -> Vec<bool> {
+        let mut covered_cells = vec![false; map_size * map_size];
+        for object in self
+            .objects_data
+            .objects
+            .iter()
+            .filter(|object| object.underground() == underground)
+        {
+            let template = &self.objects_data.templates[object.template_idx() as usize];
+            for index in object_cells(object, template, map_size) {
+                covered_cells[index] = true;
+            }
+        }
+        covered_cells
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::h3m::symmetry::Transform;
+    use crate::h3m::terrain_map::TileVariants;
+    use crate::h3m::{Obstacle, ObstacleCategory, Surface, Terrain, MAX_MAP_SIZE};
+
+    fn default_object_templates() -> DefaultObjectTemplates {
+        let object_template = |filename: &str| H3mObjectTemplate {
+            filename: filename.to_string(),
+            shape_mask: [0xFF; 6],
+            visit_mask: [0; 6],
+            surface_type_mask: 0,
+            surface_editor_group_mask: 0,
+            class: 0,
+            subclass: 0,
+            group: 0,
+            is_overlay: false,
+        };
+        [
+            object_template("AVWmrnd0.def"),
+            object_template("AVLholg0.def"),
+        ]
+    }
+
+    fn grass_terrain_map(size: usize) -> TerrainMap {
+        let surfaces = vec![
+            Some(Surface {
+                terrain: Terrain::Grass,
+                obstacle: Some(Obstacle::new(ObstacleCategory::Any)),
+            });
+            size * size
+        ];
+        TerrainMap::generate(size, false, &TileVariants::default(), false, 0, &surfaces).unwrap()
+    }
+
+    // The templates but the default ones are used by the objects,
+    // the obstacle templates point to their own templates.
+    fn assert_templates_used(obstacle_generator: &ObstacleGenerator) {
+        let templates = obstacle_generator.object_templates();
+        for template_index in DEFAULT_OBJECT_TEMPLATES_COUNT..templates.len() {
+            assert!(obstacle_generator
+                .objects()
+                .iter()
+                .any(|object| object.template_idx() as usize == template_index));
+        }
+        for obstacle in obstacle_generator.obstacle_template_list.iter() {
+            let template_index = obstacle.h3m_template_index() as usize;
+            if template_index != 0 {
+                assert_eq!(templates[template_index].filename, obstacle.filename());
+            }
+        }
+    }
+
+    fn generate_with_threads(
+        terrain_map: &TerrainMap,
+        seed: u64,
+        threads: usize,
+        symmetry: Option<Symmetry>,
+    ) -> Vec<H3mObject> {
+        let thread_pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        let mut obstacle_generator = ObstacleGenerator::new(&default_object_templates(), seed);
+        thread_pool
+            .install(|| obstacle_generator.generate(terrain_map, None, symmetry, true))
+            .unwrap();
+        obstacle_generator.objects_data.objects
+    }
+
+    #[test]
+    fn same_seed_same_obstacles_test() {
+        // 4x4 areas, so every group has several areas generated in parallel
+        let size = 144;
+        let area_groups = obstacle_map::make_area_groups(size, AREA_SIDE, AREA_SIDE);
+        assert!(area_groups.iter().all(|area_group| area_group.len() > 1));
+        let terrain_map = grass_terrain_map(size);
+
+        let objects = generate_with_threads(&terrain_map, 42, 1, None);
+        assert!(!objects.is_empty());
+        assert_eq!(objects, generate_with_threads(&terrain_map, 42, 8, None));
+        assert_ne!(objects, generate_with_threads(&terrain_map, 43, 8, None));
+
+        // the images of an area may lie next to the other areas of its group
+        let terrain_map = grass_terrain_map(108);
+        let symmetry = Some(Symmetry::Rotate90);
+        let objects = generate_with_threads(&terrain_map, 42, 1, symmetry);
+        assert_eq!(
+            objects,
+            generate_with_threads(&terrain_map, 42, 8, symmetry)
+        );
+    }
+
+    #[test]
+    fn candidate_sets_keep_placements_test() {
+        // bands of terrains and water make many cells invalid for the templates
+        let size = 72;
+        let terrains = [Terrain::Grass, Terrain::Sand, Terrain::Water, Terrain::Dirt];
+        let surfaces: Vec<Option<Surface>> = (0..size * size)
+            .map(|index| {
+                let terrain = terrains[(index % size / 9) % terrains.len()];
+                Some(if terrain.is_ground() {
+                    Surface::with_obstacle(terrain, Obstacle::new(ObstacleCategory::Any))
+                } else {
+                    Surface::new(terrain)
+                })
+            })
+            .collect();
+        let terrain_map =
+            TerrainMap::generate(size, false, &TileVariants::default(), false, 0, &surfaces)
+                .unwrap();
+
+        let generate = |full_scan| {
+            let mut obstacle_generator = ObstacleGenerator::new(&default_object_templates(), 7);
+            obstacle_generator.full_scan = full_scan;
+            obstacle_generator
+                .generate(&terrain_map, None, None, false)
+                .unwrap();
+            obstacle_generator.objects_data.objects
+        };
+        let objects = generate(false);
+        assert!(!objects.is_empty());
+        assert_eq!(objects, generate(true));
+    }
+
+    // Run with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn full_map_with_underground_generation_bench() {
+        let land_terrain_map = grass_terrain_map(MAX_MAP_SIZE);
+        let underground_surfaces = vec![
+            Some(Surface::with_obstacle(
+                Terrain::Subterranean,
+                Obstacle::new(ObstacleCategory::Any)
+            ));
+            MAX_MAP_SIZE * MAX_MAP_SIZE
+        ];
+        let underground_terrain_map = TerrainMap::generate(
+            MAX_MAP_SIZE,
+            false,
+            &TileVariants::default(),
+            true,
+            0,
+            &underground_surfaces,
+        )
+        .unwrap();
+
+        let measure = |full_scan| {
+            let start = std::time::Instant::now();
+            let mut obstacle_generator = ObstacleGenerator::new(&default_object_templates(), 0);
+            obstacle_generator.full_scan = full_scan;
+            for terrain_map in [&land_terrain_map, &underground_terrain_map] {
+                obstacle_generator
+                    .generate(terrain_map, None, None, false)
+                    .unwrap();
+            }
+            start.elapsed()
+        };
+        let candidate_sets_duration = measure(false);
+        let full_scan_duration = measure(true);
+
+        println!(
+            "{0}x{0} map with underground: candidate sets {1:?}, full scan {2:?}",
+            MAX_MAP_SIZE, candidate_sets_duration, full_scan_duration
+        );
+    }
+
+    // Run with `cargo test --release -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn parallel_generation_speedup_bench() {
+        let terrain_map = grass_terrain_map(MAX_MAP_SIZE);
+        let threads = rayon::current_num_threads();
+
+        let measure = |threads| {
+            let start = std::time::Instant::now();
+            generate_with_threads(&terrain_map, 0, threads, None);
+            start.elapsed()
+        };
+        let sequential_duration = measure(1);
+        let parallel_duration = measure(threads);
+
+        println!(
+            "{0}x{0} map: 1 thread {1:?}, {2} threads {3:?}, speedup {4:.2}",
+            MAX_MAP_SIZE,
+            sequential_duration,
+            threads,
+            parallel_duration,
+            sequential_duration.as_secs_f64() / parallel_duration.as_secs_f64()
+        );
+    }
+
+    #[test]
+    fn symmetric_obstacles_test() {
+        let size = 72;
+        let terrain_map = grass_terrain_map(size);
+        for symmetry in [
+            Symmetry::MirrorHorizontal,
+            Symmetry::MirrorVertical,
+            Symmetry::MirrorDiagonal,
+            Symmetry::Rotate180,
+            Symmetry::MirrorBoth,
+            Symmetry::Rotate90,
+        ] {
+            let mut obstacle_generator = ObstacleGenerator::new(&default_object_templates(), 0);
+            obstacle_generator
+                .generate(&terrain_map, None, Some(symmetry), true)
+                .unwrap();
+
+            let templates = obstacle_generator.object_templates();
+            let objects = obstacle_generator.objects();
+            // the images may have other templates of the same footprint
+            let footprint = |object: &H3mObject, transform: Option<Transform>| {
+                let template = &templates[object.template_idx() as usize];
+                let mut cells: Vec<usize> = object_cells(object, template, size)
+                    .map(|index| match transform {
+                        Some(transform) => transform
+                            .transform_position(size, Position::from_index(size, index))
+                            .index(size),
+                        None => index,
+                    })
+                    .collect();
+                cells.sort_unstable();
+                cells
+            };
+            let footprints: Vec<_> = objects
+                .iter()
+                .map(|object| footprint(object, None))
+                .collect();
+
+            assert!(!objects.is_empty());
+            for object in objects {
+                for transform in symmetry.transforms() {
+                    assert!(footprints.contains(&footprint(object, Some(transform))));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn underground_obstacles_test() {
+        let size = 36;
+        // forest on the left half, any obstacles on the right one
+        let surfaces: Vec<Option<Surface>> = (0..size * size)
+            .map(|index| {
+                let category = if index % size < size / 2 {
+                    ObstacleCategory::Forest
+                } else {
+                    ObstacleCategory::Any
+                };
+                Some(Surface::with_obstacle(
+                    Terrain::Grass,
+                    Obstacle::new(category),
+                ))
+            })
+            .collect();
+        let underground = true;
+        let terrain_map = TerrainMap::generate(
+            size,
+            false,
+            &TileVariants::default(),
+            underground,
+            0,
+            &surfaces,
+        )
+        .unwrap();
+
+        let mut obstacle_generator = ObstacleGenerator::new(&default_object_templates(), 0);
+        obstacle_generator
+            .generate(&terrain_map, None, None, false)
+            .unwrap();
+
+        let templates = obstacle_generator.object_templates();
+        let is_surface_only = |template: &H3mObjectTemplate| {
+            obstacle_generator
+                .obstacle_template_list
+                .iter()
+                .any(|obstacle| {
+                    obstacle.filename() == template.filename && obstacle.is_surface_only()
+                })
+        };
+        let surface_only_objects: Vec<&H3mObject> = obstacle_generator
+            .objects()
+            .iter()
+            .filter(|object| is_surface_only(&templates[object.template_idx() as usize]))
+            .collect();
+
+        // no vegetation grows underground outside the forest
+        let (forest_cells, other_cells): (Vec<usize>, Vec<usize>) = surface_only_objects
+            .iter()
+            .flat_map(|object| {
+                object_cells(object, &templates[object.template_idx() as usize], size)
+            })
+            .partition(|index| index % size < size / 2);
+        assert!(!forest_cells.is_empty());
+        assert!(other_cells.is_empty());
+    }
+
+    #[test]
+    fn fix_cut_off_areas_test() {
+        let size = 16;
+        let surfaces: Vec<Option<Surface>> = (0..size * size)
+            .map(|index| {
+                let column = index % size;
+                Some(Surface {
+                    terrain: Terrain::Grass,
+                    obstacle: if (4..12).contains(&column) {
+                        Some(Obstacle::optional(ObstacleCategory::Any))
+                    } else {
+                        None
+                    },
+                })
+            })
+            .collect();
+
+        let terrain_map =
+            TerrainMap::generate(size, false, &TileVariants::default(), false, 0, &surfaces)
+                .unwrap();
+
+        let mut obstacle_generator = ObstacleGenerator::new(&default_object_templates(), 0);
+        let report = obstacle_generator
+            .generate(&terrain_map, Some(PassabilityCheck::Fix), None, true)
+            .unwrap();
+
+        assert!(report.cut_off_areas().is_empty());
+        assert!(report.removed_obstacles_count() > 0);
+        assert!(report.coverage().ratio() < 1.0);
+        assert_templates_used(&obstacle_generator);
+    }
+
+    #[test]
+    fn remove_objects_removes_unused_templates_test() {
+        let size = 36;
+        let terrain_map = grass_terrain_map(size);
+        let mut obstacle_generator = ObstacleGenerator::new(&default_object_templates(), 0);
+        obstacle_generator
+            .generate(&terrain_map, None, None, true)
+            .unwrap();
+        let templates_count = obstacle_generator.object_templates().len();
+
+        let region: Vec<bool> = (0..size * size).map(|index| index % size < 18).collect();
+        let removed_count = obstacle_generator
+            .remove_objects_inside(size, false, &region)
+            .unwrap();
+        assert!(removed_count > 0);
+        assert!(obstacle_generator.object_templates().len() < templates_count);
+        assert_templates_used(&obstacle_generator);
+
+        let removed_count = obstacle_generator
+            .remove_objects_covering(size, false, &vec![true; size * size])
+            .unwrap();
+        assert!(removed_count > 0);
+        assert!(obstacle_generator.objects().is_empty());
+        assert_eq!(
+            obstacle_generator.object_templates().len(),
+            DEFAULT_OBJECT_TEMPLATES_COUNT
+        );
+        assert_templates_used(&obstacle_generator);
+
+        obstacle_generator
+            .generate(&terrain_map, None, None, true)
+            .unwrap();
+        assert!(!obstacle_generator.objects().is_empty());
+        assert_templates_used(&obstacle_generator);
+    }
+
+    #[test]
+    fn uncovered_cells_test() {
+        let size = 16;
+        // no mountains fit into one cell
+        let surfaces: Vec<Option<Surface>> = (0..size * size)
+            .map(|index| {
+                Some(Surface {
+                    terrain: Terrain::Grass,
+                    obstacle: (index == 8 * size + 8)
+                        .then(|| Obstacle::new(ObstacleCategory::Mountains)),
+                })
+            })
+            .collect();
+        let terrain_map =
+            TerrainMap::generate(size, false, &TileVariants::default(), false, 0, &surfaces)
+                .unwrap();
+
+        let mut obstacle_generator = ObstacleGenerator::new(&default_object_templates(), 0);
+        let report = obstacle_generator
+            .generate(&terrain_map, None, None, false)
+            .unwrap();
+        assert_eq!(report.uncovered_cells(), [(8, 8)]);
+        assert_eq!(report.coverage().ratio(), 0.0);
+        assert!(obstacle_generator.objects().is_empty());
+
+        let mut obstacle_generator = ObstacleGenerator::new(&default_object_templates(), 0);
+        assert!(matches!(
+            obstacle_generator.generate(&terrain_map, None, None, true),
+            Err(H3mError::Parameter(_))
+        ));
+    }
+
+    #[test]
+    fn fallback_category_test() {
+        let size = 16;
+        // there are no lakes for the sand
+        let surfaces = vec![
+            Some(Surface::with_obstacle(
+                Terrain::Sand,
+                Obstacle::optional(ObstacleCategory::Lakes),
+            ));
+            size * size
+        ];
+        let terrain_map =
+            TerrainMap::generate(size, false, &TileVariants::default(), false, 0, &surfaces)
+                .unwrap();
+
+        let mut obstacle_generator = ObstacleGenerator::new(&default_object_templates(), 0);
+        let report = obstacle_generator
+            .generate(&terrain_map, None, None, false)
+            .unwrap();
+        assert_eq!(
+            report.fallback_categories(),
+            [(Terrain::Sand, ObstacleCategory::Lakes)]
+        );
+        assert!(!obstacle_generator.objects().is_empty());
+    }
+
+    #[test]
+    fn optional_cells_passes_test() {
+        // the mountains don't fit into many cells without the sparsity penalties
+        let size = 72;
+        let terrains = [Terrain::Grass, Terrain::Sand, Terrain::Water, Terrain::Dirt];
+        let generate = |obstacle: Obstacle| {
+            let surfaces: Vec<Option<Surface>> = (0..size * size)
+                .map(|index| {
+                    let terrain = terrains[(index % size / 9) % terrains.len()];
+                    Some(if terrain.is_ground() {
+                        Surface::with_obstacle(terrain, obstacle)
+                    } else {
+                        Surface::new(terrain)
+                    })
+                })
+                .collect();
+            let terrain_map =
+                TerrainMap::generate(size, false, &TileVariants::default(), false, 0, &surfaces)
+                    .unwrap();
+            let mut obstacle_generator = ObstacleGenerator::new(&default_object_templates(), 0);
+            let report = obstacle_generator
+                .generate(&terrain_map, None, None, false)
+                .unwrap();
+            report.coverage().covered_cells_count()
+        };
+        assert_eq!(
+            generate(Obstacle::optional(ObstacleCategory::Mountains)),
+            generate(Obstacle::new(ObstacleCategory::Mountains))
+        );
+    }
 }
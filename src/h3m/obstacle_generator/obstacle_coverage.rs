@@ -0,0 +1,112 @@
+use std::fmt;
+
+/// Share of the obstacle cells covered by the generated obstacles.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ObstacleCoverage {
+    mandatory_cells: usize,
+    covered_mandatory_cells: usize,
+    optional_cells: usize,
+    covered_optional_cells: usize,
+}
+
+impl ObstacleCoverage {
+    pub(crate) fn add_cell(&mut self, optional: bool, covered: bool) {
+        let (cells, covered_cells) = if optional {
+            (&mut self.optional_cells, &mut self.covered_optional_cells)
+        } else {
+            (&mut self.mandatory_cells, &mut self.covered_mandatory_cells)
+        };
+
+        *cells += 1;
+        if covered {
+            *covered_cells += 1;
+        }
+    }
+
+    /// Adds the cells of another coverage, e.g. of the other level.
+    pub fn merge(&mut self, other: &ObstacleCoverage) {
+        self.mandatory_cells += other.mandatory_cells;
+        self.covered_mandatory_cells += other.covered_mandatory_cells;
+        self.optional_cells += other.optional_cells;
+        self.covered_optional_cells += other.covered_optional_cells;
+    }
+
+    /// Number of cells marked to have obstacles, mandatory and optional.
+    pub fn cells_count(&self) -> usize {
+        self.mandatory_cells + self.optional_cells
+    }
+
+    /// Number of the obstacle cells covered by obstacles.
+    pub fn covered_cells_count(&self) -> usize {
+        self.covered_mandatory_cells + self.covered_optional_cells
+    }
+
+    /// Covered part of all obstacle cells, from 0 to 1.
+    pub fn ratio(&self) -> f64 {
+        ratio(self.covered_cells_count(), self.cells_count())
+    }
+
+    /// Covered part of the optional obstacle cells, from 0 to 1.
+    pub fn optional_ratio(&self) -> f64 {
+        ratio(self.covered_optional_cells, self.optional_cells)
+    }
+}
+
+fn ratio(covered: usize, total: usize) -> f64 {
+    if total == 0 {
+        1.0
+    } else {
+        covered as f64 / total as f64
+    }
+}
+
+impl fmt::Display for ObstacleCoverage {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "obstacle coverage {:.1}% ({} of {} cells)",
+            self.ratio() * 100.0,
+            self.covered_cells_count(),
+            self.cells_count()
+        )?;
+
+        if self.optional_cells > 0 {
+            write!(
+                fmt,
+                ", optional cells coverage {:.1}% ({} of {} cells)",
+                self.optional_ratio() * 100.0,
+                self.covered_optional_cells,
+                self.optional_cells
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coverage_ratio_test() {
+        let mut coverage = ObstacleCoverage::default();
+        assert_eq!(coverage.ratio(), 1.0);
+
+        coverage.add_cell(false, true);
+        coverage.add_cell(false, true);
+        coverage.add_cell(true, true);
+        coverage.add_cell(true, false);
+
+        assert_eq!(coverage.cells_count(), 4);
+        assert_eq!(coverage.covered_cells_count(), 3);
+        assert_eq!(coverage.ratio(), 0.75);
+        assert_eq!(coverage.optional_ratio(), 0.5);
+
+        let mut total = ObstacleCoverage::default();
+        total.merge(&coverage);
+        total.merge(&coverage);
+        assert_eq!(total.cells_count(), 8);
+        assert_eq!(total.covered_optional_cells, 2);
+    }
+}
@@ -0,0 +1,73 @@
+// Set of the area cells, which still may become the obstacle position.
+#[derive(Clone)]
+pub struct CandidateSet {
+    words: Vec<u64>,
+}
+
+pub enum CandidateCheck {
+    Valid,
+    Postponed, // may become valid later, e.g. with a lower sparsity
+    Invalid,   // never becomes valid again: the cell is removed from the set
+}
+
+impl CandidateSet {
+    pub fn full(len: usize) -> CandidateSet {
+        let mut words = vec![u64::MAX; len.div_ceil(64)];
+        if len % 64 != 0 {
+            *words.last_mut().unwrap() = (1 << (len % 64)) - 1;
+        }
+        CandidateSet { words }
+    }
+
+    // Returns the last cell passing the check, dropping the invalid cells on the way.
+    pub fn find_last<F>(&mut self, mut check: F) -> Option<usize>
+    where
+        F: FnMut(usize) -> CandidateCheck,
+    {
+        for word_index in (0..self.words.len()).rev() {
+            let mut word = self.words[word_index];
+            while word != 0 {
+                let bit = 63 - word.leading_zeros() as usize;
+                word &= !(1 << bit);
+
+                match check(word_index * 64 + bit) {
+                    CandidateCheck::Valid => return Some(word_index * 64 + bit),
+                    CandidateCheck::Postponed => (),
+                    CandidateCheck::Invalid => self.words[word_index] &= !(1 << bit),
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_last_test() {
+        let mut candidate_set = CandidateSet::full(70);
+
+        let mut checked = Vec::new();
+        let found = candidate_set.find_last(|index| {
+            checked.push(index);
+            match index {
+                69 | 64 | 63 => CandidateCheck::Invalid,
+                65..=68 => CandidateCheck::Postponed,
+                _ => CandidateCheck::Valid,
+            }
+        });
+        assert_eq!(found, Some(62));
+        assert_eq!(checked, [69, 68, 67, 66, 65, 64, 63, 62]);
+
+        checked.clear();
+        let found = candidate_set.find_last(|index| {
+            checked.push(index);
+            CandidateCheck::Postponed
+        });
+        assert_eq!(found, None);
+        assert_eq!(checked.len(), 67);
+        assert_eq!(checked[..4], [68, 67, 66, 65]);
+    }
+}
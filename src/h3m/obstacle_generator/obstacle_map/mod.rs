@@ -1,18 +1,29 @@
 use super::obstacle_template::{CellValidationResult, ObstacleTemplate};
-use super::FilenameToTemplateIndexMap;
+use super::obstacle_template_list::ObstacleTemplateList;
+use super::symmetric_templates::SymmetricTemplates;
+use super::{FilenameToTemplateIndexMap, ObstacleCoverage};
 use crate::common::position::generic::{DeltaPos, Position, SignedDeltaPos};
+use crate::common::rng::SeededRng;
 use crate::h3m::result::*;
+use crate::h3m::symmetry::{Symmetry, SymmetryRegion};
 use crate::h3m::terrain_map::TerrainMap;
+use crate::h3m::{ObstacleCategory, Terrain};
+use candidate_set::CandidateCheck;
 pub use located_obstacle::LocatedObstacle;
 pub use obstacle_map_area::*;
 pub use obstacle_map_cell::{NeighborhoodSameRelation, ObstacleMapCell};
-use rand::{rngs::ThreadRng, Rng};
+pub use passability::CutOffArea;
+use passability::PlacedObstacle;
+use rand::Rng;
 use sparsity_validator::SparsityValidator;
+use std::collections::HashMap;
 
 mod areas_layout;
+mod candidate_set;
 mod located_obstacle;
 mod obstacle_map_area;
 mod obstacle_map_cell;
+mod passability;
 mod sparsity_validator;
 
 fn max_sparsity(obstacle: &ObstacleTemplate) -> usize {
@@ -74,19 +85,22 @@ impl ObstacleMapCell {
                 neighbour_same_relation_getter(1, 0),
                 neighbour_same_relation_getter(1, 1),
             ],
+            terrain_map.underground(),
         ))
     }
 }
 
+#[derive(Clone)]
 pub struct ObstacleMap {
     size: usize,
     cells: Vec<ObstacleMapCell>,
     sparsity_penalty: usize,
     sparsity_validator: SparsityValidator,
+    placed_obstacles: Vec<PlacedObstacle>,
 }
 
 impl ObstacleMap {
-    pub fn new(terrain_map: &TerrainMap) -> H3mResult<ObstacleMap> {
+    pub fn new(terrain_map: &TerrainMap, symmetry: Option<Symmetry>) -> H3mResult<ObstacleMap> {
         let size = terrain_map.size();
         let map_len = size * size;
         let cells_len = terrain_map.cells().len();
@@ -101,7 +115,14 @@ impl ObstacleMap {
         let cells = {
             let mut cells = Vec::new();
             for index in 0..map_len {
-                cells.push(ObstacleMapCell::from_map_cell_index(index, terrain_map)?);
+                let mut cell = ObstacleMapCell::from_map_cell_index(index, terrain_map)?;
+                // Obstacles on the axis cells would have to be their own images.
+                if symmetry
+                    .is_some_and(|symmetry| symmetry.region(size, index) != SymmetryRegion::Source)
+                {
+                    cell.set_reproduced();
+                }
+                cells.push(cell);
             }
             cells
         };
@@ -111,6 +132,7 @@ impl ObstacleMap {
             cells,
             sparsity_penalty: 0,
             sparsity_validator: SparsityValidator::new(size),
+            placed_obstacles: Vec::new(),
         })
     }
 
@@ -120,12 +142,14 @@ impl ObstacleMap {
 
     pub fn try_position_obstacle(
         &self,
-        area: &ObstacleMapArea,
+        area: &mut ObstacleMapArea,
         template_index: usize,
         filename_to_template_index_map: &FilenameToTemplateIndexMap,
-        obstacle: &ObstacleTemplate,
-        rng: &mut ThreadRng,
+        obstacle_template_list: &ObstacleTemplateList,
+        symmetric_templates: Option<&SymmetricTemplates>,
+        rng: &mut SeededRng,
     ) -> Option<usize> {
+        let obstacle = obstacle_template_list.template(template_index);
         struct LocalMultiSparsityEntry {
             sparsity: usize,
             neighbor_index: usize,
@@ -145,11 +169,7 @@ impl ObstacleMap {
             .collect();
 
         fn apply_sparsity_penalty(sparsity: usize, sparsity_penalty: usize) -> usize {
-            if sparsity >= sparsity_penalty {
-                sparsity - sparsity_penalty
-            } else {
-                0
-            }
+            sparsity.saturating_sub(sparsity_penalty)
         }
 
         let sparsity = if self.sparsity_penalty == 0 {
@@ -163,6 +183,9 @@ impl ObstacleMap {
             if let Some(delta_position) = delta_position {
                 let delta_position_index = delta_position.index(self.size);
                 let delta_cell = &self.cells[delta_position_index];
+                if delta_cell.is_reproduced() {
+                    return CellValidationResult::Invalid;
+                }
                 obstacle.validate_cell(delta_cell, position)
             } else {
                 CellValidationResult::Invalid
@@ -193,7 +216,13 @@ impl ObstacleMap {
             true
         };
 
-        let is_valid_index = |index| {
+        if !obstacle.is_valid_terrain(area.terrain_group()) {
+            return None;
+        }
+
+        // Obstacles are only added during generation, so a cell invalid for the shape
+        // stays invalid, while the sparsity varies from try to try.
+        let check_index = |index| {
             let position = Position::from_index(self.size, index);
             let mut is_overlapping = false;
 
@@ -201,31 +230,64 @@ impl ObstacleMap {
                 match validate_delta(&position, delta) {
                     CellValidationResult::Valid => (),
                     CellValidationResult::ValidWithOverlapping => is_overlapping = true,
-                    CellValidationResult::Invalid => return false,
+                    CellValidationResult::Invalid => return CandidateCheck::Invalid,
+                }
+            }
+
+            let is_surface_only_restricted = |delta: &DeltaPos<usize>| {
+                let delta_position = position.checked_sub_delta(delta).unwrap();
+                !self.cells[delta_position.index(self.size)].may_place_surface_only_obstacle()
+            };
+            if obstacle.is_surface_only() && obstacle.shape().iter().any(is_surface_only_restricted)
+            {
+                return CandidateCheck::Invalid;
+            }
+
+            if let Some(symmetric_templates) = symmetric_templates {
+                let image_placements = self.image_placements(
+                    index,
+                    template_index,
+                    obstacle_template_list,
+                    symmetric_templates,
+                );
+                if image_placements.is_none() {
+                    return CandidateCheck::Invalid;
                 }
             }
 
             for delta in obstacle.shape() {
                 let delta_position = position.checked_sub_delta(delta).unwrap();
                 if !is_valid_delta_sparsity(delta_position, is_overlapping) {
-                    return false;
+                    return CandidateCheck::Postponed;
                 }
                 if !is_valid_delta_multi_sparsity(delta_position) {
-                    return false;
+                    return CandidateCheck::Postponed;
                 }
             }
 
-            true
+            CandidateCheck::Valid
         };
 
-        area.indexes()
-            .iter()
-            .rev()
-            .find(|&&index| is_valid_index(index))
-            .copied()
+        area.find_last_candidate(template_index, check_index)
     }
 
     pub fn add_obstacle(
+        &mut self,
+        position_index: usize,
+        template_index: usize,
+        object_index: usize,
+        obstacle: &ObstacleTemplate,
+    ) {
+        self.placed_obstacles.push(PlacedObstacle::new(
+            position_index,
+            template_index,
+            object_index,
+        ));
+        self.place_obstacle(position_index, template_index, obstacle);
+    }
+
+    // Occupies the obstacle cells without registering the obstacle as a map object.
+    pub fn place_obstacle(
         &mut self,
         position_index: usize,
         template_index: usize,
@@ -245,16 +307,127 @@ impl ObstacleMap {
         }
     }
 
+    // Templates and position indexes of the images of the obstacle at the position index,
+    // the image templates are the first of the same footprint fitting the image cells;
+    // none if some image has no fitting template.
+    pub fn image_placements(
+        &self,
+        position_index: usize,
+        template_index: usize,
+        obstacle_template_list: &ObstacleTemplateList,
+        symmetric_templates: &SymmetricTemplates,
+    ) -> Option<Vec<(usize, usize)>> {
+        let symmetry = symmetric_templates.symmetry();
+        let position = Position::from_index(self.size, position_index);
+        let shape = obstacle_template_list.template(template_index).shape();
+
+        let fits = |image_obstacle: &ObstacleTemplate, image_position: Position<usize>| {
+            image_obstacle.shape().iter().all(|delta| {
+                let cell = &self.cells[image_position.sub_delta(delta).index(self.size)];
+                !matches!(
+                    image_obstacle.validate_cell(cell, &image_position),
+                    CellValidationResult::Invalid
+                )
+            })
+        };
+
+        symmetry
+            .transforms()
+            .into_iter()
+            .enumerate()
+            .map(|(transform_index, transform)| {
+                symmetric_templates
+                    .image_templates(template_index, transform_index)
+                    .iter()
+                    .find_map(|&image_template_index| {
+                        let image_obstacle = obstacle_template_list.template(image_template_index);
+                        let image_position = symmetry.image_position(
+                            self.size,
+                            transform,
+                            position,
+                            shape,
+                            image_obstacle.shape(),
+                        )?;
+                        fits(image_obstacle, image_position)
+                            .then(|| (image_template_index, image_position.index(self.size)))
+                    })
+            })
+            .collect()
+    }
+
     pub fn position(&self, index: usize) -> Position<u8> {
         self.cells[index].position()
     }
 
+    // The cells of the categories which have no templates for the cell terrain
+    // take any obstacles, returns these terrains and categories.
+    pub fn fall_back_to_any_category(
+        &mut self,
+        obstacle_template_list: &ObstacleTemplateList,
+    ) -> Vec<(Terrain, ObstacleCategory)> {
+        let mut has_templates = HashMap::new();
+        let mut fallback_categories = Vec::new();
+        for cell in self
+            .cells
+            .iter_mut()
+            .filter(|cell| cell.may_place_obstacle())
+        {
+            let (terrain, category) = match cell.map_cell() {
+                Some(map_cell) => (map_cell.surface().terrain, cell.obstacle_category()),
+                None => continue,
+            };
+            if *has_templates
+                .entry((terrain, category))
+                .or_insert_with(|| obstacle_template_list.has_category_templates(terrain, category))
+            {
+                continue;
+            }
+
+            cell.set_obstacle_category(ObstacleCategory::Any);
+            if !fallback_categories.contains(&(terrain, category)) {
+                fallback_categories.push((terrain, category));
+            }
+        }
+        fallback_categories
+    }
+
     pub fn generalized_terrain_group(&self) -> u16 {
         self.cells
             .iter()
             .fold(0, |result, cell| result | cell.terrain_group())
     }
 
+    pub fn may_place_surface_only_obstacles(&self) -> bool {
+        self.cells
+            .iter()
+            .any(|cell| cell.may_place_obstacle() && cell.may_place_surface_only_obstacle())
+    }
+
+    pub fn set_areas_terrain_group(&self, areas: &mut [ObstacleMapArea]) {
+        for area in areas {
+            let terrain_group = area.indexes().iter().fold(0, |result, &index| {
+                result | self.cells[index].terrain_group()
+            });
+            area.set_terrain_group(terrain_group);
+        }
+    }
+
+    pub fn coverage(&self) -> ObstacleCoverage {
+        let mut coverage = ObstacleCoverage::default();
+        for cell in self.cells.iter().filter(|cell| cell.may_place_obstacle()) {
+            coverage.add_cell(cell.optional_obstacle(), cell.is_covered());
+        }
+        coverage
+    }
+
+    // Mandatory obstacle cells, as (row, column), which are not covered.
+    pub fn uncovered_mandatory_cells(&self) -> Vec<(usize, usize)> {
+        (0..self.cells.len())
+            .filter(|&index| self.cells[index].need_place_obstacle())
+            .map(|index| (index / self.size, index % self.size))
+            .collect()
+    }
+
     pub fn first_position_to_place_obstacle(&self) -> Option<Position<usize>> {
         for (index, cell) in self.cells.iter().enumerate() {
             if cell.need_place_obstacle() {
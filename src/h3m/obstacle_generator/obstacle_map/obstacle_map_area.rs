@@ -1,16 +1,69 @@
 use super::areas_layout::AreasLayout;
+use super::candidate_set::{CandidateCheck, CandidateSet};
 use crate::common::position::Position;
 
 #[derive(Clone)]
-pub struct ObstacleMapArea(Vec<usize>);
+pub struct ObstacleMapArea {
+    indexes: Vec<usize>,
+    terrain_group: u16, // generalized terrain group of the area cells
+    candidates: Vec<Option<CandidateSet>>, // by template index
+    #[cfg(test)]
+    full_scan: bool, // check all the indexes, as before the candidate sets
+}
 
 impl ObstacleMapArea {
     fn new() -> ObstacleMapArea {
-        ObstacleMapArea(Vec::new())
+        ObstacleMapArea {
+            indexes: Vec::new(),
+            terrain_group: 0,
+            candidates: Vec::new(),
+            #[cfg(test)]
+            full_scan: false,
+        }
     }
 
     pub fn indexes(&self) -> &Vec<usize> {
-        &self.0
+        &self.indexes
+    }
+
+    pub fn terrain_group(&self) -> u16 {
+        self.terrain_group
+    }
+
+    pub fn set_terrain_group(&mut self, terrain_group: u16) {
+        self.terrain_group = terrain_group;
+    }
+
+    #[cfg(test)]
+    pub fn set_full_scan(&mut self, full_scan: bool) {
+        self.full_scan = full_scan;
+    }
+
+    // Returns the last area index passing the check. Indexes failed with
+    // CandidateCheck::Invalid are never checked again for the template.
+    pub fn find_last_candidate<F>(&mut self, template_index: usize, mut check: F) -> Option<usize>
+    where
+        F: FnMut(usize) -> CandidateCheck,
+    {
+        #[cfg(test)]
+        if self.full_scan {
+            return self
+                .indexes
+                .iter()
+                .rev()
+                .copied()
+                .find(|&index| matches!(check(index), CandidateCheck::Valid));
+        }
+
+        if self.candidates.len() <= template_index {
+            self.candidates.resize(template_index + 1, None);
+        }
+
+        let indexes = &self.indexes;
+        self.candidates[template_index]
+            .get_or_insert_with(|| CandidateSet::full(indexes.len()))
+            .find_last(|local_index| check(indexes[local_index]))
+            .map(|local_index| indexes[local_index])
     }
 }
 
@@ -21,11 +74,25 @@ pub fn make_areas(map_size: usize, area_width: usize, area_height: usize) -> Vec
     for cell_index in 0..(map_size * map_size) {
         let cell_position = Position::from_index(map_size, cell_index);
         let area_index = areas_layout.area_index(cell_position);
-        areas[area_index].0.push(cell_index);
+        areas[area_index].indexes.push(cell_index);
     }
     areas
 }
 
+// Splits the areas into four groups by the parity of the area row and column,
+// so that the areas of one group never touch each other.
+pub fn make_area_groups(map_size: usize, area_width: usize, area_height: usize) -> [Vec<usize>; 4] {
+    let areas_layout = AreasLayout::new(map_size, area_width, area_height);
+    let mut area_groups: [Vec<usize>; 4] = Default::default();
+
+    for area_index in 0..areas_layout.areas_count() {
+        let area_position = Position::from_index(areas_layout.areas_at_row(), area_index);
+        let group_index = (area_position.row() % 2) * 2 + area_position.column() % 2;
+        area_groups[group_index].push(area_index);
+    }
+    area_groups
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -35,11 +102,20 @@ mod tests {
         let areas = make_areas(5, 3, 2);
         assert_eq!(areas.len(), 6);
 
-        assert_eq!(areas[0].0, [0, 1, 2, 5, 6, 7]);
-        assert_eq!(areas[1].0, [3, 4, 8, 9]);
-        assert_eq!(areas[2].0, [10, 11, 12, 15, 16, 17]);
-        assert_eq!(areas[3].0, [13, 14, 18, 19]);
-        assert_eq!(areas[4].0, [20, 21, 22]);
-        assert_eq!(areas[5].0, [23, 24]);
+        assert_eq!(areas[0].indexes, [0, 1, 2, 5, 6, 7]);
+        assert_eq!(areas[1].indexes, [3, 4, 8, 9]);
+        assert_eq!(areas[2].indexes, [10, 11, 12, 15, 16, 17]);
+        assert_eq!(areas[3].indexes, [13, 14, 18, 19]);
+        assert_eq!(areas[4].indexes, [20, 21, 22]);
+        assert_eq!(areas[5].indexes, [23, 24]);
+    }
+
+    #[test]
+    fn make_area_groups_test() {
+        let area_groups = make_area_groups(7, 3, 3);
+        assert_eq!(area_groups[0], [0, 2, 6, 8]);
+        assert_eq!(area_groups[1], [1, 7]);
+        assert_eq!(area_groups[2], [3, 5]);
+        assert_eq!(area_groups[3], [4]);
     }
 }
@@ -3,7 +3,7 @@ use crate::common::position::generic::Position;
 use crate::h3m::obstacle_generator::obstacle_template::ObstacleTemplate;
 use crate::h3m::terrain_map::MapCell;
 use crate::h3m::terrain_map::TerrainVisibleType;
-use crate::h3m::Terrain;
+use crate::h3m::{ObstacleCategory, Terrain};
 
 pub type NeighborhoodSameRelation = [bool; 8];
 
@@ -12,8 +12,12 @@ pub struct ObstacleMapCell {
     position: Position<u8>,
     map_cell: Option<MapCell>,
     terrain_group: u16, // terrain editor group, 0 means no obstacles to place
+    obstacle_category: ObstacleCategory,
+    optional_obstacle: bool,
     located_obstacle: Option<LocatedObstacle>,
     neighborhood_same_relation: NeighborhoodSameRelation,
+    underground: bool,
+    reproduced: bool, // obstacles come to the cell from its symmetry source only
 }
 
 pub fn calc_terrain(map_cell: &MapCell) -> Terrain {
@@ -26,7 +30,7 @@ pub fn calc_terrain(map_cell: &MapCell) -> Terrain {
 
 fn calc_terrain_group(map_cell: &Option<MapCell>) -> u16 {
     if let Some(map_cell) = map_cell {
-        if map_cell.surface().obstacle {
+        if map_cell.surface().obstacle.is_some() {
             return calc_terrain(map_cell).group();
         }
     }
@@ -39,14 +43,20 @@ impl ObstacleMapCell {
         column: u8,
         map_cell: Option<MapCell>,
         neighborhood_same_relation: NeighborhoodSameRelation,
+        underground: bool,
     ) -> ObstacleMapCell {
         let terrain_group = calc_terrain_group(&map_cell);
+        let obstacle = map_cell.and_then(|map_cell| map_cell.surface().obstacle);
         ObstacleMapCell {
             position: Position::new(row, column),
             map_cell,
             terrain_group,
+            obstacle_category: obstacle.map_or(ObstacleCategory::Any, |o| o.category),
+            optional_obstacle: obstacle.is_some_and(|o| o.optional),
             located_obstacle: None,
             neighborhood_same_relation,
+            underground,
+            reproduced: false,
         }
     }
 
@@ -73,6 +83,19 @@ impl ObstacleMapCell {
         }
     }
 
+    pub fn remove_obstacle(&mut self, filename: &'static str, base_position: Position<usize>) {
+        if let Some(LocatedObstacle::Overlapping(ref mut vec)) = self.located_obstacle {
+            vec.retain(|overlapping_obstacle| {
+                (overlapping_obstacle.filename() != filename)
+                    || (*overlapping_obstacle.base_position() != base_position)
+            });
+            if !vec.is_empty() {
+                return;
+            }
+        }
+        self.located_obstacle = None;
+    }
+
     pub fn position(&self) -> Position<u8> {
         self.position
     }
@@ -85,6 +108,14 @@ impl ObstacleMapCell {
         self.terrain_group
     }
 
+    pub fn obstacle_category(&self) -> ObstacleCategory {
+        self.obstacle_category
+    }
+
+    pub fn set_obstacle_category(&mut self, obstacle_category: ObstacleCategory) {
+        self.obstacle_category = obstacle_category;
+    }
+
     pub fn located_obstacle(&self) -> &Option<LocatedObstacle> {
         &self.located_obstacle
     }
@@ -93,7 +124,35 @@ impl ObstacleMapCell {
         &self.neighborhood_same_relation
     }
 
+    pub fn may_place_obstacle(&self) -> bool {
+        self.terrain_group != 0
+    }
+
+    pub fn is_covered(&self) -> bool {
+        self.located_obstacle.is_some()
+    }
+
     pub fn need_place_obstacle(&self) -> bool {
-        (self.terrain_group != 0) && (self.located_obstacle.is_none())
+        self.may_place_obstacle()
+            && !self.optional_obstacle
+            && !self.reproduced
+            && !self.is_covered()
+    }
+
+    pub fn is_reproduced(&self) -> bool {
+        self.reproduced
+    }
+
+    pub fn set_reproduced(&mut self) {
+        self.reproduced = true;
+    }
+
+    // Trees, palms and the like grow underground only where a forest is asked for.
+    pub fn may_place_surface_only_obstacle(&self) -> bool {
+        !self.underground || self.obstacle_category == ObstacleCategory::Forest
+    }
+
+    pub fn optional_obstacle(&self) -> bool {
+        self.optional_obstacle
     }
 }
@@ -0,0 +1,281 @@
+use super::ObstacleMap;
+use crate::common::position::generic::{Position, SignedDeltaPos};
+use crate::h3m::obstacle_generator::obstacle_template_list::ObstacleTemplateList;
+use std::collections::{HashSet, VecDeque};
+
+#[derive(Clone, Copy)]
+pub struct PlacedObstacle {
+    position_index: usize,
+    template_index: usize,
+    object_index: usize,
+}
+
+impl PlacedObstacle {
+    pub fn new(position_index: usize, template_index: usize, object_index: usize) -> Self {
+        PlacedObstacle {
+            position_index,
+            template_index,
+            object_index,
+        }
+    }
+
+    pub fn object_index(&self) -> usize {
+        self.object_index
+    }
+}
+
+/// Passable area of the image separated by obstacles from the rest of its region.
+#[derive(Debug, Clone, Copy)]
+pub struct CutOffArea {
+    position: Position<usize>,
+    cells_count: usize,
+}
+
+impl CutOffArea {
+    /// Row of one of the area cells.
+    pub fn row(&self) -> usize {
+        self.position.row()
+    }
+
+    /// Column of one of the area cells.
+    pub fn column(&self) -> usize {
+        self.position.column()
+    }
+
+    /// Number of passable cells in the area.
+    pub fn cells_count(&self) -> usize {
+        self.cells_count
+    }
+}
+
+struct CutOffRegion {
+    cells: Vec<usize>,
+}
+
+// Labels 8-connected regions of passable cells, None for impassable cells.
+fn label_regions<F>(size: usize, is_passable: F) -> Vec<Option<usize>>
+where
+    F: Fn(usize) -> bool,
+{
+    let mut labels = vec![None; size * size];
+    let mut next_label = 0;
+
+    for start_index in 0..labels.len() {
+        if labels[start_index].is_some() || !is_passable(start_index) {
+            continue;
+        }
+
+        labels[start_index] = Some(next_label);
+        let mut queue = VecDeque::from([start_index]);
+
+        while let Some(index) = queue.pop_front() {
+            let position = Position::from_index(size, index);
+            for neighbour_index in neighbour_indexes(size, position) {
+                if labels[neighbour_index].is_none() && is_passable(neighbour_index) {
+                    labels[neighbour_index] = Some(next_label);
+                    queue.push_back(neighbour_index);
+                }
+            }
+        }
+
+        next_label += 1;
+    }
+
+    labels
+}
+
+fn neighbour_indexes(size: usize, position: Position<usize>) -> impl Iterator<Item = usize> {
+    [
+        (-1, -1),
+        (-1, 0),
+        (-1, 1),
+        (0, -1),
+        (0, 1),
+        (1, -1),
+        (1, 0),
+        (1, 1),
+    ]
+    .into_iter()
+    .filter_map(move |(delta_row, delta_column)| {
+        position
+            .checked_apply(size, size, &SignedDeltaPos::new(delta_row, delta_column))
+            .map(|neighbour| neighbour.index(size))
+    })
+}
+
+impl ObstacleMap {
+    fn blocked_cell_indexes(
+        &self,
+        placed_obstacle: &PlacedObstacle,
+        obstacle_template_list: &ObstacleTemplateList,
+    ) -> Vec<usize> {
+        let obstacle = obstacle_template_list.template(placed_obstacle.template_index);
+        let position = Position::from_index(self.size, placed_obstacle.position_index);
+        obstacle
+            .shape()
+            .iter()
+            .chain(obstacle.visit_shape())
+            .filter_map(|delta| position.checked_sub_delta(delta))
+            .map(|delta_position| delta_position.index(self.size))
+            .collect()
+    }
+
+    fn blocked_mask(&self, obstacle_template_list: &ObstacleTemplateList) -> Vec<bool> {
+        let mut blocked_mask = vec![false; self.cells.len()];
+        for placed_obstacle in &self.placed_obstacles {
+            for index in self.blocked_cell_indexes(placed_obstacle, obstacle_template_list) {
+                blocked_mask[index] = true;
+            }
+        }
+        blocked_mask
+    }
+
+    // Ground cells which the source image leaves open: free cells and optional obstacle cells.
+    fn is_passable_in_image(&self, index: usize) -> bool {
+        let cell = &self.cells[index];
+        match cell.map_cell() {
+            Some(map_cell) => {
+                map_cell.surface().terrain.is_ground()
+                    && (!cell.may_place_obstacle() || cell.optional_obstacle())
+            }
+            None => false,
+        }
+    }
+
+    fn cut_off_regions(&self, obstacle_template_list: &ObstacleTemplateList) -> Vec<CutOffRegion> {
+        let blocked_mask = self.blocked_mask(obstacle_template_list);
+
+        let image_labels = label_regions(self.size, |index| self.is_passable_in_image(index));
+        let labels = label_regions(self.size, |index| {
+            self.is_passable_in_image(index) && !blocked_mask[index]
+        });
+
+        let image_regions_count = image_labels.iter().flatten().max().map_or(0, |l| l + 1);
+        let regions_count = labels.iter().flatten().max().map_or(0, |l| l + 1);
+
+        let mut image_region_parts = vec![Vec::new(); image_regions_count];
+        let mut region_cells = vec![Vec::new(); regions_count];
+        for (index, (image_label, label)) in image_labels.iter().zip(&labels).enumerate() {
+            if let (Some(image_label), Some(label)) = (image_label, label) {
+                if !image_region_parts[*image_label].contains(label) {
+                    image_region_parts[*image_label].push(*label);
+                }
+                region_cells[*label].push(index);
+            }
+        }
+
+        let mut cut_off_regions = Vec::new();
+        for mut parts in image_region_parts
+            .into_iter()
+            .filter(|parts| parts.len() > 1)
+        {
+            parts.sort_by_key(|&label| std::cmp::Reverse(region_cells[label].len()));
+            for label in parts.into_iter().skip(1) {
+                cut_off_regions.push(CutOffRegion {
+                    cells: std::mem::take(&mut region_cells[label]),
+                });
+            }
+        }
+        cut_off_regions
+    }
+
+    pub fn cut_off_areas(&self, obstacle_template_list: &ObstacleTemplateList) -> Vec<CutOffArea> {
+        self.cut_off_regions(obstacle_template_list)
+            .into_iter()
+            .map(|region| CutOffArea {
+                position: Position::from_index(self.size, region.cells[0]),
+                cells_count: region.cells.len(),
+            })
+            .collect()
+    }
+
+    // Removes obstacles standing on optional cells around the cut off areas until
+    // the areas are reconnected or there is nothing left to remove.
+    // Returns the removed obstacles.
+    pub fn fix_cut_off_areas(
+        &mut self,
+        obstacle_template_list: &ObstacleTemplateList,
+    ) -> Vec<PlacedObstacle> {
+        let mut removed_obstacles = Vec::new();
+
+        loop {
+            let cut_off_regions = self.cut_off_regions(obstacle_template_list);
+            if cut_off_regions.is_empty() {
+                break;
+            }
+
+            let mut border = HashSet::new();
+            for region in &cut_off_regions {
+                for &index in &region.cells {
+                    border.extend(neighbour_indexes(
+                        self.size,
+                        Position::from_index(self.size, index),
+                    ));
+                }
+            }
+
+            let (removable, rest): (Vec<PlacedObstacle>, Vec<PlacedObstacle>) =
+                self.placed_obstacles.iter().partition(|placed_obstacle| {
+                    let blocked_cell_indexes =
+                        self.blocked_cell_indexes(placed_obstacle, obstacle_template_list);
+                    blocked_cell_indexes
+                        .iter()
+                        .all(|&index| self.cells[index].optional_obstacle())
+                        && blocked_cell_indexes
+                            .iter()
+                            .any(|index| border.contains(index))
+                });
+
+            if removable.is_empty() {
+                break;
+            }
+
+            self.placed_obstacles = rest;
+            for placed_obstacle in removable {
+                self.remove_obstacle(&placed_obstacle, obstacle_template_list);
+                removed_obstacles.push(placed_obstacle);
+            }
+        }
+
+        removed_obstacles
+    }
+
+    fn remove_obstacle(
+        &mut self,
+        placed_obstacle: &PlacedObstacle,
+        obstacle_template_list: &ObstacleTemplateList,
+    ) {
+        let obstacle = obstacle_template_list.template(placed_obstacle.template_index);
+        let position = Position::from_index(self.size, placed_obstacle.position_index);
+        for delta in obstacle.shape() {
+            let delta_position_index = position.sub_delta(delta).index(self.size);
+            self.cells[delta_position_index].remove_obstacle(obstacle.filename(), position);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn label_regions_test() {
+        #[rustfmt::skip]
+        let passable = [
+            true,  true,  false, false,
+            false, false, false, true,
+            false, false, true,  false,
+            true,  false, false, false,
+        ];
+
+        let labels = label_regions(4, |index| passable[index]);
+
+        #[rustfmt::skip]
+        assert_eq!(labels, [
+            Some(0), Some(0), None,    None,
+            None,    None,    None,    Some(1),
+            None,    None,    Some(1), None,
+            Some(2), None,    None,    None,
+        ]);
+    }
+}
@@ -3,7 +3,7 @@ use crate::common::position::generic::{Position, SignedDeltaPos};
 use std::collections::HashMap;
 
 fn delta_square(a: usize, b: usize) -> usize {
-    (if a > b { a - b } else { b - a }).pow(2)
+    a.abs_diff(b).pow(2)
 }
 
 fn distance_square(a: &Position<usize>, b: &Position<usize>) -> usize {
@@ -23,6 +23,7 @@ impl Area {
     }
 }
 
+#[derive(Clone)]
 struct Areas {
     layout: SquareAreasLayout,
     data: Vec<Area>,
@@ -43,6 +44,7 @@ impl Areas {
     }
 }
 
+#[derive(Clone)]
 pub struct SparsityValidator {
     map_size: usize,
     data: HashMap<usize, Areas>,
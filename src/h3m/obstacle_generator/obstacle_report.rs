@@ -0,0 +1,55 @@
+use super::obstacle_map::CutOffArea;
+use super::ObstacleCoverage;
+use crate::h3m::{ObstacleCategory, Terrain};
+
+/// Result of the obstacle generation on one map level.
+pub struct ObstacleReport {
+    coverage: ObstacleCoverage,
+    uncovered_cells: Vec<(usize, usize)>,
+    fallback_categories: Vec<(Terrain, ObstacleCategory)>,
+    cut_off_areas: Vec<CutOffArea>,
+    removed_obstacles_count: usize,
+}
+
+impl ObstacleReport {
+    pub(crate) fn new(
+        coverage: ObstacleCoverage,
+        uncovered_cells: Vec<(usize, usize)>,
+        fallback_categories: Vec<(Terrain, ObstacleCategory)>,
+        cut_off_areas: Vec<CutOffArea>,
+        removed_obstacles_count: usize,
+    ) -> ObstacleReport {
+        ObstacleReport {
+            coverage,
+            uncovered_cells,
+            fallback_categories,
+            cut_off_areas,
+            removed_obstacles_count,
+        }
+    }
+
+    pub fn coverage(&self) -> &ObstacleCoverage {
+        &self.coverage
+    }
+
+    /// Mandatory obstacle cells, as (row, column), which no obstacle fits into.
+    pub fn uncovered_cells(&self) -> &[(usize, usize)] {
+        &self.uncovered_cells
+    }
+
+    /// Obstacle categories which have no obstacles for the terrain,
+    /// the cells of them took any obstacles instead.
+    pub fn fallback_categories(&self) -> &[(Terrain, ObstacleCategory)] {
+        &self.fallback_categories
+    }
+
+    /// Areas cut off by obstacles, filled only with a passability check.
+    pub fn cut_off_areas(&self) -> &[CutOffArea] {
+        &self.cut_off_areas
+    }
+
+    /// Number of obstacles removed by `PassabilityCheck::Fix`.
+    pub fn removed_obstacles_count(&self) -> usize {
+        self.removed_obstacles_count
+    }
+}
@@ -76,6 +76,10 @@ impl ObstacleTemplate {
             return false;
         }
 
+        if !self.is_valid_category(obstacle_map_cell.obstacle_category()) {
+            return false;
+        }
+
         match obstacle_map_cell.located_obstacle() {
             Some(LocatedObstacle::Common) => return false,
             Some(LocatedObstacle::Overlapping(ref vec)) => {
@@ -125,13 +129,8 @@ impl ObstacleTemplate {
                 self.is_valid_mountain_mixed_tile(map_cell, nsr)
             }
             TemplateClass::Rock => self.is_valid_rock_mixed_tile(map_cell, nsr),
-            TemplateClass::Cactus => {
-                if self.filename() == "AVLwct08.def" {
-                    tile.mixed_only_with(Terrain::Sand)
-                        || same_side(nsr, &[Side::Right, Side::Left])
-                } else {
-                    true
-                }
+            TemplateClass::Cactus if self.filename() == "AVLwct08.def" => {
+                tile.mixed_only_with(Terrain::Sand) || same_side(nsr, &[Side::Right, Side::Left])
             }
             _ => true,
         } {
@@ -171,11 +170,7 @@ impl ObstacleTemplate {
                 _ => (),
             },
 
-            Terrain::Grass => {
-                if self.filename() == "grsmnt02.def" {
-                    return false;
-                }
-            }
+            Terrain::Grass if self.filename() == "grsmnt02.def" => return false,
 
             Terrain::Snow => match self.filename() {
                 "AVLmtsn2.def" => return same_side(nsr, &[Side::Right, Side::Bottom]),
@@ -183,11 +178,7 @@ impl ObstacleTemplate {
                 _ => (),
             },
 
-            Terrain::Swamp => {
-                if self.filename() == "AVLmtsw3.def" {
-                    return false;
-                }
-            }
+            Terrain::Swamp if self.filename() == "AVLmtsw3.def" => return false,
 
             Terrain::Rough => match self.filename() {
                 "avlmtrf4.def" => {
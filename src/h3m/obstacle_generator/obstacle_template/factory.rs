@@ -54,6 +54,7 @@ impl ObstacleTemplate {
             may_located_on_mixed_tiles(template_class, create_params.filename);
 
         let shape = make_shape(&create_params.shape_mask);
+        let visit_shape = make_visit_shape(&create_params.visit_mask);
 
         let frequency = frequency(
             template_class,
@@ -79,6 +80,7 @@ impl ObstacleTemplate {
             template_class,
             h3m_template_index: 0,
             shape,
+            visit_shape,
             terrain_group_mask,
             frequency,
             may_located_on_mixed_tiles,
@@ -91,7 +93,7 @@ impl ObstacleTemplate {
     }
 }
 
-fn make_shape(mask: &Mask) -> Vec<DeltaPos> {
+pub fn make_shape(mask: &Mask) -> Vec<DeltaPos> {
     let mut shape = Vec::new();
     for (row, byte) in mask.iter().rev().enumerate() {
         for column in 0..7usize {
@@ -104,6 +106,19 @@ fn make_shape(mask: &Mask) -> Vec<DeltaPos> {
     shape
 }
 
+fn make_visit_shape(mask: &Mask) -> Vec<DeltaPos> {
+    let mut visit_shape = Vec::new();
+    for (row, byte) in mask.iter().rev().enumerate() {
+        for column in 0..7usize {
+            let bit_mask = (1 << (7 - column)) as u8;
+            if byte & bit_mask != 0 {
+                visit_shape.push(DeltaPos::new(row, column));
+            }
+        }
+    }
+    visit_shape
+}
+
 fn template_class(class: u32, subclass: u32, filename: &'static str) -> TemplateClass {
     TemplateClass::from_code(class, subclass)
         .unwrap_or_else(|| panic!("Сouldn't define a class for the template '{:?}'", filename))
@@ -1,8 +1,9 @@
 use crate::common::position::DeltaPos;
 use crate::h3m::parser::H3mObjectTemplate;
 use crate::h3m::result::H3mResult;
+use crate::h3m::ObstacleCategory;
 pub use cell_validator::CellValidationResult;
-pub use factory::ObstacleTemplateCreateParams;
+pub use factory::{make_shape, ObstacleTemplateCreateParams};
 pub use multi_sparsity::MultiSparsity;
 use overlap_map::OverlapMap;
 pub use sparsity::Sparsity;
@@ -22,6 +23,7 @@ pub struct ObstacleTemplate {
     template_class: TemplateClass,
     h3m_template_index: u32,
     shape: Vec<DeltaPos>,
+    visit_shape: Vec<DeltaPos>,
     terrain_group_mask: u16,
     frequency: usize,
     may_located_on_mixed_tiles: bool,
@@ -54,6 +56,10 @@ impl ObstacleTemplate {
         &self.shape
     }
 
+    pub fn visit_shape(&self) -> &[DeltaPos] {
+        &self.visit_shape
+    }
+
     pub fn frequency(&self) -> usize {
         self.frequency
     }
@@ -74,6 +80,14 @@ impl ObstacleTemplate {
         (terrain_group & self.terrain_group_mask) != 0
     }
 
+    pub fn is_valid_category(&self, obstacle_category: ObstacleCategory) -> bool {
+        self.template_class.is_in_category(obstacle_category)
+    }
+
+    pub fn is_surface_only(&self) -> bool {
+        self.template_class.is_surface_only()
+    }
+
     pub fn overlap_obstacle_sparsity_penalty(&self) -> usize {
         self.overlap_obstacle_sparsity_penalty
     }
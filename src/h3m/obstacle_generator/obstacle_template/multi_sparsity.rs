@@ -38,7 +38,7 @@ impl MultiSparsity {
             .push(MultiSparsityEntry::new(neighbor_name, sparsity));
     }
 
-    pub fn iter(&self) -> Iter<MultiSparsityEntry> {
+    pub fn iter(&self) -> Iter<'_, MultiSparsityEntry> {
         self.0.iter()
     }
 }
@@ -1,3 +1,5 @@
+use crate::h3m::ObstacleCategory;
+
 #[derive(Clone, Copy, PartialEq)]
 pub enum TemplateClass {
     OakTrees,
@@ -62,4 +64,54 @@ impl TemplateClass {
             _ => None,
         }
     }
+
+    // Vegetation which doesn't grow underground.
+    pub fn is_surface_only(self) -> bool {
+        matches!(
+            self,
+            TemplateClass::OakTrees
+                | TemplateClass::PineTrees
+                | TemplateClass::Trees
+                | TemplateClass::Spruces
+                | TemplateClass::Palms
+                | TemplateClass::YuccaTrees
+                | TemplateClass::Cactus
+        )
+    }
+
+    pub fn is_in_category(self, category: ObstacleCategory) -> bool {
+        match category {
+            ObstacleCategory::Any => true,
+            ObstacleCategory::Forest => matches!(
+                self,
+                TemplateClass::OakTrees
+                    | TemplateClass::PineTrees
+                    | TemplateClass::Trees
+                    | TemplateClass::Spruces
+            ),
+            ObstacleCategory::Mountains => {
+                matches!(self, TemplateClass::Mountain | TemplateClass::Volcano)
+            }
+            ObstacleCategory::Lakes => matches!(
+                self,
+                TemplateClass::Lake
+                    | TemplateClass::FrozenLake
+                    | TemplateClass::LavaLake
+                    | TemplateClass::LimestoneLake
+                    | TemplateClass::TarPit
+            ),
+            ObstacleCategory::Rocks => matches!(
+                self,
+                TemplateClass::Rock
+                    | TemplateClass::Crater
+                    | TemplateClass::Mound
+                    | TemplateClass::SandDune
+                    | TemplateClass::BarchanDunes
+                    | TemplateClass::SandPit
+                    | TemplateClass::IceBlock
+                    | TemplateClass::SnowHills
+                    | TemplateClass::Reef
+            ),
+        }
+    }
 }
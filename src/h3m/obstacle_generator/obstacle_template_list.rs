@@ -1,5 +1,6 @@
 use super::obstacle_template::{ObstacleTemplate, ObstacleTemplateCreateParams};
-use std::slice::Iter;
+use crate::h3m::{ObstacleCategory, Terrain};
+use std::slice::{Iter, IterMut};
 
 pub struct ObstacleTemplateList(Vec<ObstacleTemplate>);
 
@@ -21,9 +22,20 @@ impl ObstacleTemplateList {
         &mut self.0[index]
     }
 
-    pub fn iter(&self) -> Iter<ObstacleTemplate> {
+    pub fn iter(&self) -> Iter<'_, ObstacleTemplate> {
         self.0.iter()
     }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, ObstacleTemplate> {
+        self.0.iter_mut()
+    }
+
+    // Whether some template of the category may be placed on the terrain.
+    pub fn has_category_templates(&self, terrain: Terrain, category: ObstacleCategory) -> bool {
+        self.iter().any(|obstacle| {
+            obstacle.is_valid_terrain(terrain.group()) && obstacle.is_valid_category(category)
+        })
+    }
 }
 
 fn h3m_obstacle_templates() -> Vec<ObstacleTemplateCreateParams> {
@@ -0,0 +1,68 @@
+use super::obstacle_template_list::ObstacleTemplateList;
+use crate::h3m::symmetry::{self, Symmetry};
+use std::collections::HashMap;
+
+// Templates of the obstacle images: for each template and transform of the symmetry
+// the templates of the same footprint as the transformed template one.
+pub struct SymmetricTemplates {
+    symmetry: Symmetry,
+    image_templates: Vec<Vec<Vec<usize>>>, // by the template and the transform indexes
+}
+
+impl SymmetricTemplates {
+    pub fn new(
+        symmetry: Symmetry,
+        obstacle_template_list: &ObstacleTemplateList,
+    ) -> SymmetricTemplates {
+        let mut footprint_templates: HashMap<Vec<(usize, usize)>, Vec<usize>> = HashMap::new();
+        for (index, obstacle) in obstacle_template_list.iter().enumerate() {
+            footprint_templates
+                .entry(symmetry::shape_cells(obstacle.shape()))
+                .or_default()
+                .push(index);
+        }
+
+        let image_templates = obstacle_template_list
+            .iter()
+            .enumerate()
+            .map(|(index, obstacle)| {
+                symmetry
+                    .transforms()
+                    .into_iter()
+                    .map(|transform| {
+                        let footprint = transform.transform_shape(obstacle.shape());
+                        let mut templates = footprint_templates
+                            .get(&footprint)
+                            .cloned()
+                            .unwrap_or_default();
+                        // the exact template first, then the other ones of the same footprint
+                        if let Some(position) = templates.iter().position(|&i| i == index) {
+                            templates[..=position].rotate_right(1);
+                        }
+                        templates
+                    })
+                    .collect()
+            })
+            .collect();
+
+        SymmetricTemplates {
+            symmetry,
+            image_templates,
+        }
+    }
+
+    pub fn symmetry(&self) -> Symmetry {
+        self.symmetry
+    }
+
+    // Whether all the images of the template have templates.
+    pub fn has_image_templates(&self, template_index: usize) -> bool {
+        self.image_templates[template_index]
+            .iter()
+            .all(|templates| !templates.is_empty())
+    }
+
+    pub fn image_templates(&self, template_index: usize, transform_index: usize) -> &[usize] {
+        &self.image_templates[template_index][transform_index]
+    }
+}
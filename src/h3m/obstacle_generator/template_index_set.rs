@@ -1,6 +1,7 @@
 use super::obstacle_template_list::ObstacleTemplateList;
+use super::symmetric_templates::SymmetricTemplates;
 use crate::common::index_multiset::IndexMultiset;
-use rand::rngs::ThreadRng;
+use crate::common::rng::SeededRng;
 
 #[derive(Clone)]
 pub struct TemplateIndexSet(IndexMultiset<usize>);
@@ -9,10 +10,18 @@ impl TemplateIndexSet {
     pub fn new(
         generalized_terrain_group: u16,
         obstacle_template_list: &ObstacleTemplateList,
+        symmetric_templates: Option<&SymmetricTemplates>,
+        with_surface_only: bool, // include the vegetation which doesn't grow underground
     ) -> TemplateIndexSet {
         let mut index_set = IndexMultiset::new();
         for (index, obstacle) in obstacle_template_list.iter().enumerate() {
-            if obstacle.is_valid_terrain(generalized_terrain_group) {
+            let has_image_templates = symmetric_templates.map_or(true, |symmetric_templates| {
+                symmetric_templates.has_image_templates(index)
+            });
+            if has_image_templates
+                && (with_surface_only || !obstacle.is_surface_only())
+                && obstacle.is_valid_terrain(generalized_terrain_group)
+            {
                 index_set.add_index(index, obstacle.frequency());
             }
         }
@@ -23,7 +32,7 @@ impl TemplateIndexSet {
         self.0.is_empty()
     }
 
-    pub fn random_index(&self, rng: &mut ThreadRng) -> usize {
+    pub fn random_index(&self, rng: &mut SeededRng) -> usize {
         self.0.random_index(rng).unwrap()
     }
 
@@ -4,7 +4,7 @@ use byteorder::{ReadBytesExt, LE};
 use std::io::{Read, Seek};
 
 #[derive(Debug, PartialEq)]
-enum Version {
+pub enum Version {
     RoE,
     AB,
     SoD,
@@ -13,7 +13,7 @@ enum Version {
     HotA,
 }
 
-fn read_version<RS: Read + Seek>(input: &mut RS) -> H3mResult<Version> {
+pub fn read_version<RS: Read + Seek>(input: &mut RS) -> H3mResult<Version> {
     let version = input.read_u32::<LE>()?;
     match version {
         0x0000000E => Ok(Version::RoE),
@@ -38,6 +38,7 @@ fn read_size<RS: Read + Seek>(input: &mut RS) -> H3mResult<usize> {
 }
 
 pub struct H3mHeaderInfo {
+    pub hota_subversion: u32,
     pub map_size: usize,
     pub has_underground: bool,
 }
@@ -73,6 +74,7 @@ pub fn read_header<RS: Read + Seek>(input: &mut RS) -> H3mResult<H3mHeaderInfo>
     let has_underground = read_bool(input)?;
 
     Ok(H3mHeaderInfo {
+        hota_subversion,
         map_size,
         has_underground,
     })
@@ -1,13 +1,19 @@
 use crate::h3m::result::*;
-use header::*;
+use crate::h3m::Terrain;
+use byteorder::{ReadBytesExt, LE};
+pub use header::*;
 pub use object_templates::*;
 pub use objects::*;
+use serde::Serialize;
 use std::io::Cursor;
 
 mod common;
 mod header;
 mod object_templates;
 mod objects;
+mod structure;
+#[cfg(test)]
+pub mod test_map;
 
 pub const MAP_CELL_SIZE: usize = 7;
 
@@ -20,12 +26,12 @@ pub struct H3mInfo {
     pub default_object_templates: [H3mObjectTemplate; 2],
 }
 
-struct LandOffsetInfo {
-    land_offset: usize,
-    underground_offset: Option<usize>,
+pub struct LandOffsetInfo {
+    pub land_offset: usize,
+    pub underground_offset: Option<usize>,
 }
 
-fn land_offset_from_objects_templates_offset(
+pub fn land_offset_from_objects_templates_offset(
     header_info: &H3mHeaderInfo,
     objects_templates_offset: usize,
 ) -> H3mResult<LandOffsetInfo> {
@@ -58,25 +64,285 @@ fn land_offset_from_objects_templates_offset(
     })
 }
 
-pub fn parse(raw_map: &[u8]) -> H3mResult<H3mInfo> {
-    let objects_templates_offset = find_object_templates_offset(raw_map)?;
+pub struct H3mCounts {
+    pub templates_count: usize,
+    pub objects_count: usize,
+    pub objects_offset: usize, // of the first object, right after the count
+}
 
+// Objects count follows the object templates, which are read to find it.
+pub fn read_counts(raw_map: &[u8], objects_templates_offset: usize) -> H3mResult<H3mCounts> {
     let mut raw_map = Cursor::new(raw_map);
+    raw_map.set_position(u64::try_from(objects_templates_offset)?);
+
+    let object_templates_info = read_object_templates_info(&mut raw_map)?;
+    let templates_count = object_templates_info.templates_count;
+    skip_object_templates(
+        &mut raw_map,
+        templates_count - DEFAULT_OBJECT_TEMPLATES_COUNT,
+    )?;
+    let objects_count = raw_map.read_u32::<LE>()?.try_into()?;
+
+    Ok(H3mCounts {
+        templates_count,
+        objects_count,
+        objects_offset: usize::try_from(raw_map.position())?,
+    })
+}
 
-    let header_info = read_header(&mut raw_map)?;
+pub struct H3mObjectsSection {
+    pub templates: Vec<H3mObjectTemplate>,
+    pub objects: Vec<H3mObject>,
+    pub end_offset: usize, // of the objects, the events and the rest of the map follow
+}
 
+// Objects have properties depending on their class, only the objects without them
+// can be read. Returns `None` if the map has objects of other templates.
+pub fn read_objects_section(
+    raw_map: &[u8],
+    objects_templates_offset: usize,
+    is_without_properties: impl Fn(&H3mObjectTemplate) -> bool,
+) -> H3mResult<Option<H3mObjectsSection>> {
+    let mut raw_map = Cursor::new(raw_map);
     raw_map.set_position(u64::try_from(objects_templates_offset)?);
-    let object_templates_info = read_object_templates_info(&mut raw_map)?;
 
+    let templates = read_object_templates(&mut raw_map)?;
+    let objects = read_objects_without_properties(&mut raw_map, |template_idx| {
+        templates
+            .get(template_idx as usize)
+            .is_some_and(&is_without_properties)
+    })?;
+
+    Ok(match objects {
+        Some(objects) => Some(H3mObjectsSection {
+            templates,
+            objects,
+            end_offset: usize::try_from(raw_map.position())?,
+        }),
+        None => None,
+    })
+}
+
+/// How the offsets of the map sections were found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OffsetsSource {
+    Structure, // walking the map sections from the header
+    Signature, // searching the default object templates
+}
+
+pub struct H3mSections {
+    pub land_offset: usize,
+    pub underground_offset: Option<usize>,
+    pub objects_templates_offset: usize,
+    pub source: OffsetsSource,
+    pub structure_error: Option<String>, // why the structure walk wasn't used
+}
+
+fn sections_from_structure(raw_map: &[u8], header_info: &H3mHeaderInfo) -> H3mResult<H3mSections> {
+    let mut input = Cursor::new(raw_map);
+    read_header(&mut input)?;
+    let land_offset = structure::read_land_offset(&mut input, header_info)?;
+
+    let land_size_in_bytes = header_info.map_size * header_info.map_size * MAP_CELL_SIZE;
+    let underground_offset = header_info
+        .has_underground
+        .then_some(land_offset + land_size_in_bytes);
+    let objects_templates_offset = underground_offset.unwrap_or(land_offset) + land_size_in_bytes;
+
+    // The walk may go wrong on unknown map versions, so the result is only trusted
+    // if the terrain, the object templates and the objects count can be read
+    // at the offsets and the objects fit in the rest of the map.
+    let cells = raw_map
+        .get(land_offset..objects_templates_offset)
+        .ok_or_else(|| {
+            H3mError::Parsing(ParsingError::new(
+                u64::try_from(land_offset).unwrap_or(u64::MAX),
+                "Terrain is beyond the end of the map.",
+            ))
+        })?;
+    for cell in cells.chunks_exact(MAP_CELL_SIZE) {
+        Terrain::from_code(cell[0])?;
+    }
+    let counts = read_counts(raw_map, objects_templates_offset)?;
+    let objects_min_end = counts
+        .objects_count
+        .checked_mul(MIN_OBJECT_SIZE)
+        .and_then(|objects_min_size| objects_min_size.checked_add(counts.objects_offset));
+    if objects_min_end.map_or(true, |objects_min_end| objects_min_end > raw_map.len()) {
+        return Err(H3mError::Parsing(ParsingError::new(
+            u64::try_from(counts.objects_offset)?,
+            format!(
+                "{} objects are beyond the end of the map.",
+                counts.objects_count
+            ),
+        )));
+    }
+
+    Ok(H3mSections {
+        land_offset,
+        underground_offset,
+        objects_templates_offset,
+        source: OffsetsSource::Structure,
+        structure_error: None,
+    })
+}
+
+fn sections_from_signature(raw_map: &[u8], header_info: &H3mHeaderInfo) -> H3mResult<H3mSections> {
+    let objects_templates_offset = find_object_templates_offset(raw_map)?;
     let land_offset_info =
-        land_offset_from_objects_templates_offset(&header_info, objects_templates_offset)?;
+        land_offset_from_objects_templates_offset(header_info, objects_templates_offset)?;
 
-    Ok(H3mInfo {
-        map_size: header_info.map_size,
+    Ok(H3mSections {
         land_offset: land_offset_info.land_offset,
         underground_offset: land_offset_info.underground_offset,
         objects_templates_offset,
+        source: OffsetsSource::Signature,
+        structure_error: None,
+    })
+}
+
+/// Finds the terrain and the object templates by walking the map structure,
+/// with the signature search as a fallback.
+pub fn locate_sections(raw_map: &[u8], header_info: &H3mHeaderInfo) -> H3mResult<H3mSections> {
+    match sections_from_structure(raw_map, header_info) {
+        Ok(sections) => Ok(sections),
+        Err(structure_error) => Ok(H3mSections {
+            structure_error: Some(structure_error.to_string()),
+            ..sections_from_signature(raw_map, header_info)?
+        }),
+    }
+}
+
+pub fn parse(raw_map: &[u8]) -> H3mResult<H3mInfo> {
+    let header_info = read_header(&mut Cursor::new(raw_map))?;
+    let sections = locate_sections(raw_map, &header_info)?;
+
+    let mut raw_map = Cursor::new(raw_map);
+    raw_map.set_position(u64::try_from(sections.objects_templates_offset)?);
+    let object_templates_info = read_object_templates_info(&mut raw_map)?;
+
+    Ok(H3mInfo {
+        map_size: header_info.map_size,
+        land_offset: sections.land_offset,
+        underground_offset: sections.underground_offset,
+        objects_templates_offset: sections.objects_templates_offset,
         has_non_default_templates: object_templates_info.has_non_default_templates,
         default_object_templates: object_templates_info.default_object_templates,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_map::TestMap;
+
+    fn expected_land_offset(raw_map: &[u8], map_size: usize) -> usize {
+        // the terrain is followed by the templates, the objects count and the events
+        raw_map.len() - 124 - 4 - (4 + 2 * 58) - map_size * map_size * MAP_CELL_SIZE
+    }
+
+    #[test]
+    fn structure_offsets_test() {
+        let raw_map = TestMap::new(3, Terrain::Grass).raw_map();
+
+        let info = parse(&raw_map).unwrap();
+        let header_info = read_header(&mut Cursor::new(&raw_map[..])).unwrap();
+        let sections = locate_sections(&raw_map, &header_info).unwrap();
+
+        assert_eq!(sections.source, OffsetsSource::Structure);
+        assert_eq!(info.land_offset, expected_land_offset(&raw_map, 3));
+        assert_eq!(info.underground_offset, None);
+        assert!(!info.has_non_default_templates);
+    }
+
+    #[test]
+    fn structure_offsets_of_subversions_test() {
+        for hota_subversion in [1, 3, 6, 7, 8, 9] {
+            let mut test_map = TestMap::new(4, Terrain::Grass);
+            test_map.hota_subversion = hota_subversion;
+            test_map.underground = Some(vec![Terrain::Subterranean; 4 * 4]);
+            let raw_map = test_map.raw_map();
+
+            let header_info = read_header(&mut Cursor::new(&raw_map[..])).unwrap();
+            assert_eq!(header_info.hota_subversion, hota_subversion);
+            let sections = sections_from_structure(&raw_map, &header_info).unwrap();
+            let land_offset = expected_land_offset(&raw_map, 4) - 4 * 4 * MAP_CELL_SIZE;
+            assert_eq!(sections.land_offset, land_offset, "{}", hota_subversion);
+            assert_eq!(
+                sections.underground_offset,
+                Some(land_offset + 4 * 4 * MAP_CELL_SIZE)
+            );
+        }
+    }
+
+    #[test]
+    fn structure_offsets_with_objects_beyond_map_end_test() {
+        let mut raw_map = TestMap::new(3, Terrain::Grass).raw_map();
+        let objects_count_offset = raw_map.len() - 124 - 4;
+        raw_map[objects_count_offset..objects_count_offset + 4]
+            .copy_from_slice(&100u32.to_le_bytes());
+
+        let header_info = read_header(&mut Cursor::new(&raw_map[..])).unwrap();
+        assert!(sections_from_structure(&raw_map, &header_info).is_err());
+        let sections = locate_sections(&raw_map, &header_info).unwrap();
+        assert_eq!(sections.source, OffsetsSource::Signature);
+        assert!(sections.structure_error.is_some());
+    }
+
+    #[test]
+    fn structure_offsets_with_wrong_skills_count_test() {
+        let mut raw_map = TestMap::new(3, Terrain::Grass).raw_map();
+        // the skills of the customized hero: a flag, the count, a skill and its level
+        let skills = [1, 1, 0, 0, 0, 3, 2];
+        let skills_offset = raw_map
+            .windows(skills.len())
+            .position(|window| window == skills)
+            .unwrap();
+        raw_map[skills_offset + 1..skills_offset + 5].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        let header_info = read_header(&mut Cursor::new(&raw_map[..])).unwrap();
+        assert!(sections_from_structure(&raw_map, &header_info).is_err());
+        let sections = locate_sections(&raw_map, &header_info).unwrap();
+        assert_eq!(sections.source, OffsetsSource::Signature);
+    }
+
+    #[test]
+    fn structure_offsets_without_default_templates_test() {
+        let mut test_map = TestMap::new(3, Terrain::Grass);
+        test_map.template_filenames = ["AVXsand0.def", "AVLholg0.def"];
+        let raw_map = test_map.raw_map();
+
+        assert!(find_object_templates_offset(&raw_map).is_err());
+        let info = parse(&raw_map).unwrap();
+        assert_eq!(info.land_offset, expected_land_offset(&raw_map, 3));
+    }
+
+    #[test]
+    fn structure_offsets_with_signature_in_description_test() {
+        let template_filenames = ["AVWmrnd0.def", "AVLholg0.def"];
+        let mut description = Vec::new();
+        write_object_templates(
+            &template_filenames.map(|filename| H3mObjectTemplate {
+                filename: filename.to_string(),
+                shape_mask: [0; 6],
+                visit_mask: [0; 6],
+                surface_type_mask: 0,
+                surface_editor_group_mask: 0,
+                class: 0,
+                subclass: 0,
+                group: 0,
+                is_overlay: false,
+            }),
+            &mut description,
+        )
+        .unwrap();
+        let mut test_map = TestMap::new(3, Terrain::Grass);
+        test_map.description = description;
+        let raw_map = test_map.raw_map();
+
+        assert!(find_object_templates_offset(&raw_map).unwrap() < 100);
+        let info = parse(&raw_map).unwrap();
+        assert_eq!(info.land_offset, expected_land_offset(&raw_map, 3));
+    }
+}
@@ -76,10 +76,11 @@ pub fn write_object_templates<W: Write>(
     Ok(())
 }
 
-const DEFAULT_OBJECT_TEMPLATES_COUNT: usize = 2;
+pub const DEFAULT_OBJECT_TEMPLATES_COUNT: usize = 2;
 pub type DefaultObjectTemplates = [H3mObjectTemplate; DEFAULT_OBJECT_TEMPLATES_COUNT];
 
 pub struct H3mObjectTemplatesInfo {
+    pub templates_count: usize,
     pub has_non_default_templates: bool,
     pub default_object_templates: DefaultObjectTemplates,
 }
@@ -106,11 +107,28 @@ pub fn read_object_templates_info<RS: Read + Seek>(
     let default_object_templates = [first_object_template, second_object_template];
 
     Ok(H3mObjectTemplatesInfo {
+        templates_count,
         has_non_default_templates: (templates_count > DEFAULT_OBJECT_TEMPLATES_COUNT),
         default_object_templates,
     })
 }
 
+pub fn read_object_templates<RS: Read + Seek>(input: &mut RS) -> H3mResult<Vec<H3mObjectTemplate>> {
+    let templates_count = input.read_u32::<LE>()?;
+    let mut object_templates = Vec::new();
+    for _ in 0..templates_count {
+        object_templates.push(read_object_template(input)?);
+    }
+    Ok(object_templates)
+}
+
+pub fn skip_object_templates<RS: Read + Seek>(input: &mut RS, count: usize) -> H3mResult<()> {
+    for _ in 0..count {
+        read_object_template(input)?;
+    }
+    Ok(())
+}
+
 pub fn find_object_templates_offset(raw_map: &[u8]) -> H3mResult<usize> {
     let first_signature = [
         0x0Cu8, 0x00, 0x00, 0x00, 0x41, 0x56, 0x57, 0x6D, 0x72, 0x6E, 0x64, 0x30, 0x2E, 0x64, 0x65,
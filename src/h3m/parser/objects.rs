@@ -1,9 +1,12 @@
 use crate::h3m::parser::common::*;
 use crate::h3m::result::*;
-use byteorder::{WriteBytesExt, LE};
-use std::io::Write;
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+use std::io::{Read, Seek, Write};
 
-#[derive(Debug)]
+// Location, template index and 5 unknown bytes, the properties may follow.
+pub const MIN_OBJECT_SIZE: usize = 3 + 4 + 5;
+
+#[derive(Debug, PartialEq)]
 pub struct H3mLocation {
     column: u8,
     row: u8,
@@ -20,7 +23,7 @@ impl H3mLocation {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct H3mObject {
     location: H3mLocation,
     template_idx: u32,
@@ -38,6 +41,34 @@ impl H3mObject {
             template_idx,
         }
     }
+
+    pub fn column(&self) -> u8 {
+        self.location.column
+    }
+
+    pub fn row(&self) -> u8 {
+        self.location.row
+    }
+
+    pub fn underground(&self) -> bool {
+        self.location.underground
+    }
+
+    pub fn template_idx(&self) -> u32 {
+        self.template_idx
+    }
+
+    pub fn set_template_idx(&mut self, template_idx: u32) {
+        self.template_idx = template_idx;
+    }
+}
+
+fn read_location<RS: Read + Seek>(input: &mut RS) -> H3mResult<H3mLocation> {
+    Ok(H3mLocation::new(
+        input.read_u8()?,
+        input.read_u8()?,
+        read_bool(input)?,
+    ))
 }
 
 fn write_location<W: Write>(location: &H3mLocation, output: &mut W) -> H3mResult<()> {
@@ -69,3 +100,26 @@ pub fn write_objects<W: Write>(objects: &[H3mObject], output: &mut W) -> H3mResu
 
     Ok(())
 }
+
+// Reads the objects while their templates have no object properties,
+// returns `None` at the first object of another template.
+pub fn read_objects_without_properties<RS: Read + Seek>(
+    input: &mut RS,
+    is_without_properties: impl Fn(u32) -> bool,
+) -> H3mResult<Option<Vec<H3mObject>>> {
+    let count = input.read_u32::<LE>()?;
+    let mut objects = Vec::new();
+    for _ in 0..count {
+        let location = read_location(input)?;
+        let template_idx = input.read_u32::<LE>()?;
+        if !is_without_properties(template_idx) {
+            return Ok(None);
+        }
+        skip_bytes(input, 5)?;
+        objects.push(H3mObject {
+            location,
+            template_idx,
+        });
+    }
+    Ok(Some(objects))
+}
@@ -0,0 +1,213 @@
+use crate::h3m::parser::common::*;
+use crate::h3m::parser::header::H3mHeaderInfo;
+use crate::h3m::result::*;
+use byteorder::{ReadBytesExt, LE};
+use std::io::{Read, Seek};
+
+// Walks the map sections between the header and the terrain, following the
+// HotA layout of the map editor. Only the sizes of the sections are read.
+
+const PLAYERS_COUNT: usize = 8;
+const ARTIFACT_SLOTS_COUNT: usize = 19;
+const SPELLS_BYTES: u32 = 9;
+const SKILLS_BYTES: u32 = 4;
+const PRIMARY_SKILLS_COUNT: u32 = 4;
+const NO_HERO: u8 = 0xFF;
+const NO_CONDITION: u8 = 0xFF;
+
+fn skip_string<RS: Read + Seek>(input: &mut RS) -> H3mResult<()> {
+    let size = input.read_u32::<LE>()?;
+    skip_bytes(input, size)
+}
+
+fn skip_sized_bitmask<RS: Read + Seek>(input: &mut RS) -> H3mResult<()> {
+    let bits_count = input.read_u32::<LE>()?;
+    skip_bytes(input, bits_count.div_ceil(8))
+}
+
+fn skip_player<RS: Read + Seek>(input: &mut RS) -> H3mResult<()> {
+    let can_human_play = read_bool(input)?;
+    let can_computer_play = read_bool(input)?;
+    if !can_human_play && !can_computer_play {
+        return skip_bytes(input, 13);
+    }
+
+    skip_bytes(input, 2)?; // ai tactic, unknown
+    skip_bytes(input, 2)?; // allowed factions
+    skip_bool(input)?; // random faction
+
+    let has_main_town = read_bool(input)?;
+    if has_main_town {
+        skip_bytes(input, 2 + 3)?; // hero generation flags, main town position
+    }
+
+    skip_bool(input)?; // random hero
+    let main_hero = input.read_u8()?;
+    if main_hero != NO_HERO {
+        skip_bytes(input, 1)?; // portrait
+        skip_string(input)?;
+    }
+
+    skip_bytes(input, 1)?; // unknown
+    let heroes_count = input.read_u32::<LE>()?;
+    for _ in 0..heroes_count {
+        skip_bytes(input, 1)?; // hero
+        skip_string(input)?;
+    }
+    Ok(())
+}
+
+fn skip_victory_loss_conditions<RS: Read + Seek>(input: &mut RS) -> H3mResult<()> {
+    let victory_condition = input.read_u8()?;
+    if victory_condition != NO_CONDITION {
+        skip_bytes(input, 2)?; // standard victory allowed, applies to computer
+        let bytes_to_skip = match victory_condition {
+            0 => 2,      // acquire artifact
+            1 => 2 + 4,  // accumulate creatures
+            2 => 1 + 4,  // accumulate resources
+            3 => 3 + 2,  // upgrade town
+            4..=7 => 3,  // build grail, defeat hero, capture town, defeat monster
+            8 | 9 => 0,  // flag dwellings, flag mines
+            10 => 1 + 3, // transport artifact
+            11 => 0,     // eliminate all monsters
+            12 => 4,     // survive for days
+            other => {
+                return Err(H3mError::Parsing(ParsingError::new(
+                    input.stream_position()?,
+                    format!("Unexpected victory condition {}.", other),
+                )))
+            }
+        };
+        skip_bytes(input, bytes_to_skip)?;
+    }
+
+    let loss_condition = input.read_u8()?;
+    let bytes_to_skip = match loss_condition {
+        NO_CONDITION => 0,
+        0 | 1 => 3, // lose town, lose hero
+        2 => 2,     // time expires
+        other => {
+            return Err(H3mError::Parsing(ParsingError::new(
+                input.stream_position()?,
+                format!("Unexpected loss condition {}.", other),
+            )))
+        }
+    };
+    skip_bytes(input, bytes_to_skip)
+}
+
+fn skip_teams<RS: Read + Seek>(input: &mut RS) -> H3mResult<()> {
+    let teams_count = input.read_u8()?;
+    if teams_count > 0 {
+        skip_bytes(input, PLAYERS_COUNT as u32)?;
+    }
+    Ok(())
+}
+
+fn skip_allowed_heroes<RS: Read + Seek>(input: &mut RS) -> H3mResult<()> {
+    skip_sized_bitmask(input)?;
+    let placeholders_count = input.read_u32::<LE>()?;
+    skip_bytes(input, placeholders_count)
+}
+
+fn skip_disposed_heroes<RS: Read + Seek>(input: &mut RS) -> H3mResult<()> {
+    let heroes_count = input.read_u8()?;
+    for _ in 0..heroes_count {
+        skip_bytes(input, 2)?; // hero, portrait
+        skip_string(input)?;
+        skip_bytes(input, 1)?; // players
+    }
+    Ok(())
+}
+
+fn skip_map_options<RS: Read + Seek>(input: &mut RS, hota_subversion: u32) -> H3mResult<()> {
+    skip_bytes(input, 31)?;
+    skip_bytes(input, 1 + 3)?; // special months allowed
+    if hota_subversion > 0 {
+        skip_bytes(input, 1 + 5)?;
+    }
+    if hota_subversion > 2 {
+        skip_bytes(input, 4)?; // combat round limit
+    }
+    Ok(())
+}
+
+fn skip_rumors<RS: Read + Seek>(input: &mut RS) -> H3mResult<()> {
+    let rumors_count = input.read_u32::<LE>()?;
+    for _ in 0..rumors_count {
+        skip_string(input)?; // name
+        skip_string(input)?; // text
+    }
+    Ok(())
+}
+
+fn skip_hero_artifacts<RS: Read + Seek>(input: &mut RS) -> H3mResult<()> {
+    let has_artifacts = read_bool(input)?;
+    if has_artifacts {
+        skip_bytes(input, 2 * ARTIFACT_SLOTS_COUNT as u32)?;
+        let backpack_count = input.read_u16::<LE>()?;
+        skip_bytes(input, 2 * u32::from(backpack_count))?;
+    }
+    Ok(())
+}
+
+fn skip_predefined_heroes<RS: Read + Seek>(input: &mut RS) -> H3mResult<()> {
+    let heroes_count = input.read_u32::<LE>()?;
+    for _ in 0..heroes_count {
+        let is_custom = read_bool(input)?;
+        if !is_custom {
+            continue;
+        }
+
+        if read_bool(input)? {
+            skip_bytes(input, 4)?; // experience
+        }
+        if read_bool(input)? {
+            let skills_count = input.read_u32::<LE>()?;
+            let Some(skills_size) = skills_count.checked_mul(2) else {
+                return Err(H3mError::Parsing(ParsingError::new(
+                    input.stream_position()?,
+                    format!("Unexpected hero skills count {}.", skills_count),
+                )));
+            };
+            skip_bytes(input, skills_size)?;
+        }
+        skip_hero_artifacts(input)?;
+        if read_bool(input)? {
+            skip_string(input)?; // biography
+        }
+        skip_bytes(input, 1)?; // sex
+        if read_bool(input)? {
+            skip_bytes(input, SPELLS_BYTES)?;
+        }
+        if read_bool(input)? {
+            skip_bytes(input, PRIMARY_SKILLS_COUNT)?;
+        }
+    }
+    Ok(())
+}
+
+// The input must be right after the header read by `read_header`.
+pub fn read_land_offset<RS: Read + Seek>(
+    input: &mut RS,
+    header_info: &H3mHeaderInfo,
+) -> H3mResult<usize> {
+    skip_string(input)?; // name
+    skip_string(input)?; // description
+    skip_bytes(input, 2)?; // difficulty, level limit
+
+    for _ in 0..PLAYERS_COUNT {
+        skip_player(input)?;
+    }
+    skip_victory_loss_conditions(input)?;
+    skip_teams(input)?;
+    skip_allowed_heroes(input)?;
+    skip_disposed_heroes(input)?;
+    skip_map_options(input, header_info.hota_subversion)?;
+    skip_sized_bitmask(input)?; // allowed artifacts
+    skip_bytes(input, SPELLS_BYTES + SKILLS_BYTES)?; // allowed spells and skills
+    skip_rumors(input)?;
+    skip_predefined_heroes(input)?;
+
+    Ok(usize::try_from(input.stream_position()?)?)
+}
@@ -0,0 +1,149 @@
+use super::{write_object_templates, H3mObjectTemplate};
+use crate::h3m::Terrain;
+use libflate::gzip::Encoder;
+use std::io::Write;
+
+// Minimal HotA map (subversion 3 by default) for tests: one playable player,
+// a rumor, a customized hero and no objects but the two default templates.
+pub struct TestMap {
+    pub hota_subversion: u32,
+    pub map_size: usize,
+    pub description: Vec<u8>,
+    pub land: Vec<Terrain>,
+    pub underground: Option<Vec<Terrain>>,
+    pub template_filenames: [&'static str; 2],
+}
+
+fn write_string(value: &[u8], output: &mut Vec<u8>) {
+    output.extend_from_slice(&(value.len() as u32).to_le_bytes());
+    output.extend_from_slice(value);
+}
+
+fn write_u32(value: u32, output: &mut Vec<u8>) {
+    output.extend_from_slice(&value.to_le_bytes());
+}
+
+// Header bytes between the subversion and the players existence.
+fn header_options_size(hota_subversion: u32) -> usize {
+    match hota_subversion {
+        1 => 2,
+        3 => 6,
+        6 => 11,
+        7 => 12,
+        8 => 25,
+        9 => 29,
+        other => panic!("Unsupported hota subversion {}.", other),
+    }
+}
+
+fn object_template(filename: &str) -> H3mObjectTemplate {
+    H3mObjectTemplate {
+        filename: filename.to_string(),
+        shape_mask: [255; 6],
+        visit_mask: [0; 6],
+        surface_type_mask: 0,
+        surface_editor_group_mask: 0,
+        class: 0,
+        subclass: 0,
+        group: 0,
+        is_overlay: false,
+    }
+}
+
+impl TestMap {
+    pub fn new(map_size: usize, terrain: Terrain) -> TestMap {
+        TestMap {
+            hota_subversion: 3,
+            map_size,
+            description: Vec::new(),
+            land: vec![terrain; map_size * map_size],
+            underground: None,
+            template_filenames: ["AVWmrnd0.def", "AVLholg0.def"],
+        }
+    }
+
+    pub fn raw_map(&self) -> Vec<u8> {
+        let mut raw_map = Vec::new();
+        write_u32(0x20, &mut raw_map); // HotA
+        write_u32(self.hota_subversion, &mut raw_map);
+        raw_map.extend_from_slice(&[0, 0]); // mirror, arena
+        if self.hota_subversion > 1 {
+            write_u32(12, &mut raw_map); // terrain types
+        }
+        // the options of the later subversions are left off
+        raw_map.resize(8 + header_options_size(self.hota_subversion), 0);
+        raw_map.push(1); // players existence
+        write_u32(self.map_size as u32, &mut raw_map);
+        raw_map.push(self.underground.is_some() as u8);
+        write_string(b"Test", &mut raw_map);
+        write_string(&self.description, &mut raw_map);
+        raw_map.extend_from_slice(&[1, 0]); // difficulty, level limit
+
+        // the first player with a main hero, the others disabled
+        raw_map.extend_from_slice(&[1, 1, 0, 0, 0xFF, 0x03, 0, 0, 0, 5, 30]);
+        write_string(b"Hero", &mut raw_map);
+        raw_map.push(0);
+        write_u32(1, &mut raw_map);
+        raw_map.push(7);
+        write_string(b"Named", &mut raw_map);
+        raw_map.extend_from_slice(&[0; 7 * 15]);
+
+        raw_map.extend_from_slice(&[12]); // survive for days
+        raw_map.extend_from_slice(&[0, 1]);
+        write_u32(100, &mut raw_map);
+        raw_map.extend_from_slice(&[2, 0x10, 0]); // time expires
+        raw_map.extend_from_slice(&[2, 0, 1, 0, 1, 0, 1, 0, 1]); // teams
+
+        write_u32(179, &mut raw_map); // allowed heroes
+        raw_map.extend_from_slice(&[0xFF; 23]);
+        write_u32(0, &mut raw_map); // placeholders
+        raw_map.push(0); // disposed heroes
+
+        raw_map.extend_from_slice(&[0; 31]);
+        raw_map.extend_from_slice(&[1, 0, 0, 0]);
+        if self.hota_subversion > 0 {
+            raw_map.extend_from_slice(&[16, 0, 0, 0, 0, 0]);
+        }
+        if self.hota_subversion > 2 {
+            raw_map.extend_from_slice(&(-1i32).to_le_bytes()); // combat round limit
+        }
+
+        write_u32(165, &mut raw_map); // allowed artifacts
+        raw_map.extend_from_slice(&[0; 21]);
+        raw_map.extend_from_slice(&[0; 9 + 4]); // allowed spells and skills
+
+        write_u32(1, &mut raw_map);
+        write_string(b"Rumor", &mut raw_map);
+        write_string(b"Text", &mut raw_map);
+
+        write_u32(179, &mut raw_map); // predefined heroes
+        raw_map.extend_from_slice(&[1, 1]);
+        write_u32(1000, &mut raw_map);
+        raw_map.push(1);
+        write_u32(1, &mut raw_map);
+        raw_map.extend_from_slice(&[3, 2]); // skill, level
+        raw_map.push(1);
+        raw_map.extend_from_slice(&[0xFF; 2 * 19]);
+        raw_map.extend_from_slice(&[1, 0, 5, 0]); // backpack
+        raw_map.push(1);
+        write_string(b"Biography", &mut raw_map);
+        raw_map.extend_from_slice(&[0xFF, 0, 0]);
+        raw_map.extend_from_slice(&[0; 178]);
+
+        for terrain in self.land.iter().chain(self.underground.iter().flatten()) {
+            raw_map.extend_from_slice(&[terrain.code(), 0, 0, 0, 0, 0, 0]);
+        }
+
+        let templates = self.template_filenames.map(object_template);
+        write_object_templates(&templates, &mut raw_map).unwrap();
+        write_u32(0, &mut raw_map); // objects
+        raw_map.extend_from_slice(&[0; 124]);
+        raw_map
+    }
+
+    pub fn compressed(&self) -> Vec<u8> {
+        let mut encoder = Encoder::new(Vec::new()).unwrap();
+        encoder.write_all(&self.raw_map()).unwrap();
+        encoder.finish().into_result().unwrap()
+    }
+}
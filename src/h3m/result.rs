@@ -1,3 +1,4 @@
+use image::ImageError;
 use std::convert::Infallible;
 use std::error::Error;
 use std::fmt;
@@ -7,9 +8,9 @@ pub type H3mResult<T> = Result<T, H3mError>;
 
 #[derive(Debug)]
 pub enum H3mError {
-    Parsing(ParsingError),
-    Parameter(ParameterError),
-    Internal(InternalError),
+    Parsing(ParsingError), // the input map is broken or has an unsupported format
+    Parameter(ParameterError), // the call arguments don't fit the map
+    Internal(InternalError), // generation failed, e.g. a number doesn't fit the map format
     IoError(io::Error),
 }
 
@@ -31,7 +32,14 @@ impl fmt::Display for H3mError {
     }
 }
 
-impl Error for H3mError {}
+impl Error for H3mError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            H3mError::IoError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct ParsingError {
@@ -46,6 +54,14 @@ impl ParsingError {
             msg: msg.into(),
         }
     }
+
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
 }
 
 #[derive(Debug)]
@@ -57,6 +73,10 @@ impl ParameterError {
     pub fn new(msg: impl Into<String>) -> ParameterError {
         ParameterError { msg: msg.into() }
     }
+
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
 }
 
 #[derive(Debug)]
@@ -68,6 +88,10 @@ impl InternalError {
     pub fn new(msg: impl Into<String>) -> InternalError {
         InternalError { msg: msg.into() }
     }
+
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
 }
 
 impl From<io::Error> for H3mError {
@@ -76,6 +100,15 @@ impl From<io::Error> for H3mError {
     }
 }
 
+impl From<ImageError> for H3mError {
+    fn from(err: ImageError) -> H3mError {
+        match err {
+            ImageError::IoError(err) => H3mError::IoError(err),
+            err => H3mError::Parameter(ParameterError::new(format!("Image error: {}.", err))),
+        }
+    }
+}
+
 impl From<TryFromIntError> for H3mError {
     fn from(err: TryFromIntError) -> H3mError {
         H3mError::Internal(InternalError::new(format!("Conversion error: {}.", err)))
@@ -1,7 +1,10 @@
 use crate::h3m::result::*;
-use strum_macros::EnumIter;
+use serde::{Deserialize, Serialize};
+use strum_macros::{EnumCount, EnumIter};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EnumIter)]
+/// Terrain type of a map cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EnumIter, EnumCount, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Terrain {
     Dirt,
     Sand,
@@ -18,6 +21,7 @@ pub enum Terrain {
 }
 
 impl Terrain {
+    /// Terrain by its code in the h3m format.
     pub fn from_code(code: u8) -> H3mResult<Terrain> {
         match code {
             0 => Ok(Terrain::Dirt),
@@ -39,6 +43,7 @@ impl Terrain {
         }
     }
 
+    /// Every terrain but water and rock is walkable ground.
     pub fn is_ground(self) -> bool {
         !matches!(self, Terrain::Water | Terrain::Rock)
     }
@@ -61,8 +66,60 @@ impl Terrain {
     }
 }
 
-#[derive(Clone, Copy)]
+/// Kind of obstacles to place on a cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, EnumIter)]
+pub enum ObstacleCategory {
+    Any,
+    Forest,    // trees, palms and dead vegetation
+    Mountains, // mountains, volcanoes and waterfalls
+    Lakes,     // lakes, lava lakes, tar pits and other water bodies
+    Rocks,     // rocks, craters, dunes, hills and reefs
+}
+
+/// Obstacle request for a map cell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Obstacle {
+    pub category: ObstacleCategory,
+    pub optional: bool, // optional obstacle cells may stay free
+}
+
+impl Obstacle {
+    pub fn new(category: ObstacleCategory) -> Obstacle {
+        Obstacle {
+            category,
+            optional: false,
+        }
+    }
+
+    pub fn optional(category: ObstacleCategory) -> Obstacle {
+        Obstacle {
+            category,
+            optional: true,
+        }
+    }
+}
+
+/// Desired state of a map cell: its terrain and whether obstacles should cover it.
+///
+/// Obstacles are never placed on water and rock, nor on cells without an obstacle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Surface {
     pub terrain: Terrain,
-    pub obstacle: bool,
+    pub obstacle: Option<Obstacle>,
+}
+
+impl Surface {
+    pub fn new(terrain: Terrain) -> Surface {
+        Surface {
+            terrain,
+            obstacle: None,
+        }
+    }
+
+    pub fn with_obstacle(terrain: Terrain, obstacle: Obstacle) -> Surface {
+        Surface {
+            terrain,
+            obstacle: Some(obstacle),
+        }
+    }
 }
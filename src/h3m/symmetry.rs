@@ -0,0 +1,315 @@
+use crate::common::position::{DeltaPos, Position};
+use crate::h3m::result::*;
+use serde::Deserialize;
+
+/// Mirroring or rotation of the cells of a level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Transform {
+    MirrorHorizontal, // the left and the right sides swap
+    MirrorVertical,   // the top and the bottom swap
+    MirrorDiagonal,   // the rows and the columns swap
+    #[serde(rename = "rotate-90")]
+    Rotate90, // clockwise
+    #[serde(rename = "rotate-180")]
+    Rotate180,
+    #[serde(rename = "rotate-270")]
+    Rotate270, // counterclockwise by 90°
+}
+
+impl Transform {
+    // Cell of a box of the height and width, the box of a diagonal mirroring
+    // or a rotation by 90° is turned to the width and height.
+    fn transform_cell(
+        self,
+        height: usize,
+        width: usize,
+        row: usize,
+        column: usize,
+    ) -> (usize, usize) {
+        match self {
+            Transform::MirrorHorizontal => (row, width - 1 - column),
+            Transform::MirrorVertical => (height - 1 - row, column),
+            Transform::MirrorDiagonal => (column, row),
+            Transform::Rotate90 => (column, height - 1 - row),
+            Transform::Rotate180 => (height - 1 - row, width - 1 - column),
+            Transform::Rotate270 => (width - 1 - column, row),
+        }
+    }
+
+    pub fn transform_position(self, size: usize, position: Position) -> Position {
+        let (row, column) = self.transform_cell(size, size, position.row(), position.column());
+        Position::new(row, column)
+    }
+
+    // Cells of the transformed obstacle shape as `shape_cells` gives them.
+    pub fn transform_shape(self, shape: &[DeltaPos]) -> Vec<(usize, usize)> {
+        let (height, width) = shape_size(shape);
+        let mut cells: Vec<(usize, usize)> = shape_cells(shape)
+            .into_iter()
+            .map(|(row, column)| self.transform_cell(height, width, row, column))
+            .collect();
+        cells.sort_unstable();
+        cells
+    }
+}
+
+/// Transforms the cells of a level listed row by row, like the surfaces
+/// of [`crate::H3m::set_surfaces`].
+///
+/// Fails with `H3mError::Parameter` if there are not `size²` cells.
+pub fn transform_cells<T: Copy>(
+    size: usize,
+    cells: &[T],
+    transform: Transform,
+) -> H3mResult<Vec<T>> {
+    if cells.len() != size * size {
+        return Err(H3mError::Parameter(ParameterError::new(format!(
+            "Cells length ({}) not equal squared map size ({}).",
+            cells.len(),
+            size * size
+        ))));
+    }
+
+    let mut transformed_cells = cells.to_vec();
+    for (index, &cell) in cells.iter().enumerate() {
+        let position = transform.transform_position(size, Position::from_index(size, index));
+        transformed_cells[position.index(size)] = cell;
+    }
+    Ok(transformed_cells)
+}
+
+/// Symmetry of a level: the source region, the cells coming first in row order
+/// among their images, is reproduced in the rest of the level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Symmetry {
+    MirrorHorizontal, // the left half is mirrored to the right
+    MirrorVertical,   // the top half is mirrored to the bottom
+    MirrorDiagonal,   // the half above the main diagonal is mirrored below it
+    #[serde(rename = "rotate-180")]
+    Rotate180, // the top half is rotated to the bottom
+    MirrorBoth,       // the top left quarter is mirrored to the other quarters
+    #[serde(rename = "rotate-90")]
+    Rotate90, // the top quarter between the diagonals is rotated to the other sides
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SymmetryRegion {
+    Source,
+    Image,
+    Axis, // the cell is its own image
+}
+
+impl Symmetry {
+    // Transforms of the source region to the other regions.
+    pub fn transforms(self) -> Vec<Transform> {
+        match self {
+            Symmetry::MirrorHorizontal => vec![Transform::MirrorHorizontal],
+            Symmetry::MirrorVertical => vec![Transform::MirrorVertical],
+            Symmetry::MirrorDiagonal => vec![Transform::MirrorDiagonal],
+            Symmetry::Rotate180 => vec![Transform::Rotate180],
+            Symmetry::MirrorBoth => vec![
+                Transform::MirrorHorizontal,
+                Transform::MirrorVertical,
+                Transform::Rotate180,
+            ],
+            Symmetry::Rotate90 => vec![
+                Transform::Rotate90,
+                Transform::Rotate180,
+                Transform::Rotate270,
+            ],
+        }
+    }
+
+    pub fn region(self, size: usize, index: usize) -> SymmetryRegion {
+        let position = Position::from_index(size, index);
+        let image_indexes: Vec<usize> = self
+            .transforms()
+            .into_iter()
+            .map(|transform| transform.transform_position(size, position).index(size))
+            .collect();
+
+        if image_indexes.contains(&index) {
+            SymmetryRegion::Axis
+        } else if image_indexes.iter().all(|&image_index| index < image_index) {
+            SymmetryRegion::Source
+        } else {
+            SymmetryRegion::Image
+        }
+    }
+
+    // Surfaces with the source region reproduced in the image regions.
+    pub fn complete<T: Copy>(self, size: usize, cells: &[T]) -> Vec<T> {
+        let mut completed_cells = cells.to_vec();
+        for (index, &cell) in cells.iter().enumerate() {
+            if self.region(size, index) != SymmetryRegion::Source {
+                continue;
+            }
+            let position = Position::from_index(size, index);
+            for transform in self.transforms() {
+                let image_position = transform.transform_position(size, position);
+                completed_cells[image_position.index(size)] = cell;
+            }
+        }
+        completed_cells
+    }
+
+    // Position of the obstacle of the image shape covering the image of the shape
+    // at the position, the obstacle positions are at their bottom right.
+    pub fn image_position(
+        self,
+        size: usize,
+        transform: Transform,
+        position: Position,
+        shape: &[DeltaPos],
+        image_shape: &[DeltaPos],
+    ) -> Option<Position> {
+        let image_cells: Vec<Position> = shape
+            .iter()
+            .map(|delta| {
+                let cell = position.checked_sub_delta(delta)?;
+                Some(transform.transform_position(size, cell))
+            })
+            .collect::<Option<_>>()?;
+        let min_row = image_cells.iter().map(Position::row).min()?;
+        let min_column = image_cells.iter().map(Position::column).min()?;
+        let max_delta_row = image_shape.iter().map(DeltaPos::row).max()?;
+        let max_delta_column = image_shape.iter().map(DeltaPos::column).max()?;
+
+        let image_position = Position::new(min_row + max_delta_row, min_column + max_delta_column);
+        if image_position.row() < size && image_position.column() < size {
+            Some(image_position)
+        } else {
+            None
+        }
+    }
+}
+
+// Cells of the obstacle shape from the top left of its bounding box, sorted.
+pub fn shape_cells(shape: &[DeltaPos]) -> Vec<(usize, usize)> {
+    let max_delta_row = shape.iter().map(DeltaPos::row).max().unwrap_or(0);
+    let max_delta_column = shape.iter().map(DeltaPos::column).max().unwrap_or(0);
+    let mut cells: Vec<(usize, usize)> = shape
+        .iter()
+        .map(|delta| {
+            (
+                max_delta_row - delta.row(),
+                max_delta_column - delta.column(),
+            )
+        })
+        .collect();
+    cells.sort_unstable();
+    cells
+}
+
+// Height and width of the bounding box of the obstacle shape.
+fn shape_size(shape: &[DeltaPos]) -> (usize, usize) {
+    let rows = shape.iter().map(DeltaPos::row);
+    let columns = shape.iter().map(DeltaPos::column);
+    (
+        rows.clone().max().unwrap_or(0) - rows.min().unwrap_or(0) + 1,
+        columns.clone().max().unwrap_or(0) - columns.min().unwrap_or(0) + 1,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transform_cells_test() {
+        let cells = [0, 1, 2, 3, 4, 5, 6, 7, 8];
+        let transformed_cells = |transform| transform_cells(3, &cells, transform).unwrap();
+
+        assert_eq!(
+            transformed_cells(Transform::MirrorHorizontal),
+            [2, 1, 0, 5, 4, 3, 8, 7, 6]
+        );
+        assert_eq!(
+            transformed_cells(Transform::MirrorVertical),
+            [6, 7, 8, 3, 4, 5, 0, 1, 2]
+        );
+        assert_eq!(
+            transformed_cells(Transform::MirrorDiagonal),
+            [0, 3, 6, 1, 4, 7, 2, 5, 8]
+        );
+        assert_eq!(
+            transformed_cells(Transform::Rotate90),
+            [6, 3, 0, 7, 4, 1, 8, 5, 2]
+        );
+        assert_eq!(
+            transformed_cells(Transform::Rotate180),
+            [8, 7, 6, 5, 4, 3, 2, 1, 0]
+        );
+        assert_eq!(
+            transformed_cells(Transform::Rotate270),
+            [2, 5, 8, 1, 4, 7, 0, 3, 6]
+        );
+        assert!(transform_cells(2, &cells, Transform::Rotate90).is_err());
+    }
+
+    #[test]
+    fn complete_test() {
+        let cells = [0, 1, 2, 3, 4, 5, 6, 7, 8];
+        assert_eq!(
+            Symmetry::MirrorHorizontal.complete(3, &cells),
+            [0, 1, 0, 3, 4, 3, 6, 7, 6]
+        );
+        assert_eq!(
+            Symmetry::MirrorDiagonal.complete(3, &cells),
+            [0, 1, 2, 1, 4, 5, 2, 5, 8]
+        );
+        assert_eq!(
+            Symmetry::Rotate180.complete(3, &cells),
+            [0, 1, 2, 3, 4, 3, 2, 1, 0]
+        );
+
+        let cells: Vec<usize> = (0..16).collect();
+        assert_eq!(
+            Symmetry::MirrorBoth.complete(4, &cells),
+            [0, 1, 1, 0, 4, 5, 5, 4, 4, 5, 5, 4, 0, 1, 1, 0]
+        );
+        assert_eq!(
+            Symmetry::Rotate90.complete(4, &cells),
+            [0, 1, 2, 0, 2, 5, 5, 1, 1, 5, 5, 2, 0, 2, 1, 0]
+        );
+    }
+
+    #[test]
+    fn image_position_test() {
+        // 2x3 obstacle missing its bottom left cell
+        let shape = [
+            DeltaPos::new(0, 0),
+            DeltaPos::new(0, 1),
+            DeltaPos::new(1, 0),
+            DeltaPos::new(1, 1),
+            DeltaPos::new(1, 2),
+        ];
+        assert_eq!(
+            shape_cells(&shape),
+            [(0, 0), (0, 1), (0, 2), (1, 1), (1, 2)]
+        );
+        assert_eq!(
+            Transform::MirrorHorizontal.transform_shape(&shape),
+            [(0, 0), (0, 1), (0, 2), (1, 0), (1, 1)]
+        );
+        // 3x2 missing its top left cell
+        let rotated_cells = Transform::Rotate90.transform_shape(&shape);
+        assert_eq!(rotated_cells, [(0, 1), (1, 0), (1, 1), (2, 0), (2, 1)]);
+
+        // the cells at rows 1-2 and columns 0-2 go to the rows 0-2 and columns 5-6
+        let rotated_shape: Vec<DeltaPos> = rotated_cells
+            .iter()
+            .map(|&(row, column)| DeltaPos::new(2 - row, 1 - column))
+            .collect();
+        let image_position = Symmetry::Rotate90.image_position(
+            8,
+            Transform::Rotate90,
+            Position::new(2, 2),
+            &shape,
+            &rotated_shape,
+        );
+        assert_eq!(image_position, Some(Position::new(2, 6)));
+    }
+}
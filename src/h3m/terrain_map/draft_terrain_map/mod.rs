@@ -1,8 +1,9 @@
 use crate::common::position::{Position, SignedDeltaPos};
-use crate::h3m::{terrain_map::map_cell::MapCell, Surface, MAX_MAP_SIZE};
+use crate::common::rng::SeededRng;
+use crate::h3m::terrain_map::{map_cell::MapCell, tile::ExistingTile, TileVariants};
+use crate::h3m::{Surface, Terrain};
 use draft_map_cell::DraftMapCell;
-use num::Integer;
-use tile_generator::{Neighborhood, TileGeneratingMode, TileGenerator};
+use tile_generator::{Neighborhood, TileGenerator};
 
 mod draft_map_cell;
 mod tile_generator;
@@ -55,59 +56,90 @@ impl DraftTerrainMap {
         ]
     }
 
-    pub fn set_tile_codes(&mut self, one_tile_water: bool) {
-        let mut generator = TileGenerator::new(one_tile_water);
-        for mode in [TileGeneratingMode::Main, TileGeneratingMode::Fallback] {
-            let is_done =
-                self.set_tile_codes_iterations_with_mode(&mut generator, mode, MAX_MAP_SIZE);
-            if !is_done {
-                panic!();
-            }
-        }
+    fn terrains(&self) -> Vec<Option<Terrain>> {
+        self.cells
+            .iter()
+            .map(|cell| Some(cell.as_ref()?.surface.terrain))
+            .collect()
     }
 
-    fn set_tile_codes_iterations_with_mode(
-        &mut self,
-        generator: &mut TileGenerator,
-        mode: TileGeneratingMode,
-        max_iter_count: usize,
-    ) -> bool {
-        for iter_index in 0..max_iter_count {
-            let was_changed = self.set_tile_codes_iteration(generator, mode, iter_index.is_odd());
-            if !was_changed {
-                return true;
-            }
-        }
-        false
+    // Whether every cell can get a tile fitting its neighbourhood.
+    #[cfg(test)]
+    pub fn has_fitting_tiles(&self, one_tile_water: bool) -> bool {
+        let generator = TileGenerator::new(
+            one_tile_water,
+            TileVariants::default(),
+            crate::common::rng::stream_rng(0, 0),
+        );
+        generator.has_fitting_tiles(self.size, &self.terrains())
     }
 
-    fn set_tile_codes_iteration(
+    // Existing tiles are kept where their tile group still fits the neighbourhood.
+    // Returns the positions of the cells no tile fits, these get the closest one.
+    pub fn set_tile_codes(
         &mut self,
-        generator: &mut TileGenerator,
-        mode: TileGeneratingMode,
-        backward_direction: bool,
-    ) -> bool {
-        let map_range = 0..(self.size * self.size);
-        let mut was_changed = false;
+        one_tile_water: bool,
+        tile_variants: &TileVariants,
+        rng: SeededRng,
+        existing_tiles: &[Option<ExistingTile>],
+    ) -> Vec<Position> {
+        let mut generator = TileGenerator::new(one_tile_water, tile_variants.clone(), rng);
+        let (candidates, unfitting_cells) =
+            generator.solve(self.size, &self.terrains(), existing_tiles);
 
-        let try_change_tile = |index| {
+        let repeat_distance = generator.repeat_distance();
+        for (index, candidate) in candidates.into_iter().enumerate() {
+            let Some(candidate) = candidate else {
+                continue;
+            };
             let neighborhood = self.neighborhood(index);
+            let distant_codes = self.distant_tile_codes(index, repeat_distance);
             if let Some(cell) = &mut self.cells[index] {
-                let tile = generator.try_generate_tile(cell, &neighborhood, mode);
-                if tile != cell.tile {
-                    was_changed = true;
-                    cell.tile = tile;
-                }
+                cell.tile = Some(generator.generate_tile(
+                    cell,
+                    candidate,
+                    &neighborhood,
+                    &distant_codes,
+                    existing_tiles[index].as_ref(),
+                ));
             }
+        }
+
+        unfitting_cells
+            .into_iter()
+            .map(|index| Position::from_index(self.size, index))
+            .collect()
+    }
+
+    // Codes of the same terrain tiles within the distance, except the neighbours.
+    fn distant_tile_codes(&self, index: usize, distance: usize) -> Vec<u8> {
+        let cell = match &self.cells[index] {
+            Some(cell) if distance > 1 => cell,
+            _ => return Vec::new(),
         };
 
-        if backward_direction {
-            map_range.rev().for_each(try_change_tile);
-        } else {
-            map_range.for_each(try_change_tile);
+        let (row, column) = (cell.position.row(), cell.position.column());
+        let mut codes = Vec::new();
+        for neighbour_row in row.saturating_sub(distance)..(row + distance + 1).min(self.size) {
+            for neighbour_column in
+                column.saturating_sub(distance)..(column + distance + 1).min(self.size)
+            {
+                if neighbour_row.abs_diff(row) <= 1 && neighbour_column.abs_diff(column) <= 1 {
+                    continue;
+                }
+                let neighbour =
+                    &self.cells[Position::new(neighbour_row, neighbour_column).index(self.size)];
+                if let Some(neighbour) = neighbour {
+                    if let (true, Some(tile)) = (
+                        neighbour.surface.terrain == cell.surface.terrain,
+                        neighbour.tile,
+                    ) {
+                        codes.push(tile.code());
+                    }
+                }
+            }
         }
-
-        was_changed
+        codes
     }
 
     pub fn into_map_cells(self) -> Vec<Option<MapCell>> {
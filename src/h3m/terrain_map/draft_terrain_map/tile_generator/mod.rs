@@ -1,50 +1,48 @@
 use super::draft_map_cell::DraftMapCell;
+use crate::common::rng::SeededRng;
+use crate::h3m::terrain_map::tile::ExistingTile;
+use crate::h3m::terrain_map::TileVariants;
 use crate::h3m::Terrain;
 use common::NEIGHBORHOOD_SIZE;
 pub use draft_tile::DraftTile;
-use draft_tile::{TerrainVisibleType, TileComposition};
-use rand::{rngs::ThreadRng, Rng};
-use std::cmp::{Eq, Ordering};
-use terrain_relation::{NeighborhoodPattern, TerrainRelation};
+use draft_tile::{TerrainVisibleType, TileComposition, TileType};
+use rand::Rng;
+use std::cmp::Ordering;
+use std::sync::OnceLock;
+use terrain_relation::TerrainRelation;
 use tile_codes_set::TileCodesSet;
-use tiles_table::{TilesGroupInfo, TilesTable};
+pub use tile_solver::TileCandidate;
+use tile_solver::{CandidatesTable, TileSolver};
+use tiles_table::TilesTable;
 
 mod common;
 mod draft_tile;
 mod terrain_relation;
 mod tile_codes_set;
+mod tile_solver;
 mod tiles_table;
 
 pub type Neighborhood = [Option<DraftMapCell>; NEIGHBORHOOD_SIZE];
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub enum TileGeneratingMode {
-    Main,
-    Fallback,
+// What a neighbour shows to the patterns: the visible terrain
+// and the tile type, if the tile is known.
+#[derive(Clone, Copy)]
+struct NeighbourLook {
+    terrain: Terrain,
+    tile_type: Option<TileType>,
 }
 
 fn is_terrain_relation_matched(
-    cell: &DraftMapCell,
-    neighbour: &Option<DraftMapCell>,
+    terrain: Terrain,
+    neighbour: &Option<NeighbourLook>,
     relation: TerrainRelation,
 ) -> bool {
     if let Some(neighbour) = neighbour {
-        let terrain = cell.surface.terrain;
-        let neighbour_terrain = neighbour
-            .tile
-            .and_then(|t| match t.terrain_visible_type() {
-                TerrainVisibleType::Diff(terrain_visible_type) => Some(terrain_visible_type),
-                _ => None,
-            })
-            .unwrap_or(neighbour.surface.terrain);
+        let neighbour_terrain = neighbour.terrain;
         match relation {
             TerrainRelation::Same | TerrainRelation::Eq => neighbour_terrain == terrain,
             TerrainRelation::SameTyped(tile_type) => {
-                if let Some(neighbour_tile) = neighbour.tile {
-                    (neighbour_terrain == terrain) && (tile_type == neighbour_tile.tile_type())
-                } else {
-                    false
-                }
+                (neighbour_terrain == terrain) && (Some(tile_type) == neighbour.tile_type)
             }
 
             TerrainRelation::Other(category) | TerrainRelation::Diff(category) => {
@@ -70,21 +68,10 @@ fn is_terrain_relation_matched(
     }
 }
 
-fn is_neighborhood_pattern_matched(
-    cell: &DraftMapCell,
-    neighborhood: &Neighborhood,
-    neighborhood_pattern: &NeighborhoodPattern,
-) -> bool {
-    for (neighbour, &relation) in neighborhood.iter().zip(neighborhood_pattern) {
-        if !is_terrain_relation_matched(cell, neighbour, relation) {
-            return false;
-        }
-    }
-    true
-}
-
 #[rustfmt::skip]
-fn vertical_mirroring_neighborhood(neighborhood: &Neighborhood) -> Neighborhood {
+fn vertical_mirroring_neighborhood<T: Copy>(
+    neighborhood: &[T; NEIGHBORHOOD_SIZE],
+) -> [T; NEIGHBORHOOD_SIZE] {
     [
         neighborhood[5], neighborhood[6], neighborhood[7],
         neighborhood[3],                  neighborhood[4],
@@ -93,7 +80,9 @@ fn vertical_mirroring_neighborhood(neighborhood: &Neighborhood) -> Neighborhood
 }
 
 #[rustfmt::skip]
-fn horizontal_mirroring_neighborhood(neighborhood: &Neighborhood) -> Neighborhood {
+fn horizontal_mirroring_neighborhood<T: Copy>(
+    neighborhood: &[T; NEIGHBORHOOD_SIZE],
+) -> [T; NEIGHBORHOOD_SIZE] {
     [
         neighborhood[2], neighborhood[1], neighborhood[0],
         neighborhood[4],                  neighborhood[3],
@@ -101,11 +90,11 @@ fn horizontal_mirroring_neighborhood(neighborhood: &Neighborhood) -> Neighborhoo
     ]
 }
 
-fn mirroring_neighborhood(
-    neighborhood: &Neighborhood,
+fn mirroring_neighborhood<T: Copy>(
+    neighborhood: &[T; NEIGHBORHOOD_SIZE],
     vertical: bool,
     horizontal: bool,
-) -> Neighborhood {
+) -> [T; NEIGHBORHOOD_SIZE] {
     match (vertical, horizontal) {
         (true, true) => {
             vertical_mirroring_neighborhood(&horizontal_mirroring_neighborhood(neighborhood))
@@ -116,90 +105,108 @@ fn mirroring_neighborhood(
     }
 }
 
+// The tables only depend on the one tile water option, they are built once.
+fn tables(one_tile_water: bool) -> &'static (TilesTable, CandidatesTable) {
+    static TABLES: [OnceLock<(TilesTable, CandidatesTable)>; 2] =
+        [OnceLock::new(), OnceLock::new()];
+    TABLES[one_tile_water as usize].get_or_init(|| {
+        let tiles_table = TilesTable::new(one_tile_water);
+        let candidates_table = CandidatesTable::new(&tiles_table);
+        (tiles_table, candidates_table)
+    })
+}
+
+// Codes of the same terrain tiles around the cell.
+struct ExcludedTileCodes {
+    all: Vec<u8>,
+    neighbours: Vec<u8>,
+}
+
 pub struct TileGenerator {
-    tiles_table: TilesTable,
-    rng: ThreadRng,
+    tiles_table: &'static TilesTable,
+    candidates_table: &'static CandidatesTable,
+    tile_variants: TileVariants,
+    rng: SeededRng,
 }
 
 impl TileGenerator {
-    pub fn new(one_tile_water: bool) -> TileGenerator {
+    pub fn new(one_tile_water: bool, tile_variants: TileVariants, rng: SeededRng) -> TileGenerator {
+        let (tiles_table, candidates_table) = tables(one_tile_water);
         TileGenerator {
-            tiles_table: TilesTable::new(one_tile_water),
-            rng: rand::thread_rng(),
+            tiles_table,
+            candidates_table,
+            tile_variants,
+            rng,
         }
     }
 
-    fn try_generate_code(
-        &mut self,
-        cell: &DraftMapCell,
-        neighborhood: &Neighborhood,
-        excluded_tile_codes: &[u8],
-        composition: TileComposition,
-    ) -> Option<(u8, &TilesGroupInfo)> {
-        let mut generate_code = |tile_codes_set: &TileCodesSet| {
-            tile_codes_set
-                .random_not_excluded_code(excluded_tile_codes, &mut self.rng)
-                .unwrap_or_else(|| tile_codes_set.random_code(&mut self.rng))
-        };
-        for tiles_group_info in self.tiles_table.terrain_tile_groups(cell.surface.terrain) {
-            if tiles_group_info.composition() != composition {
-                continue;
-            }
-            for pattern in tiles_group_info.patterns() {
-                if is_neighborhood_pattern_matched(cell, neighborhood, pattern) {
-                    return Some((generate_code(tiles_group_info.codes()), tiles_group_info));
-                }
-            }
-        }
-        None
+    pub fn repeat_distance(&self) -> usize {
+        self.tile_variants.repeat_distance()
     }
 
-    fn excluded_tile_codes(cell: &DraftMapCell, neighborhood: &Neighborhood) -> Vec<u8> {
-        neighborhood
+    // Candidate of the code in the terrain tile groups, main groups first.
+    fn existing_candidate(
+        &self,
+        terrain: Terrain,
+        existing_tile: &ExistingTile,
+    ) -> Option<TileCandidate> {
+        let tile_groups = self.tiles_table.terrain_tile_groups(terrain);
+        let group_index = [TileComposition::Main, TileComposition::Fallback]
             .iter()
-            .filter_map(|c| c.as_ref())
-            .filter(|neighbour| neighbour.surface.terrain == cell.surface.terrain)
-            .filter_map(|c| Some(c.tile?.code()))
-            .collect()
+            .find_map(|&composition| {
+                tile_groups.iter().position(|tiles_group_info| {
+                    tiles_group_info.composition() == composition
+                        && tiles_group_info.codes().contains(existing_tile.code)
+                })
+            })?;
+
+        Some(TileCandidate {
+            group_index,
+            vertical_mirroring: existing_tile.vertical_mirroring,
+            horizontal_mirroring: existing_tile.horizontal_mirroring,
+        })
     }
 
-    fn try_generate_tile_with_composition(
-        &mut self,
+    // Tile group and mirroring of each cell with a terrain and the indexes of
+    // the cells no tile fits, these get the closest one. Existing tiles are kept
+    // where their group still fits.
+    pub fn solve(
+        &self,
+        size: usize,
+        terrains: &[Option<Terrain>],
+        existing_tiles: &[Option<ExistingTile>],
+    ) -> (Vec<Option<TileCandidate>>, Vec<usize>) {
+        let preferred: Vec<Option<TileCandidate>> = terrains
+            .iter()
+            .zip(existing_tiles)
+            .map(|(terrain, existing_tile)| {
+                self.existing_candidate((*terrain)?, existing_tile.as_ref()?)
+            })
+            .collect();
+        TileSolver::new(self.candidates_table, size, terrains).solve(&preferred)
+    }
+
+    // Whether every cell with a terrain can get a tile fitting its neighbourhood.
+    #[cfg(test)]
+    pub fn has_fitting_tiles(&self, size: usize, terrains: &[Option<Terrain>]) -> bool {
+        TileSolver::new(self.candidates_table, size, terrains).has_solution()
+    }
+
+    fn excluded_tile_codes(
         cell: &DraftMapCell,
         neighborhood: &Neighborhood,
-        composition: TileComposition,
-    ) -> Option<DraftTile> {
-        let excluded_tile_codes = TileGenerator::excluded_tile_codes(cell, neighborhood);
-        for horizontal_mirroring in [false, true] {
-            for vertical_mirroring in [false, true] {
-                let code_info = if (false, false) == (vertical_mirroring, horizontal_mirroring) {
-                    self.try_generate_code(cell, neighborhood, &excluded_tile_codes, composition)
-                } else {
-                    let mirroring_neighborhood = mirroring_neighborhood(
-                        neighborhood,
-                        vertical_mirroring,
-                        horizontal_mirroring,
-                    );
-                    self.try_generate_code(
-                        cell,
-                        &mirroring_neighborhood,
-                        &excluded_tile_codes,
-                        composition,
-                    )
-                };
-                if let Some((code, tiles_group_info)) = code_info {
-                    return Some(DraftTile::new(
-                        tiles_group_info,
-                        composition,
-                        code,
-                        vertical_mirroring,
-                        horizontal_mirroring,
-                        neighborhood,
-                    ));
-                }
-            }
+        distant_codes: &[u8],
+    ) -> ExcludedTileCodes {
+        let neighbours: Vec<u8> = neighborhood
+            .iter()
+            .filter_map(|c| c.as_ref())
+            .filter(|neighbour| neighbour.surface.terrain == cell.surface.terrain)
+            .filter_map(|c| Some(c.tile?.code()))
+            .collect();
+        ExcludedTileCodes {
+            all: [&neighbours[..], distant_codes].concat(),
+            neighbours,
         }
-        None
     }
 
     fn randomize_mirroring(&mut self, neighborhood: &Neighborhood, tile: &mut DraftTile) {
@@ -315,59 +322,55 @@ impl TileGenerator {
         tile.set_horizontal_mirroring(horizontal_mirroring);
     }
 
-    pub fn try_generate_tile_impl(
+    // Tile of the solved candidate with a code not repeated around, the existing
+    // tile is kept if it is of the candidate.
+    pub fn generate_tile(
         &mut self,
         cell: &DraftMapCell,
+        candidate: TileCandidate,
         neighborhood: &Neighborhood,
-        mode: TileGeneratingMode,
-    ) -> Option<DraftTile> {
-        let mut tile = if let Some(tile) =
-            self.try_generate_tile_with_composition(cell, neighborhood, TileComposition::Main)
-        {
-            tile
-        } else if mode == TileGeneratingMode::Fallback {
-            self.try_generate_tile_with_composition(cell, neighborhood, TileComposition::Fallback)?
-        } else {
-            return None;
-        };
-        self.randomize_mirroring(neighborhood, &mut tile);
-        Some(tile)
-    }
-
-    fn need_change_tile(
-        &self,
-        cell: &DraftMapCell,
-        neighborhood: &Neighborhood,
-        mode: TileGeneratingMode,
-    ) -> bool {
-        if let Some(tile) = cell.tile {
-            let is_forced_fallback_tile = matches!(
-                (mode, tile.composition()),
-                (TileGeneratingMode::Fallback, TileComposition::Fallback)
-            );
-
-            if is_forced_fallback_tile {
-                return false;
-            }
-
-            if !tile.is_neighborhood_changed(neighborhood) {
-                return false;
+        distant_codes: &[u8],
+        existing_tile: Option<&ExistingTile>,
+    ) -> DraftTile {
+        let terrain = cell.surface.terrain;
+        if let Some(existing_tile) = existing_tile {
+            if self.existing_candidate(terrain, existing_tile) == Some(candidate) {
+                let tiles_group_info =
+                    &self.tiles_table.terrain_tile_groups(terrain)[candidate.group_index];
+                return DraftTile::new(
+                    tiles_group_info,
+                    existing_tile.code,
+                    existing_tile.vertical_mirroring,
+                    existing_tile.horizontal_mirroring,
+                );
             }
         }
-        true
-    }
 
-    pub fn try_generate_tile(
-        &mut self,
-        cell: &DraftMapCell,
-        neighborhood: &Neighborhood,
-        mode: TileGeneratingMode,
-    ) -> Option<DraftTile> {
-        if self.need_change_tile(cell, neighborhood, mode) {
-            self.try_generate_tile_impl(cell, neighborhood, mode)
-        } else {
-            assert!(cell.tile.is_some());
-            cell.tile
-        }
+        let excluded_tile_codes =
+            TileGenerator::excluded_tile_codes(cell, neighborhood, distant_codes);
+        let weighting = self.tile_variants.weighting(terrain);
+        let tiles_group_info =
+            &self.tiles_table.terrain_tile_groups(terrain)[candidate.group_index];
+        // the distant codes are given up first when every code is excluded
+        let tile_codes_set = tiles_group_info.codes();
+        let code = tile_codes_set
+            .random_not_excluded_code(&excluded_tile_codes.all, weighting, &mut self.rng)
+            .or_else(|| {
+                tile_codes_set.random_not_excluded_code(
+                    &excluded_tile_codes.neighbours,
+                    weighting,
+                    &mut self.rng,
+                )
+            })
+            .unwrap_or_else(|| tile_codes_set.random_code(weighting, &mut self.rng));
+
+        let mut tile = DraftTile::new(
+            tiles_group_info,
+            code,
+            candidate.vertical_mirroring,
+            candidate.horizontal_mirroring,
+        );
+        self.randomize_mirroring(neighborhood, &mut tile);
+        tile
     }
 }
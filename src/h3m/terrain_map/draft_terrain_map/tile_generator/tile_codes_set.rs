@@ -1,11 +1,22 @@
 use crate::common::index_multiset::IndexMultiset;
-use rand::rngs::ThreadRng;
+use crate::common::rng::SeededRng;
+use crate::h3m::terrain_map::VariantWeighting;
 use std::ops::RangeInclusive;
 
+// The first subset holds the plain variants, the next ones the decorated variants.
 #[derive(Clone)]
 pub struct TileCodesSet {
     subsets: Vec<IndexMultiset<u8>>,
-    subset_indexes: IndexMultiset<usize>,
+    subset_frequencies: Vec<usize>,
+    subset_indexes: [IndexMultiset<usize>; 3], // by weighting
+}
+
+fn weighting_index(weighting: VariantWeighting) -> usize {
+    match weighting {
+        VariantWeighting::Plain => 0,
+        VariantWeighting::Balanced => 1,
+        VariantWeighting::Decorated => 2,
+    }
 }
 
 impl TileCodesSet {
@@ -20,14 +31,17 @@ impl TileCodesSet {
     pub fn with_frequency(codes: RangeInclusive<u8>, frequency: usize) -> TileCodesSet {
         TileCodesSet {
             subsets: Vec::new(),
-            subset_indexes: IndexMultiset::new(),
+            subset_frequencies: Vec::new(),
+            subset_indexes: [
+                IndexMultiset::new(),
+                IndexMultiset::new(),
+                IndexMultiset::new(),
+            ],
         }
         .add_codes(codes, frequency)
     }
 
     pub fn add_codes(mut self, codes: RangeInclusive<u8>, frequency: usize) -> TileCodesSet {
-        let new_subset_index = self.subsets.len();
-
         self.subsets.push({
             let mut subset = IndexMultiset::new();
             for code in codes {
@@ -35,19 +49,39 @@ impl TileCodesSet {
             }
             subset
         });
+        self.subset_frequencies.push(frequency);
+        self.set_subset_indexes();
+        self
+    }
 
-        if frequency > 0 {
-            self.subset_indexes.add_index(new_subset_index, frequency);
+    // Plain takes the first subset only, decorated reverses the subset frequencies.
+    fn set_subset_indexes(&mut self) {
+        let frequencies = &self.subset_frequencies;
+        let mut plain = IndexMultiset::new();
+        let mut balanced = IndexMultiset::new();
+        let mut decorated = IndexMultiset::new();
+        for (subset_index, &frequency) in frequencies.iter().enumerate() {
+            balanced.add_index(subset_index, frequency);
+            decorated.add_index(
+                subset_index,
+                frequencies[frequencies.len() - 1 - subset_index],
+            );
         }
-        self
+        plain.add_index(0, 1);
+        self.subset_indexes = [plain, balanced, decorated];
+    }
+
+    pub fn contains(&self, code: u8) -> bool {
+        self.subsets.iter().any(|subset| subset.contains(code))
     }
 
     pub fn random_not_excluded_code(
         &self,
         excluded_codes: &[u8],
-        rng: &mut ThreadRng,
+        weighting: VariantWeighting,
+        rng: &mut SeededRng,
     ) -> Option<u8> {
-        let subset_index = self.subset_indexes.random_index(rng).unwrap();
+        let subset_index = self.random_subset_index(weighting, rng);
         let mut subset = self.subsets[subset_index].clone();
 
         for &code in excluded_codes {
@@ -56,9 +90,47 @@ impl TileCodesSet {
         subset.random_index(rng)
     }
 
-    pub fn random_code(&self, rng: &mut ThreadRng) -> u8 {
-        let subset_index = self.subset_indexes.random_index(rng).unwrap();
+    pub fn random_code(&self, weighting: VariantWeighting, rng: &mut SeededRng) -> u8 {
+        let subset_index = self.random_subset_index(weighting, rng);
         let subset = &self.subsets[subset_index];
         subset.random_index(rng).unwrap()
     }
+
+    fn random_subset_index(&self, weighting: VariantWeighting, rng: &mut SeededRng) -> usize {
+        self.subset_indexes[weighting_index(weighting)]
+            .random_index(rng)
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::rng;
+
+    #[test]
+    fn variant_weighting_test() {
+        let codes = TileCodesSet::with_frequency(0..=7, 4).add_codes(8..=23, 1);
+        let mut rng = rng::stream_rng(0, 0);
+        let decorated_count = |weighting, rng: &mut SeededRng| {
+            (0..1000)
+                .filter(|_| codes.random_code(weighting, rng) >= 8)
+                .count()
+        };
+
+        assert_eq!(decorated_count(VariantWeighting::Plain, &mut rng), 0);
+        let balanced_count = decorated_count(VariantWeighting::Balanced, &mut rng);
+        let decorated_heavy_count = decorated_count(VariantWeighting::Decorated, &mut rng);
+        assert!((100..300).contains(&balanced_count));
+        assert!((700..900).contains(&decorated_heavy_count));
+
+        assert_eq!(
+            codes.random_not_excluded_code(
+                &[0, 1, 2, 3, 4, 5, 6],
+                VariantWeighting::Plain,
+                &mut rng
+            ),
+            Some(7)
+        );
+    }
 }
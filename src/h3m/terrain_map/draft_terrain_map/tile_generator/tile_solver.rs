@@ -0,0 +1,769 @@
+use super::common::NEIGHBORHOOD_SIZE;
+use super::draft_tile::{TerrainVisibleType, TileComposition, TileType};
+use super::terrain_relation::TerrainRelation;
+use super::tiles_table::{TilesGroupInfo, TilesTable};
+use super::{is_terrain_relation_matched, mirroring_neighborhood, NeighbourLook};
+use crate::common::position::{Position, SignedDeltaPos};
+use crate::h3m::Terrain;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::ops::Index;
+use strum::{EnumCount, IntoEnumIterator};
+
+// Candidates of a cell are the tile groups of its terrain with each mirroring,
+// the candidate index is mirroring index * groups count + group index.
+type CandidateSet = u128;
+
+// A look is what a neighbour shows to the patterns: the visible terrain with
+// the tile type, or no neighbour at all.
+type LookSet = u128;
+
+const NO_NEIGHBOUR_LOOK: usize = Terrain::COUNT * TileType::COUNT;
+const ANY_LOOK: LookSet = LookSet::MAX;
+
+// (vertical, horizontal), in the order the mirrorings are preferred.
+const MIRRORINGS: [(bool, bool); 4] = [(false, false), (true, false), (false, true), (true, true)];
+
+#[rustfmt::skip]
+const NEIGHBOUR_DELTAS: [(isize, isize); NEIGHBORHOOD_SIZE] = [
+    (-1, -1), (-1, 0), (-1, 1),
+    (0, -1),           (0, 1),
+    (1, -1),  (1, 0),  (1, 1),
+];
+
+fn look_index(terrain: Terrain, tile_type: TileType) -> usize {
+    terrain as usize * TileType::COUNT + tile_type as usize
+}
+
+fn candidates_iter(mut candidates: CandidateSet) -> impl Iterator<Item = usize> {
+    std::iter::from_fn(move || {
+        if candidates == 0 {
+            return None;
+        }
+        let index = candidates.trailing_zeros() as usize;
+        candidates &= candidates - 1;
+        Some(index)
+    })
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TileCandidate {
+    pub group_index: usize,
+    pub vertical_mirroring: bool,
+    pub horizontal_mirroring: bool,
+}
+
+// Candidates of the cells of one terrain.
+pub struct TerrainCandidates {
+    groups_count: usize,
+    all: CandidateSet,
+    main: CandidateSet,
+    main_same_looking: CandidateSet, // main tiles showing the cell terrain
+    by_priority: Vec<usize>,         // main groups first, then by mirroring and group order
+    looks: Vec<usize>,
+    patterns: Vec<Vec<[LookSet; NEIGHBORHOOD_SIZE]>>, // looks allowed by each pattern of each group
+}
+
+impl TerrainCandidates {
+    fn new(terrain: Terrain, tile_groups: &[TilesGroupInfo]) -> TerrainCandidates {
+        let groups_count = tile_groups.len();
+        let candidates_count = MIRRORINGS.len() * groups_count;
+        assert!(candidates_count <= CandidateSet::BITS as usize);
+
+        let is_main = |candidate: usize| {
+            tile_groups[candidate % groups_count].composition() == TileComposition::Main
+        };
+        let mut by_priority: Vec<usize> = (0..candidates_count).collect();
+        by_priority.sort_by_key(|&candidate| !is_main(candidate));
+
+        let is_same_looking = |candidate: usize| {
+            !matches!(
+                tile_groups[candidate % groups_count].terrain_visible_type(),
+                TerrainVisibleType::Diff(_)
+            )
+        };
+        let looks = (0..candidates_count)
+            .map(|candidate| {
+                let tiles_group_info = &tile_groups[candidate % groups_count];
+                let visible_terrain = match tiles_group_info.terrain_visible_type() {
+                    TerrainVisibleType::Diff(visible_terrain) => visible_terrain,
+                    _ => terrain,
+                };
+                look_index(visible_terrain, tiles_group_info.tile_type())
+            })
+            .collect();
+
+        // the same relations repeat in many patterns
+        let mut relation_looks: Vec<(TerrainRelation, LookSet)> = Vec::new();
+        let mut allowed_looks = |relation| {
+            if let Some(&(_, looks)) = relation_looks.iter().find(|(r, _)| *r == relation) {
+                return looks;
+            }
+            let mut looks = 0;
+            for look_terrain in Terrain::iter() {
+                for tile_type in TileType::iter() {
+                    let neighbour = Some(NeighbourLook {
+                        terrain: look_terrain,
+                        tile_type: Some(tile_type),
+                    });
+                    if is_terrain_relation_matched(terrain, &neighbour, relation) {
+                        looks |= 1 << look_index(look_terrain, tile_type);
+                    }
+                }
+            }
+            if is_terrain_relation_matched(terrain, &None, relation) {
+                looks |= 1 << NO_NEIGHBOUR_LOOK;
+            }
+            relation_looks.push((relation, looks));
+            looks
+        };
+        let patterns = tile_groups
+            .iter()
+            .map(|tiles_group_info| {
+                tiles_group_info
+                    .patterns()
+                    .iter()
+                    .map(|pattern| pattern.map(&mut allowed_looks))
+                    .collect()
+            })
+            .collect();
+
+        TerrainCandidates {
+            groups_count,
+            all: (0..candidates_count).fold(0, |all, candidate| all | 1 << candidate),
+            main: (0..candidates_count)
+                .filter(|&candidate| is_main(candidate))
+                .fold(0, |main, candidate| main | 1 << candidate),
+            main_same_looking: (0..candidates_count)
+                .filter(|&candidate| is_main(candidate) && is_same_looking(candidate))
+                .fold(0, |main, candidate| main | 1 << candidate),
+            by_priority,
+            looks,
+            patterns,
+        }
+    }
+
+    fn candidate_index(&self, candidate: &TileCandidate) -> usize {
+        let mirroring = MIRRORINGS
+            .iter()
+            .position(|&mirroring| {
+                mirroring == (candidate.vertical_mirroring, candidate.horizontal_mirroring)
+            })
+            .unwrap();
+        mirroring * self.groups_count + candidate.group_index
+    }
+
+    fn candidate(&self, index: usize) -> TileCandidate {
+        let (vertical_mirroring, horizontal_mirroring) = MIRRORINGS[index / self.groups_count];
+        TileCandidate {
+            group_index: index % self.groups_count,
+            vertical_mirroring,
+            horizontal_mirroring,
+        }
+    }
+
+    // Relations of the best candidate pattern matched by some looks of the neighbours.
+    fn matched_relations_count(
+        &self,
+        candidate: usize,
+        neighbour_looks: &[LookSet; NEIGHBORHOOD_SIZE],
+    ) -> usize {
+        let (vertical, horizontal) = MIRRORINGS[candidate / self.groups_count];
+        let looks = mirroring_neighborhood(neighbour_looks, vertical, horizontal);
+        self.patterns[candidate % self.groups_count]
+            .iter()
+            .map(|pattern| {
+                pattern
+                    .iter()
+                    .zip(&looks)
+                    .filter(|(allowed, look)| *allowed & *look != 0)
+                    .count()
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    // Candidates among the given ones with a pattern matched by some looks of every neighbour.
+    fn supported(
+        &self,
+        candidates: CandidateSet,
+        neighbour_looks: &[LookSet; NEIGHBORHOOD_SIZE],
+    ) -> CandidateSet {
+        let mirrored_looks = MIRRORINGS.map(|(vertical, horizontal)| {
+            mirroring_neighborhood(neighbour_looks, vertical, horizontal)
+        });
+        candidates_iter(candidates)
+            .filter(|&candidate| {
+                let looks = &mirrored_looks[candidate / self.groups_count];
+                self.patterns[candidate % self.groups_count]
+                    .iter()
+                    .any(|pattern| {
+                        pattern
+                            .iter()
+                            .zip(looks)
+                            .all(|(allowed, look)| allowed & look != 0)
+                    })
+            })
+            .fold(0, |supported, candidate| supported | 1 << candidate)
+    }
+}
+
+// Candidates of the cells of each terrain, built once for a tiles table.
+pub struct CandidatesTable {
+    inner: Vec<TerrainCandidates>, // by terrain
+}
+
+impl CandidatesTable {
+    pub fn new(tiles_table: &TilesTable) -> CandidatesTable {
+        CandidatesTable {
+            inner: Terrain::iter()
+                .map(|terrain| {
+                    TerrainCandidates::new(terrain, tiles_table.terrain_tile_groups(terrain))
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Index<Terrain> for CandidatesTable {
+    type Output = TerrainCandidates;
+
+    fn index(&self, terrain: Terrain) -> &TerrainCandidates {
+        &self.inner[terrain as usize]
+    }
+}
+
+// Preferences of the candidates, from the most preferred.
+const MAIN_SAME_LOOKING: &[fn(&TerrainCandidates) -> CandidateSet] = &[
+    |candidates| candidates.main_same_looking,
+    |candidates| candidates.main,
+];
+const MAIN: &[fn(&TerrainCandidates) -> CandidateSet] = &[|candidates| candidates.main];
+
+// Cells around a given up cell searched again for candidates.
+const REPAIR_DISTANCE: usize = 2;
+// Decisions of the search around a given up cell.
+const REPAIR_DECISIONS_COUNT: usize = 256;
+
+// Assigns a candidate to every cell by constraint propagation: each cell keeps
+// the candidates supported by the possible candidates of its neighbours. Cells
+// are decided one by one, those which still may get a main tile first, and a
+// decision leading to an empty cell is revoked and its candidate dropped. A cell
+// left with no candidate is given up and matches anything afterwards. Then the
+// cells around each given up cell are searched through for candidates fitting
+// together, within `REPAIR_DECISIONS_COUNT` decisions, the cells still given up
+// get the closest candidate. Every step drops a candidate or decides a cell and
+// the searches are limited, so the solver terminates.
+pub struct TileSolver<'a> {
+    size: usize,
+    terrains: &'a [Option<Terrain>],
+    candidates_table: &'a CandidatesTable,
+    domains: Vec<CandidateSet>,
+    is_decided: Vec<bool>,
+    is_given_up: Vec<bool>,
+    trail: Vec<(usize, CandidateSet)>,
+    queue: VecDeque<usize>,
+    is_queued: Vec<bool>,
+    heap: BinaryHeap<Reverse<(bool, u32, usize)>>,
+}
+
+impl<'a> TileSolver<'a> {
+    pub fn new(
+        candidates_table: &'a CandidatesTable,
+        size: usize,
+        terrains: &'a [Option<Terrain>],
+    ) -> TileSolver<'a> {
+        let domains = terrains
+            .iter()
+            .map(|terrain| terrain.map_or(0, |terrain| candidates_table[terrain].all))
+            .collect();
+
+        TileSolver {
+            size,
+            terrains,
+            candidates_table,
+            domains,
+            is_decided: vec![false; terrains.len()],
+            is_given_up: vec![false; terrains.len()],
+            trail: Vec::new(),
+            queue: VecDeque::new(),
+            is_queued: vec![false; terrains.len()],
+            heap: BinaryHeap::new(),
+        }
+    }
+
+    fn neighbours(&self, index: usize) -> [Option<usize>; NEIGHBORHOOD_SIZE] {
+        let position = Position::from_index(self.size, index);
+        NEIGHBOUR_DELTAS.map(|(delta_row, delta_column)| {
+            let neighbour_position = position.checked_apply(
+                self.size,
+                self.size,
+                &SignedDeltaPos::new(delta_row, delta_column),
+            )?;
+            Some(neighbour_position.index(self.size))
+        })
+    }
+
+    // Looks of the cell candidates within the first preference it has candidates of.
+    fn looks(
+        &self,
+        index: Option<usize>,
+        preferences: &[fn(&TerrainCandidates) -> CandidateSet],
+    ) -> LookSet {
+        let (index, terrain) = match index.and_then(|index| Some((index, self.terrains[index]?))) {
+            Some(cell) => cell,
+            None => return 1 << NO_NEIGHBOUR_LOOK,
+        };
+        if self.is_given_up[index] {
+            return ANY_LOOK;
+        }
+        let terrain_candidates = &self.candidates_table[terrain];
+        let domain = preferences
+            .iter()
+            .map(|preferred| self.domains[index] & preferred(terrain_candidates))
+            .find(|&preferred_domain| preferred_domain != 0)
+            .unwrap_or(self.domains[index]);
+        candidates_iter(domain).fold(0, |looks, candidate| {
+            looks | 1 << terrain_candidates.looks[candidate]
+        })
+    }
+
+    // Candidates among the given ones of the cell fitting the looks of the neighbours
+    // within the preferences.
+    fn supported(
+        &self,
+        index: usize,
+        candidates: CandidateSet,
+        preferences: &[fn(&TerrainCandidates) -> CandidateSet],
+    ) -> CandidateSet {
+        let terrain = match self.terrains[index] {
+            Some(terrain) => terrain,
+            None => return 0,
+        };
+        let neighbour_looks = self
+            .neighbours(index)
+            .map(|neighbour| self.looks(neighbour, preferences));
+        self.candidates_table[terrain].supported(candidates, &neighbour_looks)
+    }
+
+    fn is_open(&self, index: usize) -> bool {
+        self.terrains[index].is_some() && !self.is_decided[index] && !self.is_given_up[index]
+    }
+
+    fn push_to_heap(&mut self, index: usize) {
+        if let Some(terrain) = self.terrains[index] {
+            let domain = self.domains[index];
+            let has_main = domain & self.candidates_table[terrain].main != 0;
+            self.heap
+                .push(Reverse((!has_main, domain.count_ones(), index)));
+        }
+    }
+
+    fn set_domain(&mut self, index: usize, domain: CandidateSet) {
+        self.trail.push((index, self.domains[index]));
+        self.domains[index] = domain;
+        self.push_to_heap(index);
+        // decided neighbours are revised too, they may lose the support
+        for neighbour in self.neighbours(index).into_iter().flatten() {
+            let is_revised = self.terrains[neighbour].is_some() && !self.is_given_up[neighbour];
+            if is_revised && !self.is_queued[neighbour] {
+                self.is_queued[neighbour] = true;
+                self.queue.push_back(neighbour);
+            }
+        }
+    }
+
+    // Drops the candidates of the cell without support, returns false if none is left.
+    fn revise(&mut self, index: usize) -> bool {
+        if self.terrains[index].is_none() {
+            return true;
+        }
+        let domain = self.supported(index, self.domains[index], &[]);
+        if domain != self.domains[index] {
+            self.set_domain(index, domain);
+        }
+        domain != 0
+    }
+
+    // With `give_up` the cells left without candidates are given up,
+    // otherwise the first such cell stops the propagation.
+    fn propagate(&mut self, give_up: bool) -> bool {
+        while let Some(index) = self.queue.pop_front() {
+            self.is_queued[index] = false;
+            if self.is_given_up[index] || self.revise(index) {
+                continue;
+            }
+            if give_up {
+                self.is_given_up[index] = true;
+            } else {
+                self.clear_queue();
+                return false;
+            }
+        }
+        true
+    }
+
+    fn clear_queue(&mut self) {
+        for index in self.queue.drain(..) {
+            self.is_queued[index] = false;
+        }
+    }
+
+    fn rollback(&mut self, checkpoint: usize) {
+        while self.trail.len() > checkpoint {
+            let (index, domain) = self.trail.pop().unwrap();
+            self.domains[index] = domain;
+            self.push_to_heap(index);
+        }
+    }
+
+    // Decides the cell on the candidate unless it empties another cell.
+    fn try_decide(&mut self, index: usize, candidate: usize) -> bool {
+        self.trail.clear();
+        self.set_domain(index, 1 << candidate);
+        if self.propagate(false) {
+            self.is_decided[index] = true;
+            true
+        } else {
+            self.rollback(0);
+            false
+        }
+    }
+
+    // The candidate fitting the neighbours showing their own terrain with main
+    // tiles is preferred, then the one fitting the neighbours with main tiles.
+    fn decide(&mut self, index: usize) {
+        let terrain = self.terrains[index].unwrap();
+        let domain = self.domains[index];
+        // the propagation is already done for a single candidate
+        if domain.count_ones() == 1 {
+            self.is_decided[index] = true;
+            return;
+        }
+        let mut preferred_domains = [MAIN_SAME_LOOKING, MAIN]
+            .map(|preferences| self.supported(index, domain, preferences))
+            .to_vec();
+        preferred_domains.push(domain);
+
+        let by_priority = &self.candidates_table[terrain].by_priority;
+        let candidate = preferred_domains
+            .iter()
+            .find_map(|&preferred_domain| {
+                by_priority
+                    .iter()
+                    .find(|&&candidate| preferred_domain & (1 << candidate) != 0)
+            })
+            .copied()
+            .unwrap();
+
+        if !self.try_decide(index, candidate) {
+            let domain = domain & !(1 << candidate);
+            self.set_domain(index, domain);
+            if domain == 0 {
+                self.is_given_up[index] = true;
+            }
+            self.propagate(true);
+        }
+    }
+
+    // Decides the looks of the cells among the given ones by a depth-first search,
+    // each decision checked by the propagation, within the decisions count of
+    // the budget. The neighbours only see the look of a cell, so once every look
+    // is decided any candidate left fits.
+    fn search(&mut self, cells: &[usize], budget: &mut usize) -> bool {
+        let Some(&index) = cells
+            .iter()
+            .find(|&&index| self.looks(Some(index), &[]).count_ones() > 1)
+        else {
+            return true;
+        };
+        let terrain_candidates = &self.candidates_table[self.terrains[index].unwrap()];
+        let domain = self.domains[index];
+        let mut tried_looks: LookSet = 0;
+        for &candidate in &terrain_candidates.by_priority {
+            let look = terrain_candidates.looks[candidate];
+            if domain & (1 << candidate) == 0 || tried_looks & (1 << look) != 0 {
+                continue;
+            }
+            tried_looks |= 1 << look;
+            if *budget == 0 {
+                return false;
+            }
+            *budget -= 1;
+            let look_domain = candidates_iter(domain)
+                .filter(|&candidate| terrain_candidates.looks[candidate] == look)
+                .fold(0, |look_domain, candidate| look_domain | 1 << candidate);
+            let checkpoint = self.trail.len();
+            self.set_domain(index, look_domain);
+            if self.propagate(false) && self.search(cells, budget) {
+                return true;
+            }
+            self.rollback(checkpoint);
+        }
+        false
+    }
+
+    // Searches through the cells around the given up cell for candidates fitting
+    // together and with the cells further, which keep theirs.
+    fn repair(&mut self, index: usize) {
+        let position = Position::from_index(self.size, index);
+        let rows = position.row().saturating_sub(REPAIR_DISTANCE)
+            ..(position.row() + REPAIR_DISTANCE + 1).min(self.size);
+        let columns = position.column().saturating_sub(REPAIR_DISTANCE)
+            ..(position.column() + REPAIR_DISTANCE + 1).min(self.size);
+        let cells: Vec<usize> = rows
+            .flat_map(|row| columns.clone().map(move |column| (row, column)))
+            .map(|(row, column)| Position::new(row, column).index(self.size))
+            .filter(|&cell| self.terrains[cell].is_some())
+            .collect();
+        let given_up_cells: Vec<usize> = cells
+            .iter()
+            .copied()
+            .filter(|&cell| self.is_given_up[cell])
+            .collect();
+
+        self.trail.clear();
+        for &cell in &cells {
+            self.is_given_up[cell] = false;
+            let all = self.candidates_table[self.terrains[cell].unwrap()].all;
+            self.set_domain(cell, all);
+        }
+        for &cell in &cells {
+            if !self.is_queued[cell] {
+                self.is_queued[cell] = true;
+                self.queue.push_back(cell);
+            }
+        }
+        let mut budget = REPAIR_DECISIONS_COUNT;
+        if !(self.propagate(false) && self.search(&cells, &mut budget)) {
+            self.rollback(0);
+            for cell in given_up_cells {
+                self.is_given_up[cell] = true;
+            }
+            return;
+        }
+        // the candidates of a look fit alike, the first by priority is taken
+        for cell in cells {
+            let domain = self.domains[cell];
+            let terrain_candidates = &self.candidates_table[self.terrains[cell].unwrap()];
+            let candidate = terrain_candidates
+                .by_priority
+                .iter()
+                .find(|&&candidate| domain & (1 << candidate) != 0)
+                .unwrap();
+            self.domains[cell] = 1 << candidate;
+        }
+    }
+
+    fn next_open_cell(&mut self) -> Option<usize> {
+        while let Some(Reverse((has_no_main, count, index))) = self.heap.pop() {
+            let domain = self.domains[index];
+            if !self.is_open(index) || domain.count_ones() != count {
+                continue;
+            }
+            let main = self.candidates_table[self.terrains[index].unwrap()].main;
+            if (domain & main == 0) == has_no_main {
+                return Some(index);
+            }
+        }
+        None
+    }
+
+    // Candidate matching the most relations of its patterns and of the patterns
+    // of the neighbours' candidates.
+    fn closest_candidate(&mut self, index: usize) -> usize {
+        let terrain = self.terrains[index].unwrap();
+        let neighbours = self.neighbours(index);
+        let neighbour_looks = neighbours.map(|neighbour| self.looks(neighbour, &[]));
+
+        // the neighbours only depend on the look of the candidate
+        self.is_given_up[index] = false;
+        let mut fitting_neighbours_counts: HashMap<usize, usize> = HashMap::new();
+        let by_priority = self.candidates_table[terrain].by_priority.clone();
+        let mut closest: Option<(usize, usize)> = None;
+        for candidate in by_priority {
+            let look = self.candidates_table[terrain].looks[candidate];
+            let fitting_neighbours_count = match fitting_neighbours_counts.get(&look) {
+                Some(&count) => count,
+                None => {
+                    self.domains[index] = 1 << candidate;
+                    let count = self.fitting_neighbours_count(&neighbours);
+                    fitting_neighbours_counts.insert(look, count);
+                    count
+                }
+            };
+            let matched_count = fitting_neighbours_count
+                + self.candidates_table[terrain]
+                    .matched_relations_count(candidate, &neighbour_looks);
+            if closest.map_or(true, |(closest_count, _)| matched_count > closest_count) {
+                closest = Some((matched_count, candidate));
+            }
+        }
+        closest.unwrap().1
+    }
+
+    fn fitting_neighbours_count(&mut self, neighbours: &[Option<usize>]) -> usize {
+        let mut count = 0;
+        for &neighbour in neighbours.iter().flatten() {
+            if self.terrains[neighbour].is_none() || self.is_given_up[neighbour] {
+                continue;
+            }
+            if self.supported(neighbour, self.domains[neighbour], &[]) != 0 {
+                count += 1;
+            }
+        }
+        count
+    }
+
+    // Whether every cell can get a candidate fitting its neighbours, by a full search.
+    #[cfg(test)]
+    pub fn has_solution(mut self) -> bool {
+        let cells: Vec<usize> = (0..self.terrains.len()).collect();
+        for &index in &cells {
+            self.is_queued[index] = true;
+            self.queue.push_back(index);
+        }
+        let mut budget = usize::MAX;
+        self.propagate(false) && self.search(&cells, &mut budget)
+    }
+
+    // Candidates of the cells, `None` for the cells without terrain, and the indexes
+    // of the cells no candidate fits, these get the closest candidate.
+    // The preferred candidates are decided first where they fit.
+    pub fn solve(
+        mut self,
+        preferred: &[Option<TileCandidate>],
+    ) -> (Vec<Option<TileCandidate>>, Vec<usize>) {
+        for index in 0..self.terrains.len() {
+            self.is_queued[index] = true;
+            self.queue.push_back(index);
+            self.push_to_heap(index);
+        }
+        self.propagate(true);
+
+        for (index, candidate) in preferred.iter().enumerate() {
+            if let (Some(terrain), Some(candidate)) = (self.terrains[index], candidate) {
+                let candidate = self.candidates_table[terrain].candidate_index(candidate);
+                if self.is_open(index) && self.domains[index] & (1 << candidate) != 0 {
+                    self.try_decide(index, candidate);
+                }
+            }
+        }
+
+        while let Some(index) = self.next_open_cell() {
+            self.decide(index);
+        }
+
+        for index in 0..self.terrains.len() {
+            if self.is_given_up[index] {
+                self.repair(index);
+            }
+        }
+
+        for index in 0..self.terrains.len() {
+            if self.is_given_up[index] {
+                let candidate = self.closest_candidate(index);
+                self.domains[index] = 1 << candidate;
+            }
+        }
+
+        // the closest candidates may not fit, nor let the neighbours fit
+        let mut unfitting_cells = Vec::new();
+        for index in 0..self.terrains.len() {
+            if self.terrains[index].is_some()
+                && self.supported(index, self.domains[index], &[]) == 0
+            {
+                unfitting_cells.push(index);
+            }
+        }
+
+        let candidates = (0..self.terrains.len())
+            .map(|index| {
+                let terrain = self.terrains[index]?;
+                let candidate = candidates_iter(self.domains[index]).next()?;
+                Some(self.candidates_table[terrain].candidate(candidate))
+            })
+            .collect();
+        (candidates, unfitting_cells)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::rng;
+    use rand::Rng;
+
+    #[test]
+    fn solve_test() {
+        let candidates_table = CandidatesTable::new(&TilesTable::new(false));
+        let solve = |terrains: &[Option<Terrain>]| {
+            TileSolver::new(&candidates_table, 3, terrains).solve(&[None; 9])
+        };
+
+        let terrains = [
+            [Terrain::Grass; 3],
+            [Terrain::Grass; 3],
+            [Terrain::Water; 3],
+        ]
+        .concat()
+        .into_iter()
+        .map(Some)
+        .collect::<Vec<_>>();
+        let (candidates, unfitting_cells) = solve(&terrains);
+        assert!(candidates.iter().all(Option::is_some));
+        assert!(unfitting_cells.is_empty());
+
+        // no tile of the grass in the middle fits both the water and the dirt
+        let terrains = [
+            Terrain::Water,
+            Terrain::Grass,
+            Terrain::Dirt,
+            Terrain::Dirt,
+            Terrain::Grass,
+            Terrain::Dirt,
+            Terrain::Dirt,
+            Terrain::Dirt,
+            Terrain::Dirt,
+        ]
+        .map(Some);
+        let (candidates, unfitting_cells) = solve(&terrains);
+        assert!(candidates.iter().all(Option::is_some));
+        assert!(!unfitting_cells.is_empty());
+
+        let (candidates, unfitting_cells) = solve(&[None; 9]);
+        assert!(candidates.iter().all(Option::is_none));
+        assert!(unfitting_cells.is_empty());
+    }
+
+    #[test]
+    fn solve_noisy_map_test() {
+        let size = 144;
+        let mut rng = rng::stream_rng(0, 0);
+        let terrains: Vec<Option<Terrain>> = (0..size * size)
+            .map(|_| Terrain::iter().nth(rng.gen_range(0..Terrain::COUNT)))
+            .collect();
+        let candidates_table = CandidatesTable::new(&TilesTable::new(false));
+        let (candidates, unfitting_cells) =
+            TileSolver::new(&candidates_table, size, &terrains).solve(&vec![None; size * size]);
+        assert!(!unfitting_cells.is_empty());
+
+        // the unfitting cells are the cells without a pattern matched by the neighbours
+        let look = |index: Option<usize>| {
+            let Some(index) = index else {
+                return 1 << NO_NEIGHBOUR_LOOK;
+            };
+            let terrain_candidates = &candidates_table[terrains[index].unwrap()];
+            let candidate = terrain_candidates.candidate_index(&candidates[index].unwrap());
+            1 << terrain_candidates.looks[candidate]
+        };
+        let solver = TileSolver::new(&candidates_table, size, &terrains);
+        for index in 0..size * size {
+            let terrain_candidates = &candidates_table[terrains[index].unwrap()];
+            let candidate = terrain_candidates.candidate_index(&candidates[index].unwrap());
+            let neighbour_looks = solver.neighbours(index).map(look);
+            let is_fitting = terrain_candidates.supported(1 << candidate, &neighbour_looks) != 0;
+            assert_eq!(is_fitting, !unfitting_cells.contains(&index));
+        }
+    }
+}
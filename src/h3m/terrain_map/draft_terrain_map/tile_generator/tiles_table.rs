@@ -103,7 +103,6 @@ pub struct TilesGroupInfo {
     composition: TileComposition,
     tile_type: TileType,
     terrain_visible_type: TerrainVisibleType,
-    group_number: usize,
 }
 
 impl TilesGroupInfo {
@@ -114,7 +113,6 @@ impl TilesGroupInfo {
         tile_type: TileType,
         terrain_visible_type: TerrainVisibleType,
         tile_symmetry: TileSymmetry,
-        group_number: usize,
     ) -> TilesGroupInfo {
         TilesGroupInfo {
             patterns: expand_patterns(patterns, tile_symmetry),
@@ -122,7 +120,6 @@ impl TilesGroupInfo {
             composition,
             tile_type,
             terrain_visible_type,
-            group_number,
         }
     }
 
@@ -145,10 +142,6 @@ impl TilesGroupInfo {
     pub fn terrain_visible_type(&self) -> TerrainVisibleType {
         self.terrain_visible_type
     }
-
-    pub fn group_number(&self) -> usize {
-        self.group_number
-    }
 }
 
 pub struct TilesTable {
@@ -1258,15 +1251,12 @@ impl TilesTable {
                         element
                             .1
                             .iter()
-                            .enumerate()
-                            .map(|( group_number,
-                                    (   patterns,
-                                        codes,
-                                        (   terrain_visible_type,
-                                            tile_symmetry,
-                                            composition,
-                                            tile_type
-                                        )
+                            .map(|( patterns,
+                                    codes,
+                                    (   terrain_visible_type,
+                                        tile_symmetry,
+                                        composition,
+                                        tile_type
                                     )
                                 )|
                             {
@@ -1276,8 +1266,7 @@ impl TilesTable {
                                     *composition,
                                     *tile_type,
                                     *terrain_visible_type,
-                                    *tile_symmetry,
-                                    group_number)
+                                    *tile_symmetry)
                             })
                             .collect::<Vec<TilesGroupInfo>>(),
                     )
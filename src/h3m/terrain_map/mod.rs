@@ -1,18 +1,23 @@
+use crate::common::position::Position;
+use crate::common::rng;
 use crate::h3m::result::*;
 use crate::h3m::Surface;
 use draft_terrain_map::DraftTerrainMap;
 pub use map_cell::MapCell;
-pub use tile::{TerrainVisibleType, Tile, TileType};
+pub use tile::{ExistingTile, TerrainVisibleType, Tile, TileType};
+pub use tile_variants::{TileVariants, VariantWeighting};
 
 mod draft_terrain_map;
 mod map_cell;
 mod tile;
+mod tile_variants;
 
 pub struct TerrainMap {
     size: usize,
     underground: bool,
     has_obstacles: bool,
     cells: Vec<Option<MapCell>>,
+    unfitting_cells: Vec<Position>,
 }
 
 impl TerrainMap {
@@ -32,14 +37,44 @@ impl TerrainMap {
         &self.cells
     }
 
+    // Cells no tile pattern fits, they got the closest tile.
+    pub fn unfitting_cells(&self) -> &[Position] {
+        &self.unfitting_cells
+    }
+
     pub fn generate(
         size: usize,
         one_tile_water: bool,
+        tile_variants: &TileVariants,
+        underground: bool,
+        seed: u64,
+        surfaces: &[Option<Surface>],
+    ) -> H3mResult<TerrainMap> {
+        let existing_tiles = vec![None; surfaces.len()];
+        TerrainMap::generate_keeping_tiles(
+            size,
+            one_tile_water,
+            tile_variants,
+            underground,
+            seed,
+            surfaces,
+            &existing_tiles,
+        )
+    }
+
+    // Cells with an existing tile keep it while its tile group fits the neighbourhood.
+    // The existing tiles must be of the cell surface terrain.
+    pub fn generate_keeping_tiles(
+        size: usize,
+        one_tile_water: bool,
+        tile_variants: &TileVariants,
         underground: bool,
+        seed: u64,
         surfaces: &[Option<Surface>],
+        existing_tiles: &[Option<ExistingTile>],
     ) -> H3mResult<TerrainMap> {
         let map_len = size * size;
-        if surfaces.len() != map_len {
+        if surfaces.len() != map_len || existing_tiles.len() != map_len {
             return Err(H3mError::Parameter(ParameterError::new(format!(
                 "surfaces length ({}) not equal map length ({}).",
                 surfaces.len(),
@@ -48,16 +83,25 @@ impl TerrainMap {
         }
 
         let mut draft_terrain_map = DraftTerrainMap::new(size, surfaces);
-        draft_terrain_map.set_tile_codes(one_tile_water);
+        let unfitting_cells = draft_terrain_map.set_tile_codes(
+            one_tile_water,
+            tile_variants,
+            rng::stream_rng(seed, underground as u64),
+            existing_tiles,
+        );
 
         Ok(TerrainMap {
             size,
             underground,
-            has_obstacles: surfaces
-                .iter()
-                .map(|s| if let Some(s) = s { s.obstacle } else { false })
-                .any(|obstacle| obstacle),
+            has_obstacles: surfaces.iter().any(|s| {
+                if let Some(s) = s {
+                    s.obstacle.is_some()
+                } else {
+                    false
+                }
+            }),
             cells: draft_terrain_map.into_map_cells(),
+            unfitting_cells,
         })
     }
 }
@@ -72,7 +116,7 @@ mod tests {
         fn default() -> Self {
             Surface {
                 terrain: Terrain::iter().next().unwrap(),
-                obstacle: false,
+                obstacle: None,
             }
         }
     }
@@ -106,7 +150,7 @@ mod tests {
             };
             self.0[index] = Some(Surface {
                 terrain: next_terrain,
-                obstacle: false,
+                obstacle: None,
             });
         }
 
@@ -131,8 +175,50 @@ mod tests {
         let mut overflow = false;
 
         while !overflow {
-            assert!(TerrainMap::generate(size, one_tile_water, underground, &surfaces.0).is_ok());
+            let terrain_map = TerrainMap::generate(
+                size,
+                one_tile_water,
+                &TileVariants::default(),
+                underground,
+                0,
+                &surfaces.0,
+            )
+            .unwrap();
+            // the old generator stopped on stale fallback tiles where no tiles fit everywhere
+            let has_fitting_tiles =
+                DraftTerrainMap::new(size, &surfaces.0).has_fitting_tiles(one_tile_water);
+            assert_eq!(terrain_map.unfitting_cells().is_empty(), has_fitting_tiles);
             surfaces.next(&mut overflow);
         }
     }
+
+    #[test]
+    fn variant_repeat_distance_test() {
+        let size = 24;
+        let surfaces = vec![Some(Surface::new(Terrain::Grass)); size * size];
+        let close_repeats_count = |repeat_distance| {
+            let mut tile_variants = TileVariants::default();
+            tile_variants.set_repeat_distance(repeat_distance);
+            let terrain_map =
+                TerrainMap::generate(size, false, &tile_variants, false, 0, &surfaces).unwrap();
+            let code = |row: usize, column: usize| {
+                terrain_map.cells()[row * size + column]
+                    .unwrap()
+                    .tile()
+                    .code()
+            };
+
+            let mut count = 0;
+            for row in 0..size {
+                for column in 0..size - 2 {
+                    if code(row, column) == code(row, column + 2) {
+                        count += 1;
+                    }
+                }
+            }
+            count
+        };
+
+        assert!(close_repeats_count(3) < close_repeats_count(1));
+    }
 }
@@ -1,4 +1,5 @@
 use crate::h3m::Terrain;
+use strum_macros::{EnumCount, EnumIter};
 
 #[derive(Clone, Copy, PartialEq)]
 pub enum TerrainVisibleType {
@@ -9,7 +10,7 @@ pub enum TerrainVisibleType {
     DiffMixed(Terrain, Terrain),
 }
 
-#[derive(Clone, Copy, PartialEq)]
+#[derive(Clone, Copy, PartialEq, EnumIter, EnumCount)]
 pub enum TileType {
     VerticalHalf,
     HorizontalHalf,
@@ -26,6 +27,14 @@ pub enum TileType {
     Undefined,
 }
 
+// Tile as stored in the map, without the terrain information.
+#[derive(Clone, Copy, PartialEq)]
+pub struct ExistingTile {
+    pub code: u8,
+    pub vertical_mirroring: bool,
+    pub horizontal_mirroring: bool,
+}
+
 #[derive(Clone, Copy, PartialEq)]
 pub struct Tile {
     terrain_visible_type: TerrainVisibleType,
@@ -0,0 +1,49 @@
+use crate::h3m::Terrain;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// How often the decorated variants of the tiles inside one terrain areas are used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VariantWeighting {
+    Plain, // plain variants only
+    #[default]
+    Balanced, // mostly plain variants, as the map editor does
+    Decorated, // mostly decorated variants
+}
+
+/// Choice of the tile variants: the weighting of each terrain and the distance
+/// within which the same variant isn't repeated on the same terrain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TileVariants {
+    weightings: HashMap<Terrain, VariantWeighting>,
+    repeat_distance: usize,
+}
+
+impl Default for TileVariants {
+    fn default() -> TileVariants {
+        TileVariants {
+            weightings: HashMap::new(),
+            repeat_distance: 1,
+        }
+    }
+}
+
+impl TileVariants {
+    pub fn weighting(&self, terrain: Terrain) -> VariantWeighting {
+        self.weightings.get(&terrain).copied().unwrap_or_default()
+    }
+
+    pub fn set_weighting(&mut self, terrain: Terrain, weighting: VariantWeighting) {
+        self.weightings.insert(terrain, weighting);
+    }
+
+    pub fn repeat_distance(&self) -> usize {
+        self.repeat_distance
+    }
+
+    /// Distance in cells, diagonals included; 1, the default, means the 8 neighbours.
+    pub fn set_repeat_distance(&mut self, repeat_distance: usize) {
+        self.repeat_distance = repeat_distance.max(1);
+    }
+}
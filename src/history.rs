@@ -0,0 +1,309 @@
+use crate::h3m::result::*;
+use crate::h3m::{H3m, MapChange};
+use crate::{create_output, open_input, STDIO_PATH};
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Cursor, Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// The history file keeps the changes of the runs which updated the map in place,
+// the entries after the position are the undone ones which can be redone.
+
+const SIGNATURE: &[u8; 8] = b"IMG2H3MH";
+const VERSION: u32 = 1;
+
+struct HistoryEntry {
+    timestamp: u64, // seconds since the Unix epoch
+    parameters: String,
+    change: MapChange,
+}
+
+struct History {
+    entries: Vec<HistoryEntry>,
+    position: usize,
+}
+
+/// Path to the history file of the map.
+pub fn history_path(map_path: &str) -> String {
+    format!("{}.history", map_path)
+}
+
+fn write_string<W: Write>(value: &str, output: &mut W) -> io::Result<()> {
+    output.write_u32::<LE>(value.len() as u32)?;
+    output.write_all(value.as_bytes())
+}
+
+// The size is checked against the rest of the data before allocating.
+fn read_bytes(input: &mut Cursor<&[u8]>) -> H3mResult<Vec<u8>> {
+    let size_offset = input.position();
+    let size = input.read_u32::<LE>()?;
+    let remaining_size = input.get_ref().len() as u64 - input.position();
+    if u64::from(size) > remaining_size {
+        return Err(H3mError::Parsing(ParsingError::new(
+            size_offset,
+            "Broken history file: an entry is beyond the end of the file.",
+        )));
+    }
+    let mut bytes = vec![0; size as usize];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+// Date and time in UTC, e.g. 2024-03-01 12:30:00.
+fn format_timestamp(timestamp: u64) -> String {
+    let (days, seconds) = (timestamp / 86400, timestamp % 86400);
+
+    // days to the civil date, the year starts in March to put the leap day last
+    let days = days as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+impl History {
+    fn load(path: &str) -> H3mResult<History> {
+        match fs::read(path) {
+            Ok(data) => History::read(&data),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(History {
+                entries: Vec::new(),
+                position: 0,
+            }),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn read(data: &[u8]) -> H3mResult<History> {
+        let mut input = Cursor::new(data);
+        let mut signature = [0; 8];
+        input.read_exact(&mut signature)?;
+        let version = input.read_u32::<LE>()?;
+        if &signature != SIGNATURE || version != VERSION {
+            return Err(H3mError::Parsing(ParsingError::new(
+                0,
+                "Unsupported history file format.",
+            )));
+        }
+
+        let position_offset = input.position();
+        let position = input.read_u32::<LE>()? as usize;
+        let entries_count = input.read_u32::<LE>()?;
+        let mut entries = Vec::new();
+        for _ in 0..entries_count {
+            let timestamp = input.read_u64::<LE>()?;
+            let parameters_offset = input.position();
+            let parameters = String::from_utf8(read_bytes(&mut input)?).map_err(|_| {
+                H3mError::Parsing(ParsingError::new(
+                    parameters_offset,
+                    "Broken history file: the parameters are not UTF-8.",
+                ))
+            })?;
+            entries.push(HistoryEntry {
+                timestamp,
+                parameters,
+                change: MapChange::read(&read_bytes(&mut input)?[..])?,
+            });
+        }
+        if position > entries.len() {
+            return Err(H3mError::Parsing(ParsingError::new(
+                position_offset,
+                "Broken history file: the position is beyond the entries.",
+            )));
+        }
+        Ok(History { entries, position })
+    }
+
+    fn save(&self, path: &str) -> H3mResult<()> {
+        let mut output = BufWriter::new(File::create(path)?);
+        self.write(&mut output)?;
+        output.flush()?;
+        Ok(())
+    }
+
+    fn write<W: Write>(&self, output: &mut W) -> H3mResult<()> {
+        output.write_all(SIGNATURE)?;
+        output.write_u32::<LE>(VERSION)?;
+        output.write_u32::<LE>(u32::try_from(self.position)?)?;
+        output.write_u32::<LE>(u32::try_from(self.entries.len())?)?;
+        for entry in &self.entries {
+            output.write_u64::<LE>(entry.timestamp)?;
+            write_string(&entry.parameters, output)?;
+            let mut change = Vec::new();
+            entry.change.write(&mut change)?;
+            output.write_u32::<LE>(u32::try_from(change.len())?)?;
+            output.write_all(&change)?;
+        }
+        Ok(())
+    }
+
+    // A new run drops the undone entries.
+    fn record(&mut self, parameters: String, change: MapChange) {
+        self.entries.truncate(self.position);
+        self.entries.push(HistoryEntry {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs()),
+            parameters,
+            change,
+        });
+        self.position = self.entries.len();
+    }
+}
+
+/// Adds the change of a run to the history of the map, runs without changes are skipped.
+pub fn record_change(map_path: &str, parameters: String, change: MapChange) -> H3mResult<()> {
+    if map_path == STDIO_PATH {
+        return Err(H3mError::Parameter(ParameterError::new(
+            "History can't be kept for a map read from stdin.",
+        )));
+    }
+    if change.is_empty() {
+        return Ok(());
+    }
+
+    let history_path = history_path(map_path);
+    let mut history = History::load(&history_path)?;
+    history.record(parameters, change);
+    history.save(&history_path)
+}
+
+// Moves the map one entry back or forth in its history.
+fn step(map_path: &str, undo: bool) -> H3mResult<()> {
+    let history_path = history_path(map_path);
+    if fs::metadata(&history_path).is_err() {
+        return Err(H3mError::Parameter(ParameterError::new(format!(
+            "The map has no history file {}.",
+            history_path
+        ))));
+    }
+    let mut history = History::load(&history_path)?;
+
+    let entry_index = if undo {
+        history
+            .position
+            .checked_sub(1)
+            .ok_or_else(|| H3mError::Parameter(ParameterError::new("Nothing to undo.")))?
+    } else if history.position < history.entries.len() {
+        history.position
+    } else {
+        return Err(H3mError::Parameter(ParameterError::new("Nothing to redo.")));
+    };
+    let mut h3m = H3m::load(open_input(map_path)?)?;
+    h3m.apply_change(&history.entries[entry_index].change, undo)?;
+
+    // the history is written first, a map left behind it fails to apply the next change
+    history.position = if undo { entry_index } else { entry_index + 1 };
+    history.save(&history_path)?;
+    let mut output = create_output(map_path)?;
+    h3m.save(&mut output)?;
+    output.flush()?;
+
+    let entry = &history.entries[entry_index];
+    println!(
+        "{} the run of {}: {}",
+        if undo { "Undone" } else { "Redone" },
+        format_timestamp(entry.timestamp),
+        entry.parameters
+    );
+    Ok(())
+}
+
+/// Reverts the last recorded run on the map, as the `undo` command does.
+pub fn undo(map_path: &str) -> H3mResult<()> {
+    step(map_path, true)
+}
+
+/// Applies the last undone run on the map again, as the `redo` command does.
+pub fn redo(map_path: &str) -> H3mResult<()> {
+    step(map_path, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::h3m::{Terrain, TestMap};
+    use std::collections::HashMap;
+
+    #[test]
+    fn format_timestamp_test() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00");
+        assert_eq!(format_timestamp(951_827_696), "2000-02-29 12:34:56");
+        assert_eq!(format_timestamp(1_709_251_200), "2024-03-01 00:00:00");
+    }
+
+    #[test]
+    fn undo_redo_test() {
+        let dir = std::env::temp_dir().join("img2h3m_undo_redo_test");
+        fs::create_dir_all(&dir).unwrap();
+        let map_path = dir.join("map.h3m");
+        let map_path = map_path.to_str().unwrap();
+        fs::write(map_path, TestMap::new(4, Terrain::Grass).compressed()).unwrap();
+        let _ = fs::remove_file(history_path(map_path));
+        let land_terrains = || -> Vec<Terrain> {
+            let h3m = H3m::load(File::open(map_path).unwrap()).unwrap();
+            let surfaces = h3m.surfaces(false).unwrap();
+            surfaces.iter().map(|surface| surface.terrain).collect()
+        };
+
+        let replacements = HashMap::from([(Terrain::Grass, Terrain::Sand)]);
+        crate::replace_terrain(map_path, None, &replacements, false, Some(1), true).unwrap();
+        assert!(land_terrains()
+            .iter()
+            .all(|&terrain| terrain == Terrain::Sand));
+
+        undo(map_path).unwrap();
+        assert!(land_terrains()
+            .iter()
+            .all(|&terrain| terrain == Terrain::Grass));
+        assert!(matches!(undo(map_path), Err(H3mError::Parameter(_))));
+
+        redo(map_path).unwrap();
+        assert!(land_terrains()
+            .iter()
+            .all(|&terrain| terrain == Terrain::Sand));
+        assert!(matches!(redo(map_path), Err(H3mError::Parameter(_))));
+
+        let history = History::load(&history_path(map_path)).unwrap();
+        assert_eq!(history.position, 1);
+        assert_eq!(history.entries.len(), 1);
+        assert_eq!(
+            history.entries[0].parameters,
+            "replace --replace grass=sand --seed 1"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn broken_history_test() {
+        let history = History {
+            entries: Vec::new(),
+            position: 0,
+        };
+        let mut data = Vec::new();
+        history.write(&mut data).unwrap();
+        // one entry with the parameters longer than the file
+        data[16..20].copy_from_slice(&1u32.to_le_bytes());
+        data.extend_from_slice(&0u64.to_le_bytes());
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(History::read(&data), Err(H3mError::Parsing(_))));
+    }
+}
@@ -0,0 +1,105 @@
+use crate::{open_input, MapInfo, OffsetsSource};
+use std::error::Error;
+use std::fmt;
+
+fn write_optional<T: fmt::Display>(
+    fmt: &mut fmt::Formatter,
+    name: &str,
+    value: Option<T>,
+) -> fmt::Result {
+    match value {
+        Some(value) => writeln!(fmt, "{}: {}", name, value),
+        None => writeln!(fmt, "{}: unknown", name),
+    }
+}
+
+impl fmt::Display for MapInfo {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write_optional(fmt, "Version", self.version.as_ref())?;
+        write_optional(
+            fmt,
+            "HotA subversion",
+            self.hota_subversion
+                .map(|subversion| format!("{}", subversion)),
+        )?;
+        write_optional(
+            fmt,
+            "Map size",
+            self.map_size.map(|size| format!("{0}x{0}", size)),
+        )?;
+        write_optional(
+            fmt,
+            "Underground",
+            self.has_underground
+                .map(|has_underground| if has_underground { "yes" } else { "no" }),
+        )?;
+        write_optional(fmt, "Object templates", self.object_templates_count)?;
+        write_optional(fmt, "Objects", self.objects_count)?;
+
+        let hex = |offset: usize| format!("0x{:X}", offset);
+        write_optional(fmt, "Land offset", self.offsets.map(|o| hex(o.land)))?;
+        if self.has_underground != Some(false) {
+            write_optional(
+                fmt,
+                "Underground offset",
+                self.offsets.and_then(|o| o.underground).map(hex),
+            )?;
+        }
+        write_optional(
+            fmt,
+            "Object templates offset",
+            self.offsets.map(|o| hex(o.object_templates)),
+        )?;
+        write_optional(
+            fmt,
+            "Offsets found by",
+            self.offsets.map(|o| match o.source {
+                OffsetsSource::Structure => "map structure",
+                OffsetsSource::Signature => "templates signature",
+            }),
+        )?;
+        if let Some(structure_error) = &self.structure_error {
+            writeln!(fmt, "Map structure error: {}", structure_error)?;
+        }
+
+        for level_info in &self.levels {
+            let level = if level_info.underground {
+                "Underground"
+            } else {
+                "Land"
+            };
+            let cells_count: usize = level_info
+                .terrain_cells
+                .iter()
+                .map(|terrain_cells| terrain_cells.cells_count)
+                .sum();
+            writeln!(fmt, "{} terrain:", level)?;
+            for terrain_cells in &level_info.terrain_cells {
+                writeln!(
+                    fmt,
+                    "  {:<13} {:>6} cells {:>5.1}%",
+                    format!("{:?}", terrain_cells.terrain).to_lowercase(),
+                    terrain_cells.cells_count,
+                    100.0 * terrain_cells.cells_count as f64 / cells_count as f64
+                )?;
+            }
+        }
+
+        if let Some(error) = &self.error {
+            writeln!(fmt, "Parsing error: {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Prints the map summary as text or JSON, as the `info` command does.
+/// Returns whether the whole map could be parsed.
+pub fn print_map_info(map_path: &str, json: bool) -> Result<bool, Box<dyn Error>> {
+    let map_info = MapInfo::read(open_input(map_path)?)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&map_info)?);
+    } else {
+        print!("{}", map_info);
+    }
+    Ok(map_info.error.is_none())
+}
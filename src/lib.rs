@@ -1,35 +1,125 @@
-use common::RgbColor;
-pub use config::Config;
-use h3m::H3m;
+//! Conversion of images to HoMM 3: HotA maps.
+//!
+//! Besides the [`run`] entry point of the CLI, the crate lets tools set the map
+//! terrain from memory:
+//!
+//! ```no_run
+//! use img2h3m::{H3m, Obstacle, ObstacleCategory, Surface, SurfaceOptions, Terrain};
+//! use std::fs::File;
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut h3m = H3m::load(File::open("blank.h3m")?)?;
+//! h3m.set_seed(42);
+//!
+//! let size = h3m.map_size();
+//! let surfaces: Vec<Option<Surface>> = (0..size * size)
+//!     .map(|index| {
+//!         Some(if index % size < size / 2 {
+//!             Surface::new(Terrain::Grass)
+//!         } else {
+//!             Surface::with_obstacle(Terrain::Grass, Obstacle::new(ObstacleCategory::Forest))
+//!         })
+//!     })
+//!     .collect();
+//!
+//! let options = SurfaceOptions {
+//!     obstacles: true,
+//!     ..SurfaceOptions::default()
+//! };
+//! let obstacle_report = h3m.set_surfaces(options, &surfaces)?;
+//! if let Some(obstacle_report) = obstacle_report {
+//!     println!("{}", obstacle_report.coverage());
+//! }
+//! h3m.save(File::create("blank.h3m")?)?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub use batch::{run_batch, BatchSummary};
+use common::rng;
+pub use common::RgbColor;
+pub use config::{Config, Region};
+pub use h3m::result::{H3mError, H3mResult, InternalError, ParameterError, ParsingError};
+pub use h3m::{
+    transform_cells, CutOffArea, H3m, LevelInfo, MapInfo, MapOffsets, Obstacle, ObstacleCategory,
+    ObstacleCoverage, ObstacleReport, OffsetsSource, PassabilityCheck, Surface, SurfaceOptions,
+    Symmetry, Terrain, TerrainCells, TerrainReplacement, TileVariants, Transform, VariantWeighting,
+    MAX_MAP_SIZE,
+};
+pub use history::{history_path, redo, undo};
 use image::io::Reader as ImageReader;
-use image::Rgb;
-use map_image::MapImage;
-use std::error::Error;
-use std::fs::File;
+pub use image::DynamicImage;
+use image::{GenericImageView, ImageOutputFormat, Rgb, RgbImage, RgbaImage};
+pub use info::print_map_info;
+use map_image::{cave_surfaces, render_surfaces, MapImage};
+pub use map_image::{ProblemRules, TerrainRepair};
+pub use palette::{
+    palette, print_palette, unmatched_colors, validate_image_palette, UnmatchedColor,
+};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use strum::IntoEnumIterator;
+pub use watch::watch;
 
+mod batch;
 mod common;
 mod config;
 mod h3m;
+mod history;
+mod info;
 mod map_image;
+mod palette;
+mod watch;
 
-struct MapImageParams {
-    one_tile_water: bool,
-    obstacles: bool,
-    transparent_color: Option<RgbColor>,
+/// Color of the pixels which are not processed in the transparent mode.
+pub const TRANSPARENT_COLOR: RgbColor = [0x00, 0xFF, 0xFF];
+
+/// Color of the keep clear mask pixels.
+pub const KEEP_CLEAR_COLOR: RgbColor = [0xFF, 0x00, 0xFF];
+
+/// Color of the region mask pixels.
+pub const REGION_COLOR: RgbColor = [0xFF, 0xFF, 0xFF];
+
+/// Color of the cave mask tunnel pixels.
+pub const TUNNEL_COLOR: RgbColor = [0xFF, 0xFF, 0xFF];
+
+// Random stream of the caves, apart from the terrain and the obstacle ones.
+const CAVE_STREAM: u64 = 2 << 32;
+
+/// Path meaning the standard input for input files and the standard output for the map.
+pub const STDIO_PATH: &str = "-";
+
+/// How the image colors turn into surfaces in [`H3m::set_image`],
+/// all off by default.
+#[derive(Default)]
+pub struct MapImageParams {
+    /// Pixels of this color are not processed.
+    pub transparent_color: Option<RgbColor>,
+    /// The terrain patterns which no tiles can draw, see [`ProblemRules::default`].
+    pub problem_rules: ProblemRules,
+    /// How the cells of the problem rules are repaired.
+    pub terrain_repair: TerrainRepair,
+    /// Move the coastline cells surrounded by the other side across it.
+    pub smooth_coastline: bool,
+    /// Mirror or rotate the surfaces before the tile generation.
+    pub transform: Option<Transform>,
 }
 
 impl MapImage {
     fn from_image(
-        image_path: impl Into<String>,
+        img: &RgbImage,
         map_size: usize,
+        options: SurfaceOptions,
         map_image_params: &MapImageParams,
-    ) -> Result<MapImage, Box<dyn Error>> {
+    ) -> MapImage {
         let mut map_image = MapImage::new(
             map_size,
-            map_image_params.one_tile_water,
-            map_image_params.obstacles,
+            options.one_tile_water,
+            options.obstacles,
+            &map_image_params.problem_rules,
+            map_image_params.terrain_repair,
         );
-        let img = ImageReader::open(image_path.into())?.decode()?.into_rgb8();
         let is_transparent_color = |pixel: &Rgb<u8>| {
             if map_image_params.transparent_color.is_none() {
                 false
@@ -46,62 +136,847 @@ impl MapImage {
                 map_image.set_pixel(row_id, column_id, *pixel);
             }
         }
-        Ok(map_image)
+        map_image
+    }
+
+    // Surfaces of the image with the problem terrain repaired.
+    fn fixed(
+        image: &DynamicImage,
+        keep_clear_mask: Option<&DynamicImage>,
+        map_size: usize,
+        options: SurfaceOptions,
+        map_image_params: &MapImageParams,
+    ) -> MapImage {
+        let mut map_image =
+            MapImage::from_image(&image.to_rgb8(), map_size, options, map_image_params);
+        if let Some(keep_clear_mask) = keep_clear_mask {
+            map_image.set_keep_clear_mask(&keep_clear_mask.to_rgb8());
+        }
+        map_image.fix();
+        if map_image_params.smooth_coastline {
+            map_image.smooth_coastline();
+        }
+        map_image
+    }
+
+    // The mask must cover the map cell by cell, any other size would misalign it.
+    fn check_keep_clear_mask(mask: &DynamicImage, map_size: usize) -> H3mResult<()> {
+        let (width, height) = mask.dimensions();
+        if (width as usize, height as usize) != (map_size, map_size) {
+            return Err(H3mError::Parameter(ParameterError::new(format!(
+                "Keep clear mask size ({}x{}) not equal map size ({}x{}).",
+                width, height, map_size, map_size
+            ))));
+        }
+        Ok(())
+    }
+
+    fn set_keep_clear_mask(&mut self, mask: &RgbImage) {
+        let map_size = self.size();
+
+        for (row_id, row) in mask.rows().take(map_size).enumerate() {
+            for (column_id, pixel) in row.take(map_size).enumerate() {
+                if pixel.0 == KEEP_CLEAR_COLOR {
+                    self.set_keep_clear(row_id, column_id);
+                }
+            }
+        }
+    }
+}
+
+/// Decodes an image held in memory, the format is guessed from the data.
+pub fn load_image_from_memory(data: &[u8]) -> H3mResult<DynamicImage> {
+    Ok(image::load_from_memory(data)?)
+}
+
+fn load_image(image_path: &str) -> H3mResult<DynamicImage> {
+    if image_path == STDIO_PATH {
+        let mut data = Vec::new();
+        io::stdin().lock().read_to_end(&mut data)?;
+        load_image_from_memory(&data)
+    } else {
+        Ok(ImageReader::open(image_path)?.decode()?)
+    }
+}
+
+fn save_image(image: &DynamicImage, image_path: &str) -> H3mResult<()> {
+    if image_path == STDIO_PATH {
+        let mut data = Vec::new();
+        image.write_to(&mut io::Cursor::new(&mut data), ImageOutputFormat::Png)?;
+        io::stdout().lock().write_all(&data)?;
+    } else {
+        image.save(image_path)?;
+    }
+    Ok(())
+}
+
+/// Draws the underground caves of the tunnels of the mask, its `TUNNEL_COLOR` pixels:
+/// the tunnels are widened and their walls smoothed randomly with the seed.
+/// The caves are subterranean surrounded by rock, in the image colors.
+pub fn cave_image(mask: &DynamicImage, map_size: usize, seed: u64) -> DynamicImage {
+    let mut tunnel = vec![false; map_size * map_size];
+    for (row_id, row) in mask.to_rgb8().rows().take(map_size).enumerate() {
+        for (column_id, pixel) in row.take(map_size).enumerate() {
+            tunnel[row_id * map_size + column_id] = pixel.0 == TUNNEL_COLOR;
+        }
+    }
+    let surfaces = cave_surfaces(map_size, &tunnel, &mut rng::stream_rng(seed, CAVE_STREAM));
+    DynamicImage::ImageRgb8(render_surfaces(map_size, &surfaces))
+}
+
+fn open_input(path: &str) -> io::Result<Box<dyn Read>> {
+    if path == STDIO_PATH {
+        Ok(Box::new(io::stdin()))
+    } else {
+        Ok(Box::new(File::open(path)?))
+    }
+}
+
+/// Reads the problem rules of a TOML or, with the `.json` extension, JSON file,
+/// see [`ProblemRules::parse`].
+pub fn load_problem_rules(path: &str) -> H3mResult<ProblemRules> {
+    let is_json = path.to_lowercase().ends_with(".json");
+    ProblemRules::parse(&fs::read_to_string(path)?, is_json)
+}
+
+fn create_output(path: &str) -> io::Result<Box<dyn Write>> {
+    if path == STDIO_PATH {
+        Ok(Box::new(io::stdout()))
+    } else {
+        Ok(Box::new(File::create(path)?))
     }
 }
 
 impl H3m {
-    fn set_image(
+    /// Sets the surfaces of a level from an image, pixel by pixel from the top left
+    /// corner; pixels beyond the map size are ignored. With a keep clear mask
+    /// of the map size, no obstacles are placed on the cells of its
+    /// `KEEP_CLEAR_COLOR` pixels; a mask of another size is an error.
+    /// With a region only its cells are set, see [`H3m::set_surfaces_in_region`].
+    /// Outside of the integration and transparent modes the underground cells
+    /// beyond the image are rock.
+    ///
+    /// See [`H3m::set_surfaces`] for the options and the result.
+    pub fn set_image(
         &mut self,
-        image_path: impl Into<String>,
-        underground: bool,
+        image: &DynamicImage,
+        keep_clear_mask: Option<&DynamicImage>,
+        options: SurfaceOptions,
         map_image_params: &MapImageParams,
-        integration_mode: bool,
-    ) -> Result<(), Box<dyn Error>> {
-        let mut map_image = MapImage::from_image(image_path, self.map_size(), map_image_params)?;
-        map_image.fix();
-        let surfaces = map_image.surfaces();
-        self.set_surfaces(
-            map_image_params.one_tile_water,
-            integration_mode,
-            map_image_params.obstacles,
-            underground,
+        region: Option<&[bool]>,
+    ) -> H3mResult<Option<ObstacleReport>> {
+        if let Some(keep_clear_mask) = keep_clear_mask {
+            MapImage::check_keep_clear_mask(keep_clear_mask, self.map_size())?;
+        }
+        let map_image = MapImage::fixed(
+            image,
+            keep_clear_mask,
+            self.map_size(),
+            options,
+            map_image_params,
+        );
+        self.set_map_image(&map_image, options, map_image_params, region)
+    }
+
+    /// Draws the pixels of the image which the terrain repair of
+    /// [`H3m::set_image`] changes, in the color of the repair,
+    /// the other pixels are transparent. See [`TerrainRepair`] for the colors.
+    pub fn render_terrain_repairs(
+        &self,
+        image: &DynamicImage,
+        options: SurfaceOptions,
+        map_image_params: &MapImageParams,
+    ) -> DynamicImage {
+        let map_image = MapImage::fixed(image, None, self.map_size(), options, map_image_params);
+        DynamicImage::ImageRgba8(map_image.render_repairs())
+    }
+
+    fn set_map_image(
+        &mut self,
+        map_image: &MapImage,
+        options: SurfaceOptions,
+        map_image_params: &MapImageParams,
+        region: Option<&[bool]>,
+    ) -> H3mResult<Option<ObstacleReport>> {
+        let mut surfaces = map_image.surfaces();
+        if let Some(transform) = map_image_params.transform {
+            surfaces = transform_cells(self.map_size(), &surfaces, transform)?;
+        }
+        // the unpainted underground is solid rock rather than the blank map terrain
+        if options.underground
+            && !options.integration_mode
+            && region.is_none()
+            && map_image_params.transparent_color.is_none()
+        {
+            for surface in surfaces.iter_mut().filter(|surface| surface.is_none()) {
+                *surface = Some(Surface::new(Terrain::Rock));
+            }
+        }
+        match region {
+            Some(region) => self.set_surfaces_in_region(options, &surfaces, region),
+            None => self.set_surfaces(options, &surfaces),
+        }
+    }
+
+    /// Draws the map with the image colors, one pixel per cell: the land and,
+    /// to the right of it, the underground. Only the obstacles generated
+    /// in this session are drawn.
+    pub fn render_preview(&self) -> H3mResult<DynamicImage> {
+        let map_size = self.map_size();
+        let levels_count = if self.has_underground() { 2 } else { 1 };
+        let mut preview = RgbImage::new((map_size * levels_count) as u32, map_size as u32);
+
+        for level in 0..levels_count {
+            let underground = level == 1;
+            let level_image = render_surfaces(map_size, &self.surfaces(underground)?);
+            image::imageops::replace(&mut preview, &level_image, (map_size * level) as u32, 0);
+        }
+        Ok(DynamicImage::ImageRgb8(preview))
+    }
+
+    /// Draws one level with the image colors, one pixel per cell.
+    /// Converting the image back gives the same terrain.
+    pub fn render_level(&self, underground: bool) -> H3mResult<DynamicImage> {
+        let surfaces = self.surfaces(underground)?;
+        Ok(DynamicImage::ImageRgb8(render_surfaces(
+            self.map_size(),
             &surfaces,
-        )?;
+        )))
+    }
 
-        Ok(())
+    fn set_image_file(
+        &mut self,
+        image_path: &str,
+        keep_clear_mask_path: Option<&str>,
+        options: SurfaceOptions,
+        map_image_params: &MapImageParams,
+        region: Option<&[bool]>,
+    ) -> H3mResult<(Option<ObstacleReport>, RgbaImage)> {
+        self.set_level_image(
+            &load_image(image_path)?,
+            keep_clear_mask_path,
+            options,
+            map_image_params,
+            region,
+        )
+    }
+
+    // Sets the image as `set_image` does, also returning the repairs overlay of the level.
+    fn set_level_image(
+        &mut self,
+        image: &DynamicImage,
+        keep_clear_mask_path: Option<&str>,
+        options: SurfaceOptions,
+        map_image_params: &MapImageParams,
+        region: Option<&[bool]>,
+    ) -> H3mResult<(Option<ObstacleReport>, RgbaImage)> {
+        let keep_clear_mask = keep_clear_mask_path.map(load_image).transpose()?;
+        if let Some(keep_clear_mask) = &keep_clear_mask {
+            MapImage::check_keep_clear_mask(keep_clear_mask, self.map_size())?;
+        }
+        let map_image = MapImage::fixed(
+            image,
+            keep_clear_mask.as_ref(),
+            self.map_size(),
+            options,
+            map_image_params,
+        );
+        let obstacle_report = self.set_map_image(&map_image, options, map_image_params, region)?;
+        Ok((obstacle_report, map_image.render_repairs()))
+    }
+}
+
+// Region cells row by row, the parts beyond the map are ignored.
+fn region_cells(region: &Region, map_size: usize) -> H3mResult<Vec<bool>> {
+    let mut cells = vec![false; map_size * map_size];
+    match region {
+        Region::Rectangle {
+            column,
+            row,
+            width,
+            height,
+        } => {
+            for row in *row..(row + height).min(map_size) {
+                for column in *column..(column + width).min(map_size) {
+                    cells[row * map_size + column] = true;
+                }
+            }
+        }
+        Region::Mask(mask_path) => {
+            let mask = load_image(mask_path)?.to_rgb8();
+            for (row_id, row) in mask.rows().take(map_size).enumerate() {
+                for (column_id, pixel) in row.take(map_size).enumerate() {
+                    cells[row_id * map_size + column_id] = pixel.0 == REGION_COLOR;
+                }
+            }
+        }
+    }
+    Ok(cells)
+}
+
+fn print_removed_obstacles_warning(replacement: &TerrainReplacement, level: &str) {
+    if replacement.removed_obstacles_count > 0 {
+        eprintln!(
+            "Warning: removed {} obstacles from the {} cells replaced with water or rock.",
+            replacement.removed_obstacles_count, level
+        );
     }
 }
 
-pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-    let input_map_file = File::open(&config.map_path)?;
-    let mut h3m = H3m::load(input_map_file)?;
+// Information goes to stderr when stdout is taken by an output.
+fn print_info(message: &str, stdout_taken: bool) {
+    if stdout_taken {
+        eprintln!("{}", message);
+    } else {
+        println!("{}", message);
+    }
+}
 
-    let map_image_params = MapImageParams {
+fn print_obstacle_report(obstacle_report: &ObstacleReport, level: &str, stdout_taken: bool) {
+    if obstacle_report.removed_obstacles_count() > 0 {
+        print_info(
+            &format!(
+                "Removed {} obstacles to keep the {} passable areas connected.",
+                obstacle_report.removed_obstacles_count(),
+                level
+            ),
+            stdout_taken,
+        );
+    }
+
+    for (terrain, category) in obstacle_report.fallback_categories() {
+        eprintln!(
+            "Warning: there are no {} obstacles for the {} {} cells, any obstacles are placed instead.",
+            format!("{:?}", category).to_lowercase(),
+            level,
+            format!("{:?}", terrain).to_lowercase()
+        );
+    }
+
+    if !obstacle_report.uncovered_cells().is_empty() {
+        eprintln!(
+            "Warning: no obstacle fits the {} obstacle cells at (row, column): {}.",
+            level,
+            format_cells(obstacle_report.uncovered_cells())
+        );
+    }
+
+    for cut_off_area in obstacle_report.cut_off_areas() {
+        eprintln!(
+            "Warning: {} area of {} passable cells at (row: {}, column: {}) is cut off by obstacles.",
+            level,
+            cut_off_area.cells_count(),
+            cut_off_area.row(),
+            cut_off_area.column()
+        );
+    }
+}
+
+// The first cells as (row, column), the number of the rest.
+fn format_cells(cells: &[(usize, usize)]) -> String {
+    const MAX_PRINTED_CELLS: usize = 10;
+    let mut printed_cells: Vec<String> = cells
+        .iter()
+        .take(MAX_PRINTED_CELLS)
+        .map(|(row, column)| format!("({}, {})", row, column))
+        .collect();
+    if cells.len() > MAX_PRINTED_CELLS {
+        printed_cells.push(format!("and {} more", cells.len() - MAX_PRINTED_CELLS));
+    }
+    printed_cells.join(", ")
+}
+
+fn print_unfitting_tile_cells(h3m: &H3m, underground: bool, level: &str) {
+    let cells = h3m.unfitting_tile_cells(underground);
+    if cells.is_empty() {
+        return;
+    }
+
+    eprintln!(
+        "Warning: no tile fits the {} terrain around the cells at (row, column): {}.",
+        level,
+        format_cells(cells)
+    );
+}
+
+/// Updates the map file of the config with its images, as the CLI does.
+/// The map path `STDIO_PATH` reads the map from stdin and writes it to stdout.
+/// With an output map path the result is written there instead of the input map.
+pub fn run(config: Config) -> H3mResult<()> {
+    convert(open_input(&config.map_path)?, &config)
+}
+
+// Options of the run as the CLI takes them, with the seed it used.
+fn run_parameters(config: &Config, seed: u64) -> String {
+    let mut parameters = vec!["convert".to_string()];
+    let mut add_value = |name: &str, value: Option<&String>| {
+        if let Some(value) = value {
+            parameters.push(format!("--{} {}", name, value));
+        }
+    };
+    add_value("land-image", config.land_image_path.as_ref());
+    add_value("underground-image", config.underground_image_path.as_ref());
+    add_value("cave-mask", config.cave_mask_path.as_ref());
+    add_value("land-keep-clear", config.land_keep_clear_mask_path.as_ref());
+    add_value(
+        "underground-keep-clear",
+        config.underground_keep_clear_mask_path.as_ref(),
+    );
+    add_value("problem-rules", config.problem_rules_path.as_ref());
+    match &config.region {
+        Some(Region::Rectangle {
+            column,
+            row,
+            width,
+            height,
+        }) => parameters.push(format!("--region {},{},{},{}", column, row, width, height)),
+        Some(Region::Mask(mask_path)) => parameters.push(format!("--region-mask {}", mask_path)),
+        None => {}
+    }
+
+    let flags = [
+        (config.obstacles, "--obstacles"),
+        (config.one_tile_water, "--one-tile-water"),
+        (config.integration_mode, "--integration"),
+        (config.transparent_color.is_some(), "--transparent"),
+        (config.smooth_coastline, "--smooth-coastline"),
+        (config.strict_obstacles, "--strict-obstacles"),
+    ];
+    for (is_set, flag) in flags {
+        if is_set {
+            parameters.push(flag.to_string());
+        }
+    }
+    if let Some(transform) = config.transform {
+        let transform_name = match transform {
+            Transform::MirrorHorizontal => "mirror-horizontal",
+            Transform::MirrorVertical => "mirror-vertical",
+            Transform::MirrorDiagonal => "mirror-diagonal",
+            Transform::Rotate90 => "rotate-90",
+            Transform::Rotate180 => "rotate-180",
+            Transform::Rotate270 => "rotate-270",
+        };
+        parameters.push(format!("--transform {}", transform_name));
+    }
+    if let Some(symmetry) = config.symmetry {
+        let symmetry_name = match symmetry {
+            Symmetry::MirrorHorizontal => "mirror-horizontal",
+            Symmetry::MirrorVertical => "mirror-vertical",
+            Symmetry::MirrorDiagonal => "mirror-diagonal",
+            Symmetry::Rotate180 => "rotate-180",
+            Symmetry::MirrorBoth => "mirror-both",
+            Symmetry::Rotate90 => "rotate-90",
+        };
+        parameters.push(format!("--symmetry {}", symmetry_name));
+    }
+    if config.terrain_repair != TerrainRepair::default() {
+        parameters.push(format!("--terrain-repair {:?}", config.terrain_repair).to_lowercase());
+    }
+    match config.passability_check {
+        Some(PassabilityCheck::Warn) => parameters.push("--passability warn".to_string()),
+        Some(PassabilityCheck::Fix) => parameters.push("--passability fix".to_string()),
+        None => {}
+    }
+    for terrain in Terrain::iter() {
+        let weighting = config.tile_variants.weighting(terrain);
+        if weighting != VariantWeighting::default() {
+            parameters
+                .push(format!("--tile-variants {:?}={:?}", terrain, weighting).to_lowercase());
+        }
+    }
+    if config.tile_variants.repeat_distance() > 1 {
+        parameters.push(format!(
+            "--variant-repeat-distance {}",
+            config.tile_variants.repeat_distance()
+        ));
+    }
+    parameters.push(format!("--seed {}", seed));
+    parameters.join(" ")
+}
+
+fn check_history_output(map_path: &str, output_map_path: &str) -> H3mResult<()> {
+    if map_path == STDIO_PATH || output_map_path != map_path {
+        return Err(H3mError::Parameter(ParameterError::new(
+            "History is kept only for maps updated in place.",
+        )));
+    }
+    Ok(())
+}
+
+// Loads the map from the input and writes the converted map and its preview.
+fn convert<R: Read>(input: R, config: &Config) -> H3mResult<()> {
+    let output_map_path = config.output_map_path.as_ref().unwrap_or(&config.map_path);
+    let stdout_outputs_count = [
+        Some(output_map_path),
+        config.preview_path.as_ref(),
+        config.repair_overlay_path.as_ref(),
+    ]
+    .iter()
+    .filter(|path| path.is_some_and(|path| path == STDIO_PATH))
+    .count();
+    if stdout_outputs_count > 1 {
+        return Err(H3mError::Parameter(ParameterError::new(
+            "Only one output can be written to stdout.",
+        )));
+    }
+    let stdout_taken = stdout_outputs_count > 0;
+    if config.history {
+        check_history_output(&config.map_path, output_map_path)?;
+    }
+    let mut h3m = H3m::load(input)?;
+    h3m.set_passability_check(config.passability_check);
+    h3m.set_strict_obstacles(config.strict_obstacles);
+    h3m.set_symmetry(config.symmetry);
+    h3m.set_tile_variants(config.tile_variants.clone());
+    if let Some(seed) = config.seed {
+        h3m.set_seed(seed);
+    }
+    let snapshot = config.history.then(|| h3m.snapshot()).transpose()?;
+
+    let options = SurfaceOptions {
         one_tile_water: config.one_tile_water,
+        integration_mode: config.integration_mode,
         obstacles: config.obstacles,
+        underground: false,
+    };
+    let underground_options = SurfaceOptions {
+        underground: true,
+        ..options
+    };
+    let map_image_params = MapImageParams {
         transparent_color: config.transparent_color,
+        problem_rules: config
+            .problem_rules_path
+            .as_deref()
+            .map(load_problem_rules)
+            .transpose()?
+            .unwrap_or_default(),
+        terrain_repair: config.terrain_repair,
+        smooth_coastline: config.smooth_coastline,
+        transform: config.transform,
     };
+    let region = config
+        .region
+        .as_ref()
+        .map(|region| region_cells(region, h3m.map_size()))
+        .transpose()?;
+
+    // the levels side by side as in the preview
+    let map_size = h3m.map_size();
+    let levels_count = if h3m.has_underground() { 2 } else { 1 };
+    let mut repair_overlay = RgbaImage::new((map_size * levels_count) as u32, map_size as u32);
 
-    if let Some(land_image_path) = config.land_image_path {
-        h3m.set_image(
-            land_image_path,
+    let mut obstacle_coverage: Option<ObstacleCoverage> = None;
+    let mut add_obstacle_report =
+        |(obstacle_report, repairs): (Option<ObstacleReport>, RgbaImage),
+         underground: bool,
+         level: &str| {
+            let repairs_column = if underground { map_size } else { 0 };
+            image::imageops::replace(&mut repair_overlay, &repairs, repairs_column as u32, 0);
+            if let Some(obstacle_report) = obstacle_report {
+                print_obstacle_report(&obstacle_report, level, stdout_taken);
+                obstacle_coverage
+                    .get_or_insert_with(ObstacleCoverage::default)
+                    .merge(obstacle_report.coverage());
+            }
+        };
+
+    if let Some(land_image_path) = &config.land_image_path {
+        add_obstacle_report(
+            h3m.set_image_file(
+                land_image_path,
+                config.land_keep_clear_mask_path.as_deref(),
+                options,
+                &map_image_params,
+                region.as_deref(),
+            )?,
             false,
-            &map_image_params,
-            config.integration_mode,
-        )?;
+            "land",
+        );
+        print_unfitting_tile_cells(&h3m, false, "land");
     }
 
-    if let Some(underground_image_path) = config.underground_image_path {
-        h3m.set_image(
-            underground_image_path,
+    if let Some(underground_image_path) = &config.underground_image_path {
+        add_obstacle_report(
+            h3m.set_image_file(
+                underground_image_path,
+                config.underground_keep_clear_mask_path.as_deref(),
+                underground_options,
+                &map_image_params,
+                region.as_deref(),
+            )?,
             true,
-            &map_image_params,
-            config.integration_mode,
+            "underground",
+        );
+        print_unfitting_tile_cells(&h3m, true, "underground");
+    }
+
+    if let Some(cave_mask_path) = &config.cave_mask_path {
+        let cave_image = cave_image(&load_image(cave_mask_path)?, map_size, h3m.seed());
+        add_obstacle_report(
+            h3m.set_level_image(
+                &cave_image,
+                None,
+                underground_options,
+                &map_image_params,
+                region.as_deref(),
+            )?,
+            true,
+            "underground",
+        );
+        print_unfitting_tile_cells(&h3m, true, "underground");
+    }
+
+    if let Some(obstacle_coverage) = obstacle_coverage {
+        print_info(&format!("Done, {}.", obstacle_coverage), stdout_taken);
+    }
+
+    let change = snapshot
+        .map(|snapshot| h3m.change_since(&snapshot))
+        .transpose()?;
+    // the history is written first, a map left behind it fails to apply the undo
+    if let Some(change) = change {
+        history::record_change(&config.map_path, run_parameters(config, h3m.seed()), change)?;
+    }
+    let mut output = create_output(output_map_path)?;
+    h3m.save(&mut output)?;
+    output.flush()?;
+
+    if let Some(preview_path) = &config.preview_path {
+        save_image(&h3m.render_preview()?, preview_path)?;
+    }
+    if let Some(repair_overlay_path) = &config.repair_overlay_path {
+        save_image(
+            &DynamicImage::ImageRgba8(repair_overlay),
+            repair_overlay_path,
+        )?;
+    }
+    Ok(())
+}
+
+/// Draws the map levels to images which `run` can convert back, as the `export` command does.
+pub fn export_images(
+    map_path: &str,
+    land_image_path: Option<&str>,
+    underground_image_path: Option<&str>,
+) -> H3mResult<()> {
+    if land_image_path == Some(STDIO_PATH) && underground_image_path == Some(STDIO_PATH) {
+        return Err(H3mError::Parameter(ParameterError::new(
+            "Only one image can be written to stdout.",
+        )));
+    }
+    let h3m = H3m::load(open_input(map_path)?)?;
+    if let Some(land_image_path) = land_image_path {
+        save_image(&h3m.render_level(false)?, land_image_path)?;
+    }
+    if let Some(underground_image_path) = underground_image_path {
+        save_image(&h3m.render_level(true)?, underground_image_path)?;
+    }
+    Ok(())
+}
+
+/// Replaces terrains on every level of the map, as the `replace` command does.
+/// Without an output map path the input map is updated. With `history`
+/// the run is recorded in the history file of the map, see [`undo`].
+pub fn replace_terrain(
+    map_path: &str,
+    output_map_path: Option<&str>,
+    replacements: &HashMap<Terrain, Terrain>,
+    one_tile_water: bool,
+    seed: Option<u64>,
+    history: bool,
+) -> H3mResult<()> {
+    let output_map_path = output_map_path.unwrap_or(map_path);
+    let map_to_stdout = output_map_path == STDIO_PATH;
+    if history {
+        check_history_output(map_path, output_map_path)?;
+    }
+    let mut h3m = H3m::load(open_input(map_path)?)?;
+    if let Some(seed) = seed {
+        h3m.set_seed(seed);
+    }
+    let snapshot = history.then(|| h3m.snapshot()).transpose()?;
+
+    let replacement = h3m.replace_terrain(one_tile_water, false, replacements)?;
+    let mut replaced_cells_count = replacement.replaced_cells_count;
+    print_unfitting_tile_cells(&h3m, false, "land");
+    print_removed_obstacles_warning(&replacement, "land");
+    if h3m.has_underground() {
+        let replacement = h3m.replace_terrain(one_tile_water, true, replacements)?;
+        replaced_cells_count += replacement.replaced_cells_count;
+        print_unfitting_tile_cells(&h3m, true, "underground");
+        print_removed_obstacles_warning(&replacement, "underground");
+    }
+    print_info(
+        &format!("Replaced terrain of {} cells.", replaced_cells_count),
+        map_to_stdout,
+    );
+
+    let change = snapshot
+        .map(|snapshot| h3m.change_since(&snapshot))
+        .transpose()?;
+    if let Some(change) = change {
+        let mut replacements: Vec<String> = replacements
+            .iter()
+            .map(|(from, to)| format!("--replace {:?}={:?}", from, to).to_lowercase())
+            .collect();
+        replacements.sort();
+        let mut parameters = vec!["replace".to_string()];
+        parameters.extend(replacements);
+        if one_tile_water {
+            parameters.push("--one-tile-water".to_string());
+        }
+        parameters.push(format!("--seed {}", h3m.seed()));
+        history::record_change(map_path, parameters.join(" "), change)?;
+    }
+    let mut output = create_output(output_map_path)?;
+    h3m.save(&mut output)?;
+    output.flush()?;
+    Ok(())
+}
+
+/// Writes a copy of the base map with every level filled with one terrain,
+/// as the `new` command does. The base map sets the size, the levels
+/// and everything but the terrain, it must have no objects.
+pub fn create_blank_map(
+    base_map_path: &str,
+    output_map_path: &str,
+    land_terrain: Terrain,
+    underground_terrain: Terrain,
+    seed: Option<u64>,
+) -> H3mResult<()> {
+    let mut h3m = H3m::load(open_input(base_map_path)?)?;
+    if h3m.has_objects() {
+        return Err(H3mError::Parameter(ParameterError::new(
+            "The base map has objects, they would stand on the new terrain.",
+        )));
+    }
+    if let Some(seed) = seed {
+        h3m.set_seed(seed);
+    }
+
+    let map_length = h3m.map_size() * h3m.map_size();
+    h3m.set_surfaces(
+        SurfaceOptions::default(),
+        &vec![Some(Surface::new(land_terrain)); map_length],
+    )?;
+    if h3m.has_underground() {
+        let options = SurfaceOptions {
+            underground: true,
+            ..SurfaceOptions::default()
+        };
+        h3m.set_surfaces(
+            options,
+            &vec![Some(Surface::new(underground_terrain)); map_length],
         )?;
     }
 
-    let output_map_file = File::create(&config.map_path)?;
-    h3m.save(output_map_file)?;
+    let mut output = create_output(output_map_path)?;
+    h3m.save(&mut output)?;
+    output.flush()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn map_image_from_memory_test() {
+        let transparent_color = [0x00, 0xFF, 0xFF];
+        let mut image = RgbImage::from_pixel(3, 3, Rgb([0x00, 0x31, 0x00]));
+        image.put_pixel(1, 0, Rgb(transparent_color));
+
+        let mut data = Vec::new();
+        DynamicImage::ImageRgb8(image)
+            .write_to(&mut data, ImageOutputFormat::Png)
+            .unwrap();
+
+        let map_image_params = MapImageParams {
+            transparent_color: Some(transparent_color),
+            ..MapImageParams::default()
+        };
+        let image = load_image_from_memory(&data).unwrap();
+        let map_image = MapImage::from_image(
+            &image.to_rgb8(),
+            2,
+            SurfaceOptions::default(),
+            &map_image_params,
+        );
+
+        let is_set: Vec<bool> = map_image
+            .surfaces()
+            .iter()
+            .map(|surface| surface.is_some())
+            .collect();
+        assert_eq!(is_set, [true, false, true, true]);
+    }
+
+    #[test]
+    fn cave_image_test() {
+        let mut mask = RgbImage::new(8, 8);
+        for column in 0..8 {
+            mask.put_pixel(column, 4, Rgb(TUNNEL_COLOR));
+        }
+
+        let options = SurfaceOptions {
+            obstacles: true,
+            ..SurfaceOptions::default()
+        };
+        let image = cave_image(&DynamicImage::ImageRgb8(mask), 8, 0);
+        let map_image = MapImage::fixed(&image, None, 8, options, &MapImageParams::default());
+
+        let surfaces = map_image.surfaces();
+        for (index, surface) in surfaces.iter().enumerate() {
+            let terrain = surface.unwrap().terrain;
+            assert!(matches!(terrain, Terrain::Subterranean | Terrain::Rock));
+            if index / 8 == 4 {
+                assert_eq!(terrain, Terrain::Subterranean);
+            }
+        }
+        assert_eq!(surfaces[0].unwrap().terrain, Terrain::Rock);
+    }
+
+    #[test]
+    fn keep_clear_mask_size_test() {
+        let test_map = h3m::TestMap::new(4, Terrain::Grass);
+        let mut h3m = H3m::load(&test_map.compressed()[..]).unwrap();
+        let options = SurfaceOptions {
+            obstacles: true,
+            ..SurfaceOptions::default()
+        };
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(4, 4, Rgb([0x00, 0x42, 0x00])));
+
+        for (mask_size, is_valid) in [(4, true), (3, false), (5, false)] {
+            let mask = DynamicImage::ImageRgb8(RgbImage::from_pixel(
+                mask_size,
+                mask_size,
+                Rgb(KEEP_CLEAR_COLOR),
+            ));
+            let result = h3m.set_image(
+                &image,
+                Some(&mask),
+                options,
+                &MapImageParams::default(),
+                None,
+            );
+            assert_eq!(result.is_ok(), is_valid, "{}", mask_size);
+        }
+    }
+
+    #[test]
+    fn region_cells_test() {
+        let region = Region::Rectangle {
+            column: 1,
+            row: 2,
+            width: 5,
+            height: 1,
+        };
+        #[rustfmt::skip]
+        assert_eq!(region_cells(&region, 3).unwrap(), [
+            false, false, false,
+            false, false, false,
+            false, true,  true,
+        ]);
+    }
+}
@@ -1,10 +1,98 @@
+use cli::Command;
+use img2h3m::TRANSPARENT_COLOR;
+use std::error::Error;
 use std::process;
 
 mod cli;
 
+fn run_command(command: Command) -> Result<(), Box<dyn Error>> {
+    match command {
+        Command::Convert(config) => Ok(img2h3m::run(config)?),
+        Command::Watch(config) => img2h3m::watch(config),
+        Command::New {
+            base_map_path,
+            output_map_path,
+            land_terrain,
+            underground_terrain,
+            seed,
+        } => Ok(img2h3m::create_blank_map(
+            &base_map_path,
+            &output_map_path,
+            land_terrain,
+            underground_terrain,
+            seed,
+        )?),
+        Command::Replace {
+            map_path,
+            output_map_path,
+            replacements,
+            one_tile_water,
+            seed,
+            history,
+        } => Ok(img2h3m::replace_terrain(
+            &map_path,
+            output_map_path.as_deref(),
+            &replacements,
+            one_tile_water,
+            seed,
+            history,
+        )?),
+        Command::Undo { map_path } => Ok(img2h3m::undo(&map_path)?),
+        Command::Redo { map_path } => Ok(img2h3m::redo(&map_path)?),
+        Command::Export {
+            map_path,
+            land_image_path,
+            underground_image_path,
+        } => Ok(img2h3m::export_images(
+            &map_path,
+            land_image_path.as_deref(),
+            underground_image_path.as_deref(),
+        )?),
+        Command::Info { map_path, json } => {
+            if !img2h3m::print_map_info(&map_path, json)? {
+                process::exit(1);
+            }
+            Ok(())
+        }
+        Command::Palette {
+            obstacles,
+            image_path: None,
+            ..
+        } => {
+            img2h3m::print_palette(obstacles);
+            Ok(())
+        }
+        Command::Palette {
+            obstacles,
+            image_path: Some(image_path),
+            transparent,
+        } => {
+            let transparent_color = if transparent {
+                Some(TRANSPARENT_COLOR)
+            } else {
+                None
+            };
+            if !img2h3m::validate_image_palette(&image_path, obstacles, transparent_color)? {
+                process::exit(1);
+            }
+            Ok(())
+        }
+        Command::Batch {
+            manifest_path,
+            parallel,
+        } => {
+            let batch_summary = img2h3m::run_batch(&manifest_path, parallel)?;
+            println!("{}", batch_summary);
+            if batch_summary.failed_jobs_count() > 0 {
+                process::exit(1);
+            }
+            Ok(())
+        }
+    }
+}
+
 fn main() {
-    let config = cli::get_config();
-    if let Err(e) = img2h3m::run(config) {
+    if let Err(e) = run_command(cli::get_command()) {
         eprintln!("Application error: {}", e);
         process::exit(1);
     }
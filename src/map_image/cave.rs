@@ -0,0 +1,114 @@
+use crate::common::position::{Position, SignedDeltaPos};
+use crate::common::rng::SeededRng;
+use crate::h3m::{Surface, Terrain};
+use rand::Rng;
+
+const MAX_TUNNEL_RADIUS: isize = 2;
+
+// Passes of the cave smoothing, each one rounds the walls a little more.
+const SMOOTHING_PASSES_COUNT: usize = 3;
+
+// Floor neighbours a rock cell needs to become floor and a floor cell to stay one.
+const MIN_FLOOR_NEIGHBOURS_COUNT: usize = 5;
+const MIN_KEPT_FLOOR_NEIGHBOURS_COUNT: usize = 4;
+
+// Underground surfaces of the tunnels: every tunnel cell is widened to a disc
+// of a random radius and the walls are smoothed, the tunnel cells stay floor.
+// The floor is subterranean, the rest of the cells are rock.
+pub fn cave_surfaces(size: usize, tunnel: &[bool], rng: &mut SeededRng) -> Vec<Surface> {
+    let mut floor = tunnel.to_vec();
+    for index in (0..tunnel.len()).filter(|&index| tunnel[index]) {
+        let radius = rng.gen_range(1..=MAX_TUNNEL_RADIUS);
+        for (neighbour, _) in disc_cells(size, index, radius) {
+            floor[neighbour] = true;
+        }
+    }
+
+    for _ in 0..SMOOTHING_PASSES_COUNT {
+        floor = (0..floor.len())
+            .map(|index| {
+                // the cells beyond the map are rock
+                let floor_neighbours_count = disc_cells(size, index, 1)
+                    .filter(|&(neighbour, distance)| distance > 0 && floor[neighbour])
+                    .count();
+                tunnel[index]
+                    || floor_neighbours_count >= MIN_FLOOR_NEIGHBOURS_COUNT
+                    || (floor[index] && floor_neighbours_count >= MIN_KEPT_FLOOR_NEIGHBOURS_COUNT)
+            })
+            .collect();
+    }
+
+    floor
+        .into_iter()
+        .map(|is_floor| {
+            Surface::new(if is_floor {
+                Terrain::Subterranean
+            } else {
+                Terrain::Rock
+            })
+        })
+        .collect()
+}
+
+// Cells of the map within the radius of the cell, the radius 1 gives all 8 neighbours,
+// with their squared distances.
+fn disc_cells(size: usize, index: usize, radius: isize) -> impl Iterator<Item = (usize, isize)> {
+    let position = Position::from_index(size, index);
+    let max_distance = std::cmp::max(radius * radius, 2);
+    (-radius..=radius)
+        .flat_map(move |delta_row| {
+            (-radius..=radius).map(move |delta_column| (delta_row, delta_column))
+        })
+        .filter(move |(delta_row, delta_column)| {
+            delta_row * delta_row + delta_column * delta_column <= max_distance
+        })
+        .filter_map(move |(delta_row, delta_column)| {
+            let neighbour_position = position.checked_apply(
+                size,
+                size,
+                &SignedDeltaPos::new(delta_row, delta_column),
+            )?;
+            Some((
+                neighbour_position.index(size),
+                delta_row * delta_row + delta_column * delta_column,
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::rng;
+
+    #[test]
+    fn cave_surfaces_test() {
+        let size = 16;
+        // a horizontal tunnel through the middle row
+        let tunnel: Vec<bool> = (0..size * size).map(|index| index / size == 8).collect();
+        let surfaces = cave_surfaces(size, &tunnel, &mut rng::stream_rng(0, 0));
+
+        for (index, surface) in surfaces.iter().enumerate() {
+            let (row, column) = (index / size, index % size);
+            // the cells beyond the map narrow the tunnel at the borders
+            let is_border = column == 0 || column == size - 1;
+            let expected_terrain = match row {
+                8 => Some(Terrain::Subterranean),
+                7 | 9 if !is_border => Some(Terrain::Subterranean),
+                0..=4 | 12.. => Some(Terrain::Rock),
+                _ => None,
+            };
+            if let Some(expected_terrain) = expected_terrain {
+                assert_eq!(
+                    surface.terrain, expected_terrain,
+                    "row {}, column {}",
+                    row, column
+                );
+            }
+            assert!(surface.obstacle.is_none());
+        }
+        assert_eq!(
+            surfaces,
+            cave_surfaces(size, &tunnel, &mut rng::stream_rng(0, 0))
+        );
+    }
+}
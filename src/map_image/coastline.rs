@@ -0,0 +1,368 @@
+use super::{majority_terrain, MapImage};
+use crate::common::position::{Position, SignedDeltaPos};
+use crate::h3m::Terrain;
+
+// Neighbours of the other side a coastline cell needs to move across it.
+const MIN_ACROSS_NEIGHBOURS_COUNT: usize = 5;
+
+// Cells of the other side within AREA_RADIUS a coastline cell also moves across it with,
+// out of 24: straight and diagonal coastlines have 10 of them, the corners
+// of the steps of a staircase 13 or more.
+const AREA_RADIUS: isize = 2;
+const MIN_ACROSS_AREA_CELLS_COUNT: usize = 13;
+
+impl MapImage {
+    // Moves the coastline cells surrounded mostly by the other side across it,
+    // which smooths out the bumps, notches and uneven steps, then the corners
+    // of the staircase steps and the cells left uneven by them. The corners
+    // are all found before any of them moves, so that the moves don't eat
+    // along the coastline. Every cell moves at most once and only if no problem
+    // cells appear, so the coastline moves at most one cell and the water keeps
+    // its width rules.
+    pub fn smooth_coastline(&mut self) {
+        let mut is_movable: Vec<bool> = (0..self.pixels.len())
+            .map(|index| self.is_coastline(index))
+            .collect();
+
+        self.move_surrounded_cells(&mut is_movable);
+
+        let step_corners: Vec<(usize, Terrain)> = (0..self.pixels.len())
+            .filter(|&index| is_movable[index])
+            .filter_map(|index| Some((index, self.across_step_corner_terrain(index)?)))
+            .collect();
+        for (index, terrain) in step_corners {
+            if self.try_move_across_coastline(index, terrain) {
+                is_movable[index] = false;
+            }
+        }
+
+        self.move_surrounded_cells(&mut is_movable);
+    }
+
+    fn move_surrounded_cells(&mut self, is_movable: &mut [bool]) {
+        let mut is_moved = true;
+        while is_moved {
+            is_moved = false;
+            for (index, is_movable) in is_movable.iter_mut().enumerate() {
+                if !*is_movable {
+                    continue;
+                }
+                if let Some(terrain) = self.across_coastline_terrain(index) {
+                    if self.try_move_across_coastline(index, terrain) {
+                        *is_movable = false;
+                        is_moved = true;
+                    }
+                }
+            }
+        }
+    }
+
+    fn is_water(&self, index: usize) -> Option<bool> {
+        self.terrain(index).map(|terrain| terrain == Terrain::Water)
+    }
+
+    fn is_coastline(&self, index: usize) -> bool {
+        match self.is_water(index) {
+            Some(is_water) => self
+                .neighbours(index)
+                .any(|neighbour| self.is_water(neighbour) == Some(!is_water)),
+            None => false,
+        }
+    }
+
+    // Water for land cells, the land terrain of most neighbours for water cells.
+    fn across_coastline_terrain(&self, index: usize) -> Option<Terrain> {
+        let is_water = self.is_water(index)?;
+        let across_terrains = self.across_terrains(index, is_water);
+        if across_terrains.len() < MIN_ACROSS_NEIGHBOURS_COUNT {
+            return None;
+        }
+        Self::across_terrain(is_water, across_terrains)
+    }
+
+    // Same as across_coastline_terrain for the corners of the staircase steps.
+    fn across_step_corner_terrain(&self, index: usize) -> Option<Terrain> {
+        let is_water = self.is_water(index)?;
+        if !self.is_across_area(index, is_water) {
+            return None;
+        }
+        Self::across_terrain(is_water, self.across_terrains(index, is_water))
+    }
+
+    fn across_terrains(&self, index: usize, is_water: bool) -> Vec<Terrain> {
+        self.neighbours(index)
+            .filter(|&neighbour| self.is_water(neighbour) == Some(!is_water))
+            .filter_map(|neighbour| self.terrain(neighbour))
+            .collect()
+    }
+
+    fn across_terrain(is_water: bool, across_terrains: Vec<Terrain>) -> Option<Terrain> {
+        if !is_water {
+            return Some(Terrain::Water);
+        }
+        majority_terrain(across_terrains.into_iter())
+    }
+
+    // Whether enough cells around the cell are of the other side, the area must
+    // be inside the map and have no transparent cells.
+    fn is_across_area(&self, index: usize, is_water: bool) -> bool {
+        let position = Position::from_index(self.size, index);
+        let mut across_cells_count = 0;
+        for delta_row in -AREA_RADIUS..=AREA_RADIUS {
+            for delta_column in -AREA_RADIUS..=AREA_RADIUS {
+                if (delta_row, delta_column) == (0, 0) {
+                    continue;
+                }
+                let cell_is_water = position
+                    .checked_apply(
+                        self.size,
+                        self.size,
+                        &SignedDeltaPos::new(delta_row, delta_column),
+                    )
+                    .and_then(|cell_position| self.is_water(cell_position.index(self.size)));
+                match cell_is_water {
+                    Some(cell_is_water) if cell_is_water != is_water => across_cells_count += 1,
+                    Some(_) => {}
+                    None => return false,
+                }
+            }
+        }
+        across_cells_count >= MIN_ACROSS_AREA_CELLS_COUNT
+    }
+
+    // The cell loses its obstacle.
+    fn try_move_across_coastline(&mut self, index: usize, terrain: Terrain) -> bool {
+        let checked_indexes: Vec<usize> = self.neighbours(index).chain([index]).collect();
+        let had_problems: Vec<bool> = checked_indexes
+            .iter()
+            .map(|&index| self.has_problem(index))
+            .collect();
+
+        let pixel = match &mut self.pixels[index] {
+            Some(pixel) => pixel,
+            None => return false,
+        };
+        let surface = pixel.surface;
+        pixel.surface.terrain = terrain;
+        pixel.surface.obstacle = None;
+
+        let has_new_problems = checked_indexes
+            .iter()
+            .zip(had_problems)
+            .any(|(&index, had_problem)| !had_problem && self.has_problem(index));
+        if has_new_problems {
+            if let Some(pixel) = &mut self.pixels[index] {
+                pixel.surface = surface;
+            }
+        }
+        !has_new_problems
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ProblemRules, TerrainRepair};
+    use super::*;
+    use image::Rgb;
+
+    // Smooths the square map of the rows of grass 'G' and water 'W' cells.
+    fn smooth(coast: &[&str]) -> Vec<String> {
+        let size = coast.len();
+        let grass_color = Rgb([0x00, 0x42, 0x00]);
+        let water_color = Rgb([0x08, 0x52, 0x94]);
+        let mut map_image = MapImage::new(
+            size,
+            false,
+            false,
+            &ProblemRules::default(),
+            TerrainRepair::Nearest,
+        );
+        for (row, line) in coast.iter().enumerate() {
+            for (column, terrain) in line.chars().enumerate() {
+                let color = if terrain == 'W' {
+                    water_color
+                } else {
+                    grass_color
+                };
+                map_image.set_pixel(row, column, color);
+            }
+        }
+        map_image.fix();
+        map_image.smooth_coastline();
+
+        map_image
+            .surfaces()
+            .chunks(size)
+            .map(|row| {
+                row.iter()
+                    .map(|surface| match surface.unwrap().terrain {
+                        Terrain::Water => 'W',
+                        _ => 'G',
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn smooth_coastline_test() {
+        // a grass coast on the left with a bump and a notch
+        assert_eq!(
+            smooth(&["GGWWWW", "GGGWWW", "GGWWWW", "GGWWWW", "GWWWWW", "GGWWWW"]),
+            ["GGWWWW", "GGWWWW", "GGWWWW", "GGWWWW", "GGWWWW", "GGWWWW"]
+        );
+
+        // straight and diagonal coastlines and 1-high staircases stay
+        let coast = [
+            "GGGGWWWWWW",
+            "GGGGWWWWWW",
+            "GGGGWWWWWW",
+            "GGGGWWWWWW",
+            "GGGGWWWWWW",
+            "GGGGWWWWWW",
+            "GGGGWWWWWW",
+            "GGGGWWWWWW",
+            "GGGGWWWWWW",
+            "GGGGWWWWWW",
+        ];
+        assert_eq!(smooth(&coast), coast);
+        let coast = [
+            "GGGWWWWWWW",
+            "GGGGWWWWWW",
+            "GGGGGWWWWW",
+            "GGGGGGWWWW",
+            "GGGGGGGWWW",
+            "GGGGGGGGWW",
+            "GGGGGGGGGW",
+            "GGGGGGGGGG",
+            "GGGGGGGGGG",
+            "GGGGGGGGGG",
+        ];
+        assert_eq!(smooth(&coast), coast);
+        let coast = [
+            "GGWWWWWWWW",
+            "GGGGWWWWWW",
+            "GGGGGGWWWW",
+            "GGGGGGGGWW",
+            "GGGGGGGGGG",
+            "GGGGGGGGGG",
+            "GGGGGGGGGG",
+            "GGGGGGGGGG",
+            "GGGGGGGGGG",
+            "GGGGGGGGGG",
+        ];
+        assert_eq!(smooth(&coast), coast);
+
+        // the 2-wide steps of a staircase become a diagonal
+        assert_eq!(
+            smooth(&[
+                "GGWWWWWWWW",
+                "GGWWWWWWWW",
+                "GGGGWWWWWW",
+                "GGGGWWWWWW",
+                "GGGGGGWWWW",
+                "GGGGGGWWWW",
+                "GGGGGGGGWW",
+                "GGGGGGGGWW",
+                "GGGGGGGGGG",
+                "GGGGGGGGGG",
+            ]),
+            [
+                "GGWWWWWWWW",
+                "GGGWWWWWWW",
+                "GGGGWWWWWW",
+                "GGGGGWWWWW",
+                "GGGGGGWWWW",
+                "GGGGGGGWWW",
+                "GGGGGGGGWW",
+                "GGGGGGGGGW",
+                "GGGGGGGGGG",
+                "GGGGGGGGGG",
+            ]
+        );
+
+        // the 3-wide ones too, their corners are cut on both sides
+        assert_eq!(
+            smooth(&[
+                "GWWWWWWWWW",
+                "GWWWWWWWWW",
+                "GWWWWWWWWW",
+                "GGGGWWWWWW",
+                "GGGGWWWWWW",
+                "GGGGWWWWWW",
+                "GGGGGGGWWW",
+                "GGGGGGGWWW",
+                "GGGGGGGWWW",
+                "GGGGGGGGGG",
+            ]),
+            [
+                "GWWWWWWWWW",
+                "GWWWWWWWWW",
+                "GGWWWWWWWW",
+                "GGGWWWWWWW",
+                "GGGGWWWWWW",
+                "GGGGGWWWWW",
+                "GGGGGGWWWW",
+                "GGGGGGGWWW",
+                "GGGGGGGGWW",
+                "GGGGGGGGGG",
+            ]
+        );
+
+        // a single 2-wide step becomes two 1-wide ones
+        assert_eq!(
+            smooth(&[
+                "GGWWWWWWWW",
+                "GGWWWWWWWW",
+                "GGWWWWWWWW",
+                "GGWWWWWWWW",
+                "GGWWWWWWWW",
+                "GGGGWWWWWW",
+                "GGGGWWWWWW",
+                "GGGGWWWWWW",
+                "GGGGWWWWWW",
+                "GGGGWWWWWW",
+            ]),
+            [
+                "GGWWWWWWWW",
+                "GGWWWWWWWW",
+                "GGWWWWWWWW",
+                "GGGWWWWWWW",
+                "GGGWWWWWWW",
+                "GGGWWWWWWW",
+                "GGGGWWWWWW",
+                "GGGGWWWWWW",
+                "GGGGWWWWWW",
+                "GGGGWWWWWW",
+            ]
+        );
+
+        // the corners of an island are cut without eating its sides
+        assert_eq!(
+            smooth(&[
+                "WWWWWWWWWW",
+                "WWWWWWWWWW",
+                "WWGGGGGGWW",
+                "WWGGGGGGWW",
+                "WWGGGGGGWW",
+                "WWGGGGGGWW",
+                "WWGGGGGGWW",
+                "WWGGGGGGWW",
+                "WWWWWWWWWW",
+                "WWWWWWWWWW",
+            ]),
+            [
+                "WWWWWWWWWW",
+                "WWWWWWWWWW",
+                "WWWWGGWWWW",
+                "WWWGGGGWWW",
+                "WWGGGGGGWW",
+                "WWGGGGGGWW",
+                "WWWGGGGWWW",
+                "WWWWGGWWWW",
+                "WWWWWWWWWW",
+                "WWWWWWWWWW",
+            ]
+        );
+    }
+}
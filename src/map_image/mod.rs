@@ -1,16 +1,93 @@
-use crate::common::position::Position;
-use crate::h3m::{Surface, MAX_MAP_SIZE};
-use image::Rgb;
-use palettes::Palettes;
+use crate::common::position::{Position, SignedDeltaPos};
+use crate::h3m::{Surface, Terrain, MAX_MAP_SIZE};
+pub use cave::cave_surfaces;
+use image::{Rgb, RgbImage, Rgba, RgbaImage};
+pub use palettes::Palettes;
+use serde::Deserialize;
+use std::collections::HashSet;
+use strum::{EnumCount, IntoEnumIterator};
+pub use terrain_check::ProblemRules;
 use terrain_check::TerrainCheck;
 
+mod cave;
+mod coastline;
 mod palettes;
 mod terrain_check;
 
+// Draws the surfaces of a level row by row, one pixel per cell.
+pub fn render_surfaces(size: usize, surfaces: &[Surface]) -> RgbImage {
+    let mut image = RgbImage::new(size as u32, size as u32);
+    for (index, surface) in surfaces.iter().enumerate() {
+        let position = Position::from_index(size, index);
+        image.put_pixel(
+            position.column() as u32,
+            position.row() as u32,
+            Rgb(palettes::surface_rgb_color(surface)),
+        );
+    }
+    image
+}
+
+/// How the cells of the image which no tiles can draw, like 1-wide water strips
+/// or checkerboards, are repaired. By default only water and rock cells have such
+/// problems, see [`ProblemRules`].
+/// The repair overlay draws the pixels repaired by each variant in red, yellow,
+/// green and magenta respectively.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TerrainRepair {
+    #[default]
+    Nearest, // the ground terrain of the nearest color
+    Majority, // the ground terrain of most neighbours
+    Widen,    // the feature is widened to 2 cells, what is left is repaired by majority
+    Remove,   // the whole feature takes the ground terrain of most cells around it
+}
+
+fn repair_rgba_color(terrain_repair: TerrainRepair) -> Rgba<u8> {
+    match terrain_repair {
+        TerrainRepair::Nearest => Rgba([0xFF, 0x00, 0x00, 0xFF]),
+        TerrainRepair::Majority => Rgba([0xFF, 0xFF, 0x00, 0xFF]),
+        TerrainRepair::Widen => Rgba([0x00, 0xFF, 0x00, 0xFF]),
+        TerrainRepair::Remove => Rgba([0xFF, 0x00, 0xFF, 0xFF]),
+    }
+}
+
+const NEIGHBOUR_DELTAS: [(isize, isize); 8] = [
+    (0, 1),
+    (1, 0),
+    (0, -1),
+    (-1, 0),
+    (-1, -1),
+    (-1, 1),
+    (1, -1),
+    (1, 1),
+];
+
+// Widening goes to the orthogonal neighbours only.
+const ORTHOGONAL_NEIGHBOURS_COUNT: usize = 4;
+
+// Terrain of most cells, the first terrain wins a tie.
+fn majority_terrain(terrains: impl Iterator<Item = Terrain>) -> Option<Terrain> {
+    let mut counts = [0; Terrain::COUNT];
+    for terrain in terrains {
+        counts[terrain as usize] += 1;
+    }
+    Terrain::iter()
+        .filter(|&terrain| counts[terrain as usize] > 0)
+        .max_by_key(|&terrain| {
+            (
+                counts[terrain as usize],
+                std::cmp::Reverse(terrain as usize),
+            )
+        })
+}
+
 #[derive(Clone, Copy)]
 struct MapPixel {
     original_color: Rgb<u8>,
     surface: Surface,
+    keep_clear: bool,              // no obstacles may be placed on the pixel
+    repair: Option<TerrainRepair>, // the last repair of the pixel surface
 }
 
 pub struct MapImage {
@@ -18,18 +95,30 @@ pub struct MapImage {
     pixels: Vec<Option<MapPixel>>,
     palettes: Palettes,
     terrain_check: TerrainCheck,
+    terrain_repair: TerrainRepair,
 }
 
 impl MapImage {
-    pub fn new(size: usize, one_tile_water: bool, obstacles: bool) -> MapImage {
+    pub fn new(
+        size: usize,
+        one_tile_water: bool,
+        obstacles: bool,
+        problem_rules: &ProblemRules,
+        terrain_repair: TerrainRepair,
+    ) -> MapImage {
         MapImage {
             size,
             pixels: vec![None; size * size],
             palettes: Palettes::new(obstacles),
-            terrain_check: TerrainCheck::new(size, one_tile_water),
+            terrain_check: TerrainCheck::new(size, one_tile_water, problem_rules),
+            terrain_repair,
         }
     }
 
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
     pub fn set_pixel(&mut self, row: usize, column: usize, pixel: Rgb<u8>) {
         let ground_only = false;
         let surface = self.palettes.nearest_surface(&pixel, ground_only);
@@ -38,59 +127,323 @@ impl MapImage {
         self.pixels[index] = Some(MapPixel {
             surface,
             original_color: pixel,
+            keep_clear: false,
+            repair: None,
         });
     }
 
+    pub fn set_keep_clear(&mut self, row: usize, column: usize) {
+        let index = Position::new(row, column).index(self.size);
+        if let Some(pixel) = &mut self.pixels[index] {
+            pixel.keep_clear = true;
+        }
+    }
+
+    // Every iteration but the widening one turns the problem cells into
+    // problemless ground, so the problem cells run out.
     pub fn fix(&mut self) {
-        for _ in 0..MAX_MAP_SIZE {
-            let has_problems = self.fix_iteration();
-            if !has_problems {
+        for iteration in 0..MAX_MAP_SIZE {
+            let problem_surface_indexes = self.problem_surface_indexes();
+            if problem_surface_indexes.is_empty() {
                 return;
             }
+
+            match self.terrain_repair {
+                TerrainRepair::Nearest => {
+                    for index in problem_surface_indexes {
+                        self.fix_problem_surface(index);
+                    }
+                }
+                TerrainRepair::Majority => self.repair_by_majority(&problem_surface_indexes),
+                TerrainRepair::Widen if iteration == 0 => self.widen(&problem_surface_indexes),
+                TerrainRepair::Widen => self.repair_by_majority(&problem_surface_indexes),
+                TerrainRepair::Remove => self.remove_features(&problem_surface_indexes),
+            }
         }
         panic!();
     }
 
+    // Repaired pixels in the color of their repair, the other pixels are transparent.
+    pub fn render_repairs(&self) -> RgbaImage {
+        let mut image = RgbaImage::new(self.size as u32, self.size as u32);
+        for (index, pixel) in self.pixels.iter().enumerate() {
+            if let Some(repair) = pixel.and_then(|pixel| pixel.repair) {
+                let position = Position::from_index(self.size, index);
+                image.put_pixel(
+                    position.column() as u32,
+                    position.row() as u32,
+                    repair_rgba_color(repair),
+                );
+            }
+        }
+        image
+    }
+
     pub fn surfaces(&self) -> Vec<Option<Surface>> {
-        self.pixels.iter().map(|p| p.map(|p| p.surface)).collect()
+        self.pixels
+            .iter()
+            .map(|p| {
+                p.map(|p| {
+                    if p.keep_clear {
+                        Surface {
+                            obstacle: None,
+                            ..p.surface
+                        }
+                    } else {
+                        p.surface
+                    }
+                })
+            })
+            .collect()
     }
 
     fn fix_problem_surface(&mut self, index: usize) {
-        let ground_only = true;
+        let terrain_check = &self.terrain_check;
         let pixel = &mut self.pixels[index];
         if let Some(pixel) = pixel {
+            // the problem rules leave some ground terrain to repair with
             pixel.surface = self
                 .palettes
-                .nearest_surface(&pixel.original_color, ground_only);
+                .nearest_ground_surface(&pixel.original_color, |terrain| {
+                    terrain_check.is_repair_terrain(terrain)
+                })
+                .unwrap();
+            pixel.repair = Some(TerrainRepair::Nearest);
         }
     }
 
-    fn fix_iteration(&mut self) -> bool {
-        let terrain_getter = |position: Position| {
-            let index = position.index(self.size);
-            self.pixels[index].map(|p| p.surface.terrain)
-        };
+    fn neighbours(&self, index: usize) -> impl Iterator<Item = usize> + '_ {
+        let position = Position::from_index(self.size, index);
+        NEIGHBOUR_DELTAS
+            .iter()
+            .filter_map(move |&(delta_row, delta_column)| {
+                position.checked_apply(
+                    self.size,
+                    self.size,
+                    &SignedDeltaPos::new(delta_row, delta_column),
+                )
+            })
+            .map(|neighbour_position| neighbour_position.index(self.size))
+    }
+
+    fn terrain(&self, index: usize) -> Option<Terrain> {
+        self.pixels[index].map(|pixel| pixel.surface.terrain)
+    }
 
-        let mut problem_surface_indexes = Vec::new();
+    // Repair terrain of most cells.
+    fn majority_repair_terrain(&self, indexes: impl Iterator<Item = usize>) -> Option<Terrain> {
+        majority_terrain(
+            indexes
+                .filter_map(|index| self.terrain(index))
+                .filter(|&terrain| self.terrain_check.is_repair_terrain(terrain)),
+        )
+    }
 
-        for row in 0..self.size {
-            for column in 0..self.size {
-                let is_problem_surface =
-                    self.terrain_check.has_problem(row, column, terrain_getter);
+    // Without ground around the pixel falls back to the nearest color.
+    fn set_repaired_terrain(
+        &mut self,
+        index: usize,
+        terrain: Option<Terrain>,
+        repair: TerrainRepair,
+    ) {
+        match (terrain, &mut self.pixels[index]) {
+            (Some(terrain), Some(pixel)) => {
+                pixel.surface.terrain = terrain;
+                pixel.repair = Some(repair);
+            }
+            _ => self.fix_problem_surface(index),
+        }
+    }
 
-                if is_problem_surface {
-                    let index = Position::new(row, column).index(self.size);
-                    problem_surface_indexes.push(index);
+    fn repair_by_majority(&mut self, problem_surface_indexes: &[usize]) {
+        let terrains: Vec<Option<Terrain>> = problem_surface_indexes
+            .iter()
+            .map(|&index| self.majority_repair_terrain(self.neighbours(index)))
+            .collect();
+        for (&index, terrain) in problem_surface_indexes.iter().zip(terrains) {
+            self.set_repaired_terrain(index, terrain, TerrainRepair::Majority);
+        }
+    }
+
+    // The first orthogonal neighbour of another terrain takes the problem surface.
+    fn widen(&mut self, problem_surface_indexes: &[usize]) {
+        let widenings: Vec<(usize, MapPixel)> = problem_surface_indexes
+            .iter()
+            .filter_map(|&index| {
+                let pixel = self.pixels[index]?;
+                let neighbour = self
+                    .neighbours(index)
+                    .take(ORTHOGONAL_NEIGHBOURS_COUNT)
+                    .find(|&neighbour| {
+                        self.terrain(neighbour)
+                            .is_some_and(|terrain| terrain != pixel.surface.terrain)
+                    })?;
+                Some((neighbour, pixel))
+            })
+            .collect();
+        for (neighbour, pixel) in widenings {
+            if let Some(neighbour_pixel) = &mut self.pixels[neighbour] {
+                neighbour_pixel.surface = pixel.surface;
+                neighbour_pixel.repair = Some(TerrainRepair::Widen);
+            }
+        }
+    }
+
+    // Features are the connected problem cells of one terrain.
+    fn remove_features(&mut self, problem_surface_indexes: &[usize]) {
+        let mut is_problem = vec![false; self.pixels.len()];
+        for &index in problem_surface_indexes {
+            is_problem[index] = true;
+        }
+
+        let mut removals = Vec::new();
+        for &start in problem_surface_indexes {
+            if !is_problem[start] {
+                continue;
+            }
+            is_problem[start] = false;
+            let mut feature = vec![start];
+            let mut next = 0;
+            while next < feature.len() {
+                let index = feature[next];
+                next += 1;
+                for neighbour in self.neighbours(index) {
+                    if is_problem[neighbour] && self.terrain(neighbour) == self.terrain(start) {
+                        is_problem[neighbour] = false;
+                        feature.push(neighbour);
+                    }
                 }
             }
+
+            let feature_cells: HashSet<usize> = feature.iter().copied().collect();
+            let border = feature
+                .iter()
+                .flat_map(|&index| self.neighbours(index))
+                .filter(|neighbour| !feature_cells.contains(neighbour));
+            let terrain = self.majority_repair_terrain(border);
+            removals.extend(feature.into_iter().map(|index| (index, terrain)));
+        }
+
+        for (index, terrain) in removals {
+            self.set_repaired_terrain(index, terrain, TerrainRepair::Remove);
         }
+    }
 
-        let has_problems = !problem_surface_indexes.is_empty();
+    fn has_problem(&self, index: usize) -> bool {
+        let terrain_getter = |position: Position| self.terrain(position.index(self.size));
+        let position = Position::from_index(self.size, index);
+        self.terrain_check
+            .has_problem(position.row(), position.column(), terrain_getter)
+    }
 
-        for index in problem_surface_indexes {
-            self.fix_problem_surface(index);
+    fn problem_surface_indexes(&self) -> Vec<usize> {
+        (0..self.pixels.len())
+            .filter(|&index| self.has_problem(index))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::h3m::{Obstacle, ObstacleCategory, Terrain};
+
+    #[test]
+    fn keep_clear_pixels_have_no_obstacles_test() {
+        let obstacle_color = Rgb([0x00, 0x31, 0x00]);
+        let mut map_image = MapImage::new(
+            2,
+            false,
+            true,
+            &ProblemRules::default(),
+            TerrainRepair::Nearest,
+        );
+        for index in 0..4 {
+            map_image.set_pixel(index / 2, index % 2, obstacle_color);
+        }
+        map_image.set_keep_clear(0, 1);
+
+        let obstacles: Vec<bool> = map_image
+            .surfaces()
+            .iter()
+            .map(|surface| surface.unwrap().obstacle.is_some())
+            .collect();
+        assert_eq!(obstacles, [true, false, true, true]);
+    }
+
+    #[test]
+    fn rendered_surfaces_are_read_back_test() {
+        let surfaces = [
+            Surface::new(Terrain::Grass),
+            Surface::with_obstacle(Terrain::Grass, Obstacle::new(ObstacleCategory::Any)),
+            Surface::new(Terrain::Water),
+            Surface::with_obstacle(Terrain::Snow, Obstacle::new(ObstacleCategory::Any)),
+        ];
+        let image = render_surfaces(2, &surfaces);
+
+        let mut map_image = MapImage::new(
+            2,
+            false,
+            true,
+            &ProblemRules::default(),
+            TerrainRepair::Nearest,
+        );
+        for (column, row, pixel) in image.enumerate_pixels() {
+            map_image.set_pixel(row as usize, column as usize, *pixel);
+        }
+        let read_surfaces: Vec<Surface> = map_image.surfaces().into_iter().flatten().collect();
+        assert_eq!(read_surfaces, surfaces);
+    }
+
+    #[test]
+    fn terrain_repairs_test() {
+        let grass_color = Rgb([0x00, 0x42, 0x00]);
+        let water_color = Rgb([0x08, 0x52, 0x94]);
+        // a 1-wide water strip in the grass
+        let repaired_map_image = |terrain_repair| {
+            let mut map_image =
+                MapImage::new(4, false, false, &ProblemRules::default(), terrain_repair);
+            for index in 0..16 {
+                let color = if index % 4 == 1 {
+                    water_color
+                } else {
+                    grass_color
+                };
+                map_image.set_pixel(index / 4, index % 4, color);
+            }
+            map_image.fix();
+            map_image
+        };
+        let water_columns = |map_image: &MapImage| {
+            let surfaces = map_image.surfaces();
+            (0..4)
+                .filter(|&column| surfaces[column].unwrap().terrain == Terrain::Water)
+                .collect::<Vec<_>>()
+        };
+        let repaired_columns = |map_image: &MapImage, terrain_repair| {
+            let repairs = map_image.render_repairs();
+            (0..4)
+                .filter(|&column| {
+                    *repairs.get_pixel(column, 0) == repair_rgba_color(terrain_repair)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        for terrain_repair in [TerrainRepair::Majority, TerrainRepair::Remove] {
+            let map_image = repaired_map_image(terrain_repair);
+            assert!(map_image
+                .surfaces()
+                .iter()
+                .all(|surface| surface.unwrap().terrain == Terrain::Grass));
+            assert_eq!(repaired_columns(&map_image, terrain_repair), [1]);
         }
 
-        has_problems
+        let map_image = repaired_map_image(TerrainRepair::Widen);
+        assert_eq!(water_columns(&map_image), [1, 2]);
+        assert_eq!(repaired_columns(&map_image, TerrainRepair::Widen), [2]);
+
+        let map_image = repaired_map_image(TerrainRepair::Nearest);
+        assert!(water_columns(&map_image).is_empty());
+        assert_eq!(repaired_columns(&map_image, TerrainRepair::Nearest), [1]);
     }
 }
@@ -1,5 +1,5 @@
 use crate::common::RgbColor;
-use crate::h3m::{Surface, Terrain};
+use crate::h3m::{Obstacle, ObstacleCategory, Surface, Terrain};
 use delta_e::DE2000;
 use image::Rgb;
 use strum::IntoEnumIterator;
@@ -38,13 +38,94 @@ fn obstacle_rgb_color(terrain: Terrain) -> Option<RgbColor> {
     }
 }
 
+fn optional_obstacle_rgb_color(terrain: Terrain) -> Option<RgbColor> {
+    match terrain {
+        Terrain::Dirt => Some([0x6A, 0x5E, 0x45]),
+        Terrain::Sand => Some([0xBB, 0xB4, 0x90]),
+        Terrain::Grass => Some([0x3F, 0x64, 0x3F]),
+        Terrain::Snow => Some([0xA8, 0xB4, 0xB4]),
+        Terrain::Swamp => Some([0x58, 0x83, 0x71]),
+        Terrain::Rough => Some([0x8A, 0x7D, 0x58]),
+        Terrain::Subterranean => Some([0x83, 0x45, 0x3F]),
+        Terrain::Lava => Some([0x5E, 0x5E, 0x5E]),
+        Terrain::Highlands => Some([0x58, 0x7D, 0x4B]),
+        Terrain::Wasteland => Some([0xB4, 0x71, 0x45]),
+        Terrain::Water => Some([0x3F, 0x5E, 0x90]),
+        Terrain::Rock => None,
+    }
+}
+
+pub fn surface_rgb_color(surface: &Surface) -> RgbColor {
+    let terrain_color = terrain_rgb_color(surface.terrain);
+    match surface.obstacle {
+        Some(_) => obstacle_rgb_color(surface.terrain).unwrap_or(terrain_color),
+        None => terrain_color,
+    }
+}
+
+fn obstacle_category_rgb_color(terrain: Terrain, category: ObstacleCategory) -> Option<RgbColor> {
+    match (terrain, category) {
+        (_, ObstacleCategory::Any) => obstacle_rgb_color(terrain),
+
+        (Terrain::Dirt, ObstacleCategory::Forest) => Some([0x1C, 0x4A, 0x04]),
+        (Terrain::Dirt, ObstacleCategory::Mountains) => Some([0x56, 0x41, 0x25]),
+        (Terrain::Dirt, ObstacleCategory::Lakes) => Some([0x1C, 0x41, 0x6B]),
+        (Terrain::Dirt, ObstacleCategory::Rocks) => Some([0x6A, 0x62, 0x52]),
+
+        (Terrain::Sand, ObstacleCategory::Mountains) => Some([0x8C, 0x7B, 0x56]),
+        (Terrain::Sand, ObstacleCategory::Rocks) => Some([0xA0, 0x9C, 0x83]),
+
+        (Terrain::Grass, ObstacleCategory::Forest) => Some([0x00, 0x4E, 0x00]),
+        (Terrain::Grass, ObstacleCategory::Mountains) => Some([0x39, 0x45, 0x21]),
+        (Terrain::Grass, ObstacleCategory::Lakes) => Some([0x00, 0x45, 0x67]),
+        (Terrain::Grass, ObstacleCategory::Rocks) => Some([0x4E, 0x66, 0x4E]),
+
+        (Terrain::Snow, ObstacleCategory::Forest) => Some([0x46, 0x83, 0x4E]),
+        (Terrain::Snow, ObstacleCategory::Mountains) => Some([0x7F, 0x7B, 0x6F]),
+        (Terrain::Snow, ObstacleCategory::Lakes) => Some([0x46, 0x7B, 0xB5]),
+        (Terrain::Snow, ObstacleCategory::Rocks) => Some([0x94, 0x9C, 0x9C]),
+
+        (Terrain::Swamp, ObstacleCategory::Forest) => Some([0x10, 0x62, 0x21]),
+        (Terrain::Swamp, ObstacleCategory::Mountains) => Some([0x4A, 0x5A, 0x42]),
+        (Terrain::Swamp, ObstacleCategory::Lakes) => Some([0x10, 0x5A, 0x88]),
+        (Terrain::Swamp, ObstacleCategory::Rocks) => Some([0x5E, 0x7B, 0x6F]),
+
+        (Terrain::Rough, ObstacleCategory::Forest) => Some([0x31, 0x5E, 0x10]),
+        (Terrain::Rough, ObstacleCategory::Mountains) => Some([0x6B, 0x56, 0x31]),
+        (Terrain::Rough, ObstacleCategory::Lakes) => Some([0x31, 0x56, 0x77]),
+        (Terrain::Rough, ObstacleCategory::Rocks) => Some([0x7F, 0x77, 0x5E]),
+
+        (Terrain::Subterranean, ObstacleCategory::Mountains) => Some([0x66, 0x31, 0x21]),
+        (Terrain::Subterranean, ObstacleCategory::Lakes) => Some([0x2D, 0x31, 0x67]),
+        (Terrain::Subterranean, ObstacleCategory::Rocks) => Some([0x7B, 0x52, 0x4E]),
+
+        (Terrain::Lava, ObstacleCategory::Mountains) => Some([0x4E, 0x41, 0x35]),
+        (Terrain::Lava, ObstacleCategory::Lakes) => Some([0x14, 0x41, 0x7B]),
+        (Terrain::Lava, ObstacleCategory::Rocks) => Some([0x62, 0x62, 0x62]),
+
+        (Terrain::Highlands, ObstacleCategory::Forest) => Some([0x10, 0x5E, 0x08]),
+        (Terrain::Highlands, ObstacleCategory::Mountains) => Some([0x4A, 0x56, 0x29]),
+        (Terrain::Highlands, ObstacleCategory::Lakes) => Some([0x10, 0x56, 0x6F]),
+        (Terrain::Highlands, ObstacleCategory::Rocks) => Some([0x5E, 0x77, 0x56]),
+
+        (Terrain::Wasteland, ObstacleCategory::Forest) => Some([0x4E, 0x56, 0x04]),
+        (Terrain::Wasteland, ObstacleCategory::Mountains) => Some([0x87, 0x4E, 0x25]),
+        (Terrain::Wasteland, ObstacleCategory::Lakes) => Some([0x4E, 0x4E, 0x6B]),
+        (Terrain::Wasteland, ObstacleCategory::Rocks) => Some([0x9C, 0x6F, 0x52]),
+
+        (Terrain::Water, ObstacleCategory::Rocks) => Some([0x4E, 0x62, 0x83]),
+
+        _ => None,
+    }
+}
+
 struct Color {
     surface: Surface,
     rgb_color: RgbColor,
 }
 
 impl Color {
-    fn new(terrain: Terrain, obstacle: bool, rgb_color: RgbColor) -> Color {
+    fn new(terrain: Terrain, obstacle: Option<Obstacle>, rgb_color: RgbColor) -> Color {
         Color {
             surface: Surface { terrain, obstacle },
             rgb_color,
@@ -69,25 +150,37 @@ impl Palettes {
         let mut add_terrain = |terrain: Terrain| {
             let color = terrain_rgb_color(terrain);
 
-            palettes.all.push(Color::new(terrain, false, color));
+            palettes.all.push(Color::new(terrain, None, color));
 
             if terrain.is_ground() {
-                palettes.ground.push(Color::new(terrain, false, color));
+                palettes.ground.push(Color::new(terrain, None, color));
+            }
+
+            if !obstacles {
+                return;
             }
 
-            let obstacle_color = if obstacles {
-                obstacle_rgb_color(terrain)
-            } else {
-                None
-            };
+            let obstacle_colors = ObstacleCategory::iter()
+                .filter_map(|category| {
+                    Some((
+                        Obstacle::new(category),
+                        obstacle_category_rgb_color(terrain, category)?,
+                    ))
+                })
+                .chain(
+                    optional_obstacle_rgb_color(terrain)
+                        .map(|color| (Obstacle::optional(ObstacleCategory::Any), color)),
+                );
 
-            if let Some(obstacle_color) = obstacle_color {
-                palettes.all.push(Color::new(terrain, true, obstacle_color));
+            for (obstacle, obstacle_color) in obstacle_colors {
+                palettes
+                    .all
+                    .push(Color::new(terrain, Some(obstacle), obstacle_color));
 
                 if terrain.is_ground() {
                     palettes
                         .ground
-                        .push(Color::new(terrain, true, obstacle_color));
+                        .push(Color::new(terrain, Some(obstacle), obstacle_color));
                 }
             }
         };
@@ -99,6 +192,12 @@ impl Palettes {
         palettes
     }
 
+    pub fn colors(&self) -> impl Iterator<Item = (Surface, RgbColor)> + '_ {
+        self.all
+            .iter()
+            .map(|color| (color.surface, color.rgb_color))
+    }
+
     pub fn nearest_surface(&self, pixel: &Rgb<u8>, ground_only: bool) -> Surface {
         let input_color = &pixel.0;
 
@@ -116,4 +215,55 @@ impl Palettes {
             .unwrap()
             .0
     }
+
+    // Nearest surface of the ground terrains the filter passes.
+    pub fn nearest_ground_surface<F>(&self, pixel: &Rgb<u8>, terrain_filter: F) -> Option<Surface>
+    where
+        F: Fn(Terrain) -> bool,
+    {
+        let input_color = &pixel.0;
+
+        self.ground
+            .iter()
+            .filter(|color| terrain_filter(color.surface.terrain))
+            .map(|color| {
+                (
+                    &color.surface,
+                    DE2000::from_rgb(&color.rgb_color, input_color),
+                )
+            })
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .map(|(surface, _)| *surface)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn palette_colors_map_to_own_surfaces_test() {
+        let palettes = Palettes::new(true);
+        for color in &palettes.all {
+            let surface = palettes.nearest_surface(&Rgb(color.rgb_color), false);
+            assert_eq!(surface.terrain, color.surface.terrain);
+            assert_eq!(surface.obstacle, color.surface.obstacle);
+        }
+    }
+
+    #[test]
+    fn obstacle_category_colors_test() {
+        // a category color only where some obstacle of the category fits the terrain
+        for terrain in Terrain::iter().filter(|&terrain| terrain != Terrain::Rock) {
+            for category in ObstacleCategory::iter() {
+                assert_eq!(
+                    obstacle_category_rgb_color(terrain, category).is_some(),
+                    crate::h3m::has_obstacle_templates(terrain, category),
+                    "{:?} {:?}",
+                    terrain,
+                    category
+                );
+            }
+        }
+    }
 }
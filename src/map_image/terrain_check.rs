@@ -1,10 +1,13 @@
 use crate::common::position::{Position, SignedDeltaPos};
+use crate::h3m::result::{H3mError, H3mResult, ParameterError};
 use crate::h3m::Terrain;
+use serde::Deserialize;
+use strum::IntoEnumIterator;
 
 #[derive(Clone, Copy, PartialEq)]
 enum TerrainRelation {
     Same, // Some neighborhood == test terrain
-    Diff, // Some neighborhood != test terrain
+    Diff, // Some neighborhood != test terrain, of the rule neighbours if they are set
     Any,  // any neighborhood, including None
 }
 
@@ -18,6 +21,7 @@ fn is_terrain_relation_matched(
     test_terrain: Terrain,
     neighbour: &Option<Terrain>,
     relation: TerrainRelation,
+    rule_neighbours: Option<&[Terrain]>,
 ) -> bool {
     use TerrainRelation::*;
 
@@ -30,6 +34,7 @@ fn is_terrain_relation_matched(
             relation == Same
         } else {
             relation == Diff
+                && rule_neighbours.map_or(true, |terrains| terrains.contains(neighbour))
         }
     } else {
         false
@@ -39,10 +44,11 @@ fn is_terrain_relation_matched(
 fn is_problem_pattern_matched(
     neighborhood: &Neighborhood,
     problem_pattern: &ProblemPattern,
+    rule_neighbours: Option<&[Terrain]>,
 ) -> bool {
     let test_terrain = neighborhood[TEST_TERRAIN_INDEX].unwrap();
     for (neighbour, &relation) in neighborhood.iter().zip(problem_pattern) {
-        if !is_terrain_relation_matched(test_terrain, neighbour, relation) {
+        if !is_terrain_relation_matched(test_terrain, neighbour, relation, rule_neighbours) {
             return false;
         }
     }
@@ -70,45 +76,198 @@ fn add_problem_pattern(
     }
 }
 
+const PROBLEM_PATTERN_NAMES: [&str; 3] = ["strip", "checkerboard", "corner"];
+
+// Built-in pattern with its rotations.
 #[rustfmt::skip]
-fn problem_patterns() -> Vec<ProblemPattern> {
+fn named_problem_patterns(name: &str) -> Option<Vec<ProblemPattern>> {
     use TerrainRelation::*;
 
     let mut problem_patterns = Vec::new();
 
-    add_problem_pattern([
-        Any,  Any,  Any,
-        Diff, Same, Diff,
-        Any,  Any,  Any,
-    ], 1, &mut problem_patterns);
+    match name {
+        // 1-wide strip
+        "strip" => add_problem_pattern([
+            Any,  Any,  Any,
+            Diff, Same, Diff,
+            Any,  Any,  Any,
+        ], 1, &mut problem_patterns),
+
+        // cells of other terrains touching by corners across the cell
+        "checkerboard" => add_problem_pattern([
+            Diff, Same, Any,
+            Same, Same, Same,
+            Any,  Same, Diff,
+        ], 1, &mut problem_patterns),
+
+        "corner" => add_problem_pattern([
+            Diff, Any,  Any,
+            Any,  Same, Diff,
+            Any,  Diff, Any,
+        ], 3, &mut problem_patterns),
+
+        _ => return None,
+    }
+
+    Some(problem_patterns)
+}
+
+// Pattern of 9 characters row by row with its rotations: 's' for the same terrain,
+// 'd' for another terrain and '.' for any neighbour, whitespace is ignored.
+fn parse_problem_pattern(pattern: &str) -> Option<Vec<ProblemPattern>> {
+    let relations = pattern
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| match c {
+            's' => Some(TerrainRelation::Same),
+            'd' => Some(TerrainRelation::Diff),
+            '.' => Some(TerrainRelation::Any),
+            _ => None,
+        })
+        .collect::<Option<Vec<TerrainRelation>>>()?;
+    let problem_pattern: ProblemPattern = relations.try_into().ok()?;
+    if problem_pattern[TEST_TERRAIN_INDEX] != TerrainRelation::Same {
+        return None;
+    }
+
+    let mut problem_patterns = Vec::new();
+    add_problem_pattern(problem_pattern, 3, &mut problem_patterns);
+    Some(problem_patterns)
+}
+
+#[derive(Clone)]
+struct ProblemRule {
+    terrain: Terrain,
+    neighbours: Option<Vec<Terrain>>, // any other terrain by default
+    patterns: Vec<ProblemPattern>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProblemRuleEntry {
+    terrain: Terrain,
+    neighbours: Option<Vec<Terrain>>,
+    patterns: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProblemRulesFile {
+    rules: Vec<ProblemRuleEntry>,
+}
+
+/// Which terrain neighbourhoods no tiles can draw: each rule sets the patterns
+/// a terrain can't have, optionally only next to some other terrains. The cells
+/// matching a rule are repaired with ground terrains which have no rules.
+///
+/// By default water and rock can't form 1-wide strips, checkerboards and corners
+/// with any other terrain.
+#[derive(Clone)]
+pub struct ProblemRules {
+    rules: Vec<ProblemRule>,
+}
+
+impl Default for ProblemRules {
+    fn default() -> ProblemRules {
+        let patterns: Vec<ProblemPattern> = PROBLEM_PATTERN_NAMES
+            .iter()
+            .flat_map(|name| named_problem_patterns(name).unwrap())
+            .collect();
+        let rules = [Terrain::Water, Terrain::Rock]
+            .map(|terrain| ProblemRule {
+                terrain,
+                neighbours: None,
+                patterns: patterns.clone(),
+            })
+            .to_vec();
+        ProblemRules { rules }
+    }
+}
+
+impl ProblemRules {
+    /// Parses rules like this TOML, or the same JSON:
+    ///
+    /// ```toml
+    /// [[rules]]
+    /// terrain = "sand"
+    /// neighbours = ["water"] # any other terrain by default
+    /// patterns = ["strip", "checkerboard", "corner", "d.. .sd .d."]
+    /// ```
+    ///
+    /// A pattern is one of the built-in names or 9 characters row by row:
+    /// 's' for the terrain, 'd' for another terrain and '.' for anything,
+    /// whitespace is ignored. Patterns are checked in every rotation.
+    ///
+    /// Fails with `H3mError::Parameter` for a broken file or wrong rules.
+    pub fn parse(rules: &str, is_json: bool) -> H3mResult<ProblemRules> {
+        let rules_error = |msg: String| {
+            H3mError::Parameter(ParameterError::new(format!("Problem rules: {}.", msg)))
+        };
+        let rules_file: ProblemRulesFile = if is_json {
+            serde_json::from_str(rules).map_err(|e| rules_error(e.to_string()))?
+        } else {
+            toml::from_str(rules).map_err(|e| rules_error(e.to_string()))?
+        };
 
-    add_problem_pattern([
-        Diff, Same, Any,
-        Same, Same, Same,
-        Any,  Same, Diff,
-    ], 1, &mut problem_patterns);
+        let mut problem_rules = Vec::new();
+        for entry in rules_file.rules {
+            let mut patterns = Vec::new();
+            for pattern in &entry.patterns {
+                let parsed_patterns = named_problem_patterns(pattern)
+                    .or_else(|| parse_problem_pattern(pattern))
+                    .ok_or_else(|| {
+                        rules_error(format!(
+                            "wrong problem pattern '{}', it must be one of {} or 9 characters \
+                             of 's', 'd' and '.' with 's' in the middle",
+                            pattern,
+                            PROBLEM_PATTERN_NAMES.join(", ")
+                        ))
+                    })?;
+                patterns.extend(parsed_patterns);
+            }
+            problem_rules.push(ProblemRule {
+                terrain: entry.terrain,
+                neighbours: entry.neighbours,
+                patterns,
+            });
+        }
 
-    add_problem_pattern([
-        Diff, Any,  Any,
-        Any,  Same, Diff,
-        Any,  Diff, Any,
-    ], 3, &mut problem_patterns);
+        let problem_rules = ProblemRules {
+            rules: problem_rules,
+        };
+        if !Terrain::iter().any(|terrain| terrain.is_ground() && problem_rules.problemless(terrain))
+        {
+            return Err(rules_error(
+                "the rules must leave a ground terrain without rules \
+                 to repair the problem cells with"
+                    .to_string(),
+            ));
+        }
+        Ok(problem_rules)
+    }
 
-    problem_patterns
+    fn problemless(&self, terrain: Terrain) -> bool {
+        self.rules.iter().all(|rule| rule.terrain != terrain)
+    }
 }
 
 pub struct TerrainCheck {
     size: usize,
-    one_tile_water: bool,
-    problem_patterns: Vec<ProblemPattern>,
+    problem_rules: ProblemRules,
 }
 
 impl TerrainCheck {
-    pub fn new(size: usize, one_tile_water: bool) -> TerrainCheck {
+    // With one-tile water the water has no problems.
+    pub fn new(size: usize, one_tile_water: bool, problem_rules: &ProblemRules) -> TerrainCheck {
+        let mut problem_rules = problem_rules.clone();
+        if one_tile_water {
+            problem_rules
+                .rules
+                .retain(|rule| rule.terrain != Terrain::Water);
+        }
         TerrainCheck {
             size,
-            one_tile_water,
-            problem_patterns: problem_patterns(),
+            problem_rules,
         }
     }
 
@@ -144,17 +303,81 @@ impl TerrainCheck {
     }
 
     fn problemless_terrain(&self, terrain: Terrain) -> bool {
-        assert!(!Terrain::Water.is_ground());
-        if terrain == Terrain::Water {
-            self.one_tile_water
-        } else {
-            terrain.is_ground()
-        }
+        self.problem_rules.problemless(terrain)
+    }
+
+    // Terrain the problem cells may be repaired with.
+    pub fn is_repair_terrain(&self, terrain: Terrain) -> bool {
+        terrain.is_ground() && self.problemless_terrain(terrain)
     }
 
     fn has_neighborhood_problem(&self, neighborhood: &Neighborhood) -> bool {
-        self.problem_patterns
+        let test_terrain = neighborhood[TEST_TERRAIN_INDEX].unwrap();
+        self.problem_rules
+            .rules
             .iter()
-            .any(|pattern| is_problem_pattern_matched(neighborhood, pattern))
+            .filter(|rule| rule.terrain == test_terrain)
+            .any(|rule| {
+                rule.patterns.iter().any(|pattern| {
+                    is_problem_pattern_matched(neighborhood, pattern, rule.neighbours.as_deref())
+                })
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn problem_rules_test() {
+        use Terrain::*;
+
+        let rules = r#"
+            [[rules]]
+            terrain = "sand"
+            neighbours = ["water"]
+            patterns = ["strip", "ddd .s. ..."]
+        "#;
+        let problem_rules = ProblemRules::parse(rules, false).unwrap();
+        let has_problem = |problem_rules: &ProblemRules, terrains: [Terrain; 9]| {
+            let terrain_check = TerrainCheck::new(3, false, problem_rules);
+            terrain_check.has_problem(1, 1, |position: Position| Some(terrains[position.index(3)]))
+        };
+
+        let sand_strip = [Grass, Sand, Grass, Water, Sand, Water, Grass, Sand, Grass];
+        assert!(has_problem(&problem_rules, sand_strip));
+        assert!(!has_problem(&ProblemRules::default(), sand_strip));
+        let sand_strip = [Grass, Sand, Grass, Water, Sand, Grass, Grass, Sand, Grass];
+        assert!(!has_problem(&problem_rules, sand_strip));
+        let sand_bay = [Grass, Sand, Water, Grass, Sand, Grass, Water, Water, Water];
+        assert!(has_problem(&problem_rules, sand_bay));
+
+        let water_strip = [
+            Grass, Water, Grass, Grass, Water, Grass, Grass, Water, Grass,
+        ];
+        assert!(!has_problem(&problem_rules, water_strip));
+        assert!(has_problem(&ProblemRules::default(), water_strip));
+        assert!(
+            !TerrainCheck::new(3, true, &ProblemRules::default()).has_problem(
+                1,
+                1,
+                |position: Position| Some(water_strip[position.index(3)])
+            )
+        );
+
+        let json_rules = r#"{"rules": [{"terrain": "water", "patterns": ["sds ..."]}]}"#;
+        assert!(ProblemRules::parse(json_rules, true).is_err());
+        let ground_rules: String = Terrain::iter()
+            .filter(|terrain| terrain.is_ground())
+            .map(|terrain| {
+                format!(
+                    "[[rules]]\nterrain = \"{:?}\"\npatterns = [\"strip\"]\n",
+                    terrain
+                )
+                .to_lowercase()
+            })
+            .collect();
+        assert!(ProblemRules::parse(&ground_rules, false).is_err());
     }
 }
@@ -0,0 +1,126 @@
+use crate::common::RgbColor;
+use crate::h3m::Surface;
+use crate::load_image;
+use crate::map_image::Palettes;
+use image::{DynamicImage, Rgb};
+use std::collections::HashMap;
+use std::error::Error;
+
+/// Image color which is not in the palette.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnmatchedColor {
+    pub color: RgbColor,
+    pub pixels_count: usize,
+    pub nearest_surface: Surface, // the surface the pixels turn into
+}
+
+/// Surfaces the images can have and their colors, with obstacle colors if `obstacles`.
+pub fn palette(obstacles: bool) -> Vec<(Surface, RgbColor)> {
+    Palettes::new(obstacles).colors().collect()
+}
+
+/// Colors of the image which are not exact palette colors, most frequent first.
+/// Pixels of the transparent color are skipped.
+pub fn unmatched_colors(
+    image: &DynamicImage,
+    obstacles: bool,
+    transparent_color: Option<RgbColor>,
+) -> Vec<UnmatchedColor> {
+    let palettes = Palettes::new(obstacles);
+    let palette_colors: Vec<RgbColor> = palettes.colors().map(|(_, color)| color).collect();
+
+    let mut pixels_counts: HashMap<RgbColor, usize> = HashMap::new();
+    for pixel in image.to_rgb8().pixels() {
+        if !palette_colors.contains(&pixel.0) && Some(pixel.0) != transparent_color {
+            *pixels_counts.entry(pixel.0).or_default() += 1;
+        }
+    }
+
+    let ground_only = false;
+    let mut unmatched_colors: Vec<UnmatchedColor> = pixels_counts
+        .into_iter()
+        .map(|(color, pixels_count)| UnmatchedColor {
+            color,
+            pixels_count,
+            nearest_surface: palettes.nearest_surface(&Rgb(color), ground_only),
+        })
+        .collect();
+    unmatched_colors.sort_by_key(|c| (std::cmp::Reverse(c.pixels_count), c.color));
+    unmatched_colors
+}
+
+// E.g. "grass", "grass with forest obstacles" or "grass with optional obstacles".
+fn surface_name(surface: &Surface) -> String {
+    let terrain = format!("{:?}", surface.terrain).to_lowercase();
+    match surface.obstacle {
+        None => terrain,
+        Some(obstacle) if obstacle.optional => format!("{} with optional obstacles", terrain),
+        Some(obstacle) => format!(
+            "{} with {} obstacles",
+            terrain,
+            format!("{:?}", obstacle.category).to_lowercase()
+        ),
+    }
+}
+
+fn hex_color(color: RgbColor) -> String {
+    format!("#{:02X}{:02X}{:02X}", color[0], color[1], color[2])
+}
+
+/// Prints the palette colors and their surfaces, as the `palette` command does.
+pub fn print_palette(obstacles: bool) {
+    for (surface, color) in palette(obstacles) {
+        println!("{} {}", hex_color(color), surface_name(&surface));
+    }
+}
+
+/// Prints the image colors which are not in the palette, as `palette --validate` does.
+/// Returns whether all the image colors are in the palette.
+pub fn validate_image_palette(
+    image_path: &str,
+    obstacles: bool,
+    transparent_color: Option<RgbColor>,
+) -> Result<bool, Box<dyn Error>> {
+    let image = load_image(image_path)?;
+    let unmatched_colors = unmatched_colors(&image, obstacles, transparent_color);
+    for unmatched_color in &unmatched_colors {
+        println!(
+            "{}: {} pixels, read as {}",
+            hex_color(unmatched_color.color),
+            unmatched_color.pixels_count,
+            surface_name(&unmatched_color.nearest_surface)
+        );
+    }
+    Ok(unmatched_colors.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::h3m::Terrain;
+    use image::RgbImage;
+
+    #[test]
+    fn unmatched_colors_test() {
+        let grass_color = [0x00, 0x42, 0x00];
+        let near_grass_color = [0x01, 0x43, 0x01];
+        let transparent_color = [0x00, 0xFF, 0xFF];
+        let mut image = RgbImage::from_pixel(3, 1, Rgb(grass_color));
+        image.put_pixel(1, 0, Rgb(near_grass_color));
+        image.put_pixel(2, 0, Rgb(transparent_color));
+
+        let unmatched_colors = unmatched_colors(
+            &DynamicImage::ImageRgb8(image),
+            false,
+            Some(transparent_color),
+        );
+        assert_eq!(
+            unmatched_colors,
+            [UnmatchedColor {
+                color: near_grass_color,
+                pixels_count: 1,
+                nearest_surface: Surface::new(Terrain::Grass),
+            }]
+        );
+    }
+}
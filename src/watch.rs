@@ -0,0 +1,103 @@
+use crate::{convert, Config, Region, STDIO_PATH};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Read;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+fn watched_paths(config: &Config) -> Vec<&str> {
+    let region_mask_path = match &config.region {
+        Some(Region::Mask(mask_path)) => Some(mask_path),
+        _ => None,
+    };
+    [
+        config.land_image_path.as_ref(),
+        config.underground_image_path.as_ref(),
+        config.cave_mask_path.as_ref(),
+        config.land_keep_clear_mask_path.as_ref(),
+        config.underground_keep_clear_mask_path.as_ref(),
+        region_mask_path,
+        config.problem_rules_path.as_ref(),
+    ]
+    .into_iter()
+    .flatten()
+    .map(String::as_str)
+    .collect()
+}
+
+// None for files which can't be read right now, e.g. while an editor replaces them.
+fn modification_times(paths: &[&str]) -> Vec<Option<SystemTime>> {
+    paths
+        .iter()
+        .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// Converts the map as [`crate::run`] does and then again each time one of
+/// the images or masks of the config changes, until the process is stopped.
+///
+/// The input map is read once, so every conversion starts from the original map
+/// even when the result overwrites it. Without a seed in the config one is picked
+/// for all the conversions, so a small image change keeps the rest of the map.
+/// Conversion errors are printed and the watching goes on. Inputs can't be read
+/// from stdin and the outputs can't be written to stdout.
+pub fn watch(mut config: Config) -> Result<(), Box<dyn Error>> {
+    let seed = *config.seed.get_or_insert_with(rand::random);
+    let paths = watched_paths(&config);
+    if config.map_path == STDIO_PATH || paths.contains(&STDIO_PATH) {
+        return Err("Watch mode can't read inputs from stdin.".into());
+    }
+    let outputs = [
+        config.output_map_path.as_ref(),
+        config.preview_path.as_ref(),
+        config.repair_overlay_path.as_ref(),
+    ];
+    if outputs.into_iter().flatten().any(|path| path == STDIO_PATH) {
+        return Err("Watch mode can't write outputs to stdout.".into());
+    }
+    println!("Watching with seed {}.", seed);
+
+    let mut map_data = Vec::new();
+    File::open(&config.map_path)?.read_to_end(&mut map_data)?;
+
+    let mut last_modification_times = None;
+    loop {
+        let current_modification_times = modification_times(&paths);
+        let is_complete = current_modification_times.iter().all(Option::is_some);
+
+        if is_complete && last_modification_times.as_ref() != Some(&current_modification_times) {
+            last_modification_times = Some(current_modification_times);
+            match convert(&map_data[..], &config) {
+                Ok(()) => println!("Map updated, watching for changes..."),
+                Err(e) => eprintln!("Conversion error: {}", e),
+            }
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modification_time_change_test() {
+        let path = std::env::temp_dir().join("img2h3m_modification_time_change_test.png");
+        let path = path.to_str().unwrap();
+        fs::write(path, [0u8]).unwrap();
+        let modification_times_before = modification_times(&[path]);
+
+        let file = File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        let modification_times_after = modification_times(&[path]);
+        fs::remove_file(path).unwrap();
+
+        assert!(modification_times_before[0].is_some());
+        assert_ne!(modification_times_before, modification_times_after);
+        assert_eq!(modification_times(&[path]), [None]);
+    }
+}